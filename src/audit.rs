@@ -0,0 +1,446 @@
+/// Audits a training dataset before it's used: what it contains, which files are
+/// unreadable, and which images are duplicated within or across classes.
+use super::cache::Fnv1a;
+use super::preprocess::{
+    decode_samples, find_files, guess_format, samples_as_matrix, PreprocessOptions,
+};
+use super::Matrix;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which findings make an audit fail.
+#[derive(Copy, Clone, Debug)]
+pub struct AuditOptions {
+    /// Images whose perceptual hashes differ in at most this many bits are near
+    /// duplicates.
+    pub near_duplicate_distance: u32,
+    pub fail_on_corrupt: bool,
+    /// Fail on exact or near duplicates within a class.
+    pub fail_on_duplicates: bool,
+    /// Fail on faces that also appear (exactly or nearly) among the backgrounds.
+    pub fail_on_cross_class: bool,
+    /// Fail on images that aren't window-sized.
+    pub fail_on_wrong_size: bool,
+    /// Fail if either class has fewer images than this.
+    pub min_per_class: usize,
+}
+
+impl Default for AuditOptions {
+    fn default() -> AuditOptions {
+        AuditOptions {
+            near_duplicate_distance: 4,
+            fail_on_corrupt: true,
+            fail_on_duplicates: false,
+            fail_on_cross_class: true,
+            fail_on_wrong_size: false,
+            min_per_class: 1,
+        }
+    }
+}
+
+/// What was found in the directory of one class.
+#[derive(Debug, Default, Serialize)]
+pub struct ClassStats {
+    pub dir: String,
+    /// Files found, including those that aren't images.
+    pub files: usize,
+    /// Images that decoded.
+    pub images: usize,
+    /// Decoded images by "<width>x<height>".
+    pub sizes: BTreeMap<String, usize>,
+    /// Decoded images by color type and bit depth.
+    pub color_types: BTreeMap<String, usize>,
+    /// Images that aren't window-sized.
+    pub wrong_size: Vec<PathBuf>,
+    /// Files that aren't in a supported image format.
+    pub skipped: Vec<PathBuf>,
+    /// Files that looked like images but couldn't be read or decoded.
+    pub corrupt: Vec<(PathBuf, String)>,
+    /// Groups of byte-for-byte identical files.
+    pub exact_duplicates: Vec<Vec<PathBuf>>,
+    /// Pairs of distinct files that look alike, with the distance between their
+    /// perceptual hashes.
+    pub near_duplicates: Vec<(PathBuf, PathBuf, u32)>,
+}
+
+/// Faces that also appear among the backgrounds: (face, background, distance).
+#[derive(Debug, Default, Serialize)]
+pub struct AuditReport {
+    pub faces: ClassStats,
+    pub backgrounds: ClassStats,
+    pub cross_class: Vec<(PathBuf, PathBuf, u32)>,
+}
+
+/// A decoded image's identity: the hash of its file and its perceptual hash.
+struct Fingerprint {
+    path: PathBuf,
+    file_hash: u64,
+    dhash: u64,
+}
+
+/// The difference hash of an image: shrink it to 9x8 by averaging and record, for
+/// each row, whether each cell is brighter than its right neighbour. Resizing,
+/// recompression and small changes of brightness leave it (nearly) unchanged.
+fn dhash(img: &Matrix) -> u64 {
+    let (rows, cols) = img.dim();
+    let cell_mean = |r: usize, c: usize| {
+        let (y0, x0) = (r * rows / 8, c * cols / 9);
+        let (y1, x1) = (
+            ((r + 1) * rows / 8).max(y0 + 1),
+            ((c + 1) * cols / 9).max(x0 + 1),
+        );
+        let cell = img.slice(s![y0..y1.min(rows), x0..x1.min(cols)]);
+        cell.sum() as f64 / cell.len() as f64
+    };
+
+    let mut hash = 0;
+    for r in 0..8 {
+        for c in 0..8 {
+            hash <<= 1;
+            if cell_mean(r, c) > cell_mean(r, c + 1) {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+/// Pairs of fingerprints whose perceptual hashes are within `max_distance` bits,
+/// leaving out identical files (reported as exact duplicates).
+fn near_pairs(
+    a: &[Fingerprint],
+    b: &[Fingerprint],
+    same_set: bool,
+    max_distance: u32,
+) -> Vec<(PathBuf, PathBuf, u32)> {
+    let mut pairs = Vec::new();
+    for (i, x) in a.iter().enumerate() {
+        let others = if same_set { &b[i + 1..] } else { b };
+        for y in others {
+            let distance = (x.dhash ^ y.dhash).count_ones();
+            let identical = x.file_hash == y.file_hash;
+            if distance <= max_distance && !(same_set && identical) {
+                pairs.push((x.path.clone(), y.path.clone(), distance));
+            }
+        }
+    }
+
+    pairs
+}
+
+fn audit_dir(
+    dir: &str,
+    preprocess: &PreprocessOptions,
+    options: &AuditOptions,
+) -> (ClassStats, Vec<Fingerprint>) {
+    let mut paths = Vec::new();
+    find_files(Path::new(dir), &mut paths);
+    paths.sort();
+
+    let mut stats = ClassStats {
+        dir: dir.to_string(),
+        files: paths.len(),
+        ..ClassStats::default()
+    };
+    let mut fingerprints = Vec::new();
+    for path in paths {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                stats.corrupt.push((path, err.to_string()));
+                continue;
+            }
+        };
+        if guess_format(&bytes).is_err() {
+            stats.skipped.push(path);
+            continue;
+        }
+        let samples = match decode_samples(&bytes) {
+            Ok(samples) => samples,
+            Err(err) => {
+                stats.corrupt.push((path, err.to_string()));
+                continue;
+            }
+        };
+
+        stats.images += 1;
+        *stats
+            .sizes
+            .entry(format!("{}x{}", samples.w, samples.h))
+            .or_insert(0) += 1;
+        *stats
+            .color_types
+            .entry(format!("{:?}", samples.color))
+            .or_insert(0) += 1;
+        let side = preprocess.window_size as u32;
+        if (samples.w, samples.h) != (side, side) {
+            stats.wrong_size.push(path.clone());
+        }
+
+        let mut file_hash = Fnv1a::new();
+        file_hash.write(&bytes);
+        fingerprints.push(Fingerprint {
+            path,
+            file_hash: file_hash.0,
+            dhash: dhash(&samples_as_matrix(&samples, preprocess.luma)),
+        });
+    }
+
+    let mut by_hash: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for fingerprint in &fingerprints {
+        by_hash
+            .entry(fingerprint.file_hash)
+            .or_default()
+            .push(fingerprint.path.clone());
+    }
+    stats.exact_duplicates = by_hash
+        .values()
+        .filter(|group| group.len() > 1)
+        .cloned()
+        .collect();
+    stats.exact_duplicates.sort();
+    stats.near_duplicates = near_pairs(
+        &fingerprints,
+        &fingerprints,
+        true,
+        options.near_duplicate_distance,
+    );
+
+    (stats, fingerprints)
+}
+
+/// Audit a faces and a background directory, loading them as the `preprocess`
+/// loaders would.
+pub fn audit_dataset(
+    faces_dir: &str,
+    background_dir: &str,
+    preprocess: &PreprocessOptions,
+    options: &AuditOptions,
+) -> AuditReport {
+    let (faces, face_prints) = audit_dir(faces_dir, preprocess, options);
+    let (backgrounds, background_prints) = audit_dir(background_dir, preprocess, options);
+    let cross_class = near_pairs(
+        &face_prints,
+        &background_prints,
+        false,
+        options.near_duplicate_distance,
+    );
+
+    AuditReport {
+        faces,
+        backgrounds,
+        cross_class,
+    }
+}
+
+impl AuditReport {
+    /// The reasons this dataset fails the audit, empty if it passes.
+    pub fn failures(&self, options: &AuditOptions) -> Vec<String> {
+        let mut failures = Vec::new();
+        for (name, stats) in &[("faces", &self.faces), ("backgrounds", &self.backgrounds)] {
+            if stats.images < options.min_per_class {
+                failures.push(format!(
+                    "{} has {} images, fewer than {}",
+                    name, stats.images, options.min_per_class
+                ));
+            }
+            if options.fail_on_corrupt && !stats.corrupt.is_empty() {
+                failures.push(format!(
+                    "{} has {} corrupt files",
+                    name,
+                    stats.corrupt.len()
+                ));
+            }
+            let duplicates = stats.exact_duplicates.len() + stats.near_duplicates.len();
+            if options.fail_on_duplicates && duplicates > 0 {
+                failures.push(format!("{} has {} duplicates", name, duplicates));
+            }
+            if options.fail_on_wrong_size && !stats.wrong_size.is_empty() {
+                failures.push(format!(
+                    "{} has {} images that aren't window-sized",
+                    name,
+                    stats.wrong_size.len()
+                ));
+            }
+        }
+        if options.fail_on_cross_class && !self.cross_class.is_empty() {
+            failures.push(format!(
+                "{} faces also appear among the backgrounds",
+                self.cross_class.len()
+            ));
+        }
+
+        failures
+    }
+}
+
+impl fmt::Display for ClassStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} images in {} files",
+            self.dir, self.images, self.files
+        )?;
+        for (size, count) in &self.sizes {
+            writeln!(f, "  {:>6} x {}", count, size)?;
+        }
+        for (color, count) in &self.color_types {
+            writeln!(f, "  {:>6} x {}", count, color)?;
+        }
+        writeln!(f, "  {} not window-sized", self.wrong_size.len())?;
+        for path in &self.skipped {
+            writeln!(f, "  skipped {}: not a supported image", path.display())?;
+        }
+        for (path, err) in &self.corrupt {
+            writeln!(f, "  corrupt {}: {}", path.display(), err)?;
+        }
+        for group in &self.exact_duplicates {
+            let paths: Vec<String> = group.iter().map(|p| p.display().to_string()).collect();
+            writeln!(f, "  identical: {}", paths.join(", "))?;
+        }
+        for (a, b, distance) in &self.near_duplicates {
+            writeln!(
+                f,
+                "  similar ({} bits): {}, {}",
+                distance,
+                a.display(),
+                b.display()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.faces, self.backgrounds)?;
+        writeln!(
+            f,
+            "{} faces also among the backgrounds",
+            self.cross_class.len()
+        )?;
+        for (face, background, distance) in &self.cross_class {
+            writeln!(
+                f,
+                "  ({} bits): {}, {}",
+                distance,
+                face.display(),
+                background.display()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Audit a dataset, print the report, write it as JSON to `json_path` and return
+/// the reasons it fails.
+pub fn run_audit(
+    faces_dir: &str,
+    background_dir: &str,
+    json_path: &str,
+    options: &AuditOptions,
+) -> Vec<String> {
+    let report = audit_dataset(
+        faces_dir,
+        background_dir,
+        &PreprocessOptions::default(),
+        options,
+    );
+    print!("{}", report);
+    fs::write(
+        json_path,
+        serde_json::to_string_pretty(&report).expect("Failed to serialize the audit"),
+    )
+    .expect("Failed to write the audit");
+
+    report.failures(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    fn pattern(seed: u32) -> GrayImage {
+        GrayImage::from_fn(16, 16, |x, y| {
+            Luma([((x * 13 + y * 7 + seed * 31) * (seed + 3) % 251) as u8])
+        })
+    }
+
+    #[test]
+    fn perceptual_hash_ignores_brightness() {
+        let img = Matrix::from_shape_fn((18, 16), |(y, x)| ((x * 37 + y * 11) % 200) as i64);
+        assert!(dhash(&img) == dhash(&(img.clone() + 20)));
+        assert!(dhash(&img) != dhash(&img.t().to_owned()));
+        // Tiny images still hash
+        dhash(&Matrix::zeros((2, 2)));
+    }
+
+    #[test]
+    fn audit_finds_duplicates_and_corrupt_files() {
+        let dir = test_dir("audit");
+        let (faces, backgrounds) = (dir.join("faces"), dir.join("background"));
+        fs::create_dir_all(&faces).unwrap();
+        fs::create_dir_all(&backgrounds).unwrap();
+
+        pattern(1).save(faces.join("a.png")).unwrap();
+        fs::copy(faces.join("a.png"), faces.join("a_copy.png")).unwrap();
+        pattern(2).save(faces.join("b.png")).unwrap();
+        RgbImage::from_pixel(8, 8, Rgb([1, 2, 3]))
+            .save(faces.join("c.png"))
+            .unwrap();
+        fs::write(faces.join("notes.txt"), b"not an image").unwrap();
+        let png = fs::read(faces.join("b.png")).unwrap();
+        fs::write(faces.join("broken.png"), &png[..30]).unwrap();
+        // The same face brightened is a near duplicate across the classes
+        let brighter = GrayImage::from_fn(16, 16, |x, y| {
+            Luma([pattern(2).get_pixel(x, y).data[0].saturating_add(3)])
+        });
+        brighter.save(backgrounds.join("b_bright.png")).unwrap();
+
+        let preprocess = PreprocessOptions {
+            window_size: 16,
+            ..PreprocessOptions::default()
+        };
+        let options = AuditOptions::default();
+        let report = audit_dataset(
+            faces.to_str().unwrap(),
+            backgrounds.to_str().unwrap(),
+            &preprocess,
+            &options,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let stats = &report.faces;
+        assert!(stats.files == 6 && stats.images == 4);
+        assert!(stats.sizes["16x16"] == 3 && stats.sizes["8x8"] == 1);
+        assert!(stats.color_types["Gray(8)"] == 3 && stats.color_types["RGB(8)"] == 1);
+        assert!(stats.wrong_size == vec![faces.join("c.png")]);
+        assert!(stats.skipped == vec![faces.join("notes.txt")]);
+        assert!(stats.corrupt.len() == 1);
+        assert!(
+            stats.exact_duplicates == vec![vec![faces.join("a.png"), faces.join("a_copy.png")]]
+        );
+        assert!(stats.near_duplicates.is_empty());
+        assert!(report.cross_class.len() == 1);
+        assert!(report.cross_class[0].0 == faces.join("b.png"));
+
+        let failures = report.failures(&options);
+        assert!(failures.len() == 2);
+        assert!(report
+            .failures(&AuditOptions {
+                fail_on_corrupt: false,
+                fail_on_cross_class: false,
+                ..options
+            })
+            .is_empty());
+        assert!(serde_json::to_string(&report).is_ok());
+    }
+}
@@ -0,0 +1,201 @@
+/// Random geometric and photometric augmentation of training images.
+use super::Matrix;
+use rand::distributions::{Distribution, Normal, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Each augmented copy draws every perturbation uniformly from within the given
+/// bounds (a bound of zero disables it).
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct AugmentOptions {
+    /// Augmented copies generated per positive, in addition to the original.
+    pub copies_per_positive: usize,
+    /// Seeds the random number generator, so augmented sets are reproducible.
+    pub seed: u64,
+    /// Mirror half of the copies horizontally.
+    pub mirror: bool,
+    /// The largest in-plane rotation, in degrees either way.
+    pub max_rotation_degrees: f64,
+    /// The largest change in scale, as a fraction (0.1 allows 0.9x to 1.1x).
+    pub max_scale: f64,
+    /// The largest shift along each axis, as a fraction of the image size.
+    pub max_translation: f64,
+    /// The largest brightness offset, in gray levels.
+    pub max_brightness: f64,
+    /// The largest change in contrast around the mean, as a fraction.
+    pub max_contrast: f64,
+    /// The largest change in gamma, as a fraction (0.2 allows 1/1.2 to 1.2).
+    pub max_gamma: f64,
+    /// The standard deviation of additive Gaussian noise, in gray levels.
+    pub noise_sigma: f64,
+}
+
+impl Default for AugmentOptions {
+    fn default() -> AugmentOptions {
+        AugmentOptions {
+            copies_per_positive: 1,
+            seed: 0,
+            mirror: true,
+            max_rotation_degrees: 10.,
+            max_scale: 0.1,
+            max_translation: 0.05,
+            max_brightness: 20.,
+            max_contrast: 0.2,
+            max_gamma: 0.2,
+            noise_sigma: 2.,
+        }
+    }
+}
+
+/// Draw uniformly from [-bound, bound].
+fn symmetric<R: Rng>(rng: &mut R, bound: f64) -> f64 {
+    if bound == 0. {
+        0.
+    } else {
+        Uniform::new_inclusive(-bound, bound).sample(rng)
+    }
+}
+
+/// Sample an image at a real-valued position with bilinear interpolation,
+/// replicating the edge pixels outside the image.
+fn sample_bilinear(img: &Matrix, x: f64, y: f64) -> f64 {
+    let (rows, cols) = img.dim();
+    let x = x.max(0.).min((cols - 1) as f64);
+    let y = y.max(0.).min((rows - 1) as f64);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(cols - 1), (y0 + 1).min(rows - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+    let top = img[[y0, x0]] as f64 * (1. - fx) + img[[y0, x1]] as f64 * fx;
+    let bottom = img[[y1, x0]] as f64 * (1. - fx) + img[[y1, x1]] as f64 * fx;
+    top * (1. - fy) + bottom * fy
+}
+
+/// Mirror, rotate, scale and translate an image about its centre, keeping its size.
+fn transform(img: &Matrix, mirror: bool, degrees: f64, scale: f64, shift: (f64, f64)) -> Matrix {
+    let (rows, cols) = img.dim();
+    let (cx, cy) = ((cols as f64 - 1.) / 2., (rows as f64 - 1.) / 2.);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+
+    // Map each output pixel back to the input it came from
+    Matrix::from_shape_fn((rows, cols), |(y, x)| {
+        let dx = (x as f64 - cx - shift.0) / scale;
+        let dy = (y as f64 - cy - shift.1) / scale;
+        let src_x = cos * dx + sin * dy;
+        let src_y = -sin * dx + cos * dy;
+        let src_x = if mirror { -src_x } else { src_x };
+
+        sample_bilinear(img, cx + src_x, cy + src_y).round() as i64
+    })
+}
+
+/// Apply a random augmentation to an image.
+fn augment_image<R: Rng>(img: &Matrix, options: &AugmentOptions, rng: &mut R) -> Matrix {
+    let (rows, cols) = img.dim();
+    let mirror = options.mirror && rng.gen::<bool>();
+    let degrees = symmetric(rng, options.max_rotation_degrees);
+    let scale = 1. + symmetric(rng, options.max_scale);
+    let shift = (
+        symmetric(rng, options.max_translation) * cols as f64,
+        symmetric(rng, options.max_translation) * rows as f64,
+    );
+    let brightness = symmetric(rng, options.max_brightness);
+    let contrast = 1. + symmetric(rng, options.max_contrast);
+    let gamma = (1. + options.max_gamma).powf(symmetric(rng, 1.));
+    let noise = if options.noise_sigma > 0. {
+        Some(Normal::new(0., options.noise_sigma))
+    } else {
+        None
+    };
+
+    let mut out = transform(img, mirror, degrees, scale, shift);
+    let mean = out.iter().sum::<i64>() as f64 / out.len() as f64;
+    out.mapv_inplace(|px| {
+        let mut v = (px as f64 - mean) * contrast + mean + brightness;
+        v = 255. * (v.clamp(0., 255.) / 255.).powf(gamma);
+        if let Some(ref noise) = noise {
+            v += noise.sample(rng);
+        }
+        v.round().clamp(0., 255.) as i64
+    });
+
+    out
+}
+
+/// The positives followed by `copies_per_positive` augmented copies of each.
+pub fn augment_positives(positives: Vec<Matrix>, options: &AugmentOptions) -> Vec<Matrix> {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+
+    let mut augmented = Vec::with_capacity(positives.len() * options.copies_per_positive);
+    for img in &positives {
+        for _ in 0..options.copies_per_positive {
+            augmented.push(augment_image(img, options, &mut rng));
+        }
+    }
+
+    let mut out = positives;
+    out.append(&mut augmented);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disabled() -> AugmentOptions {
+        AugmentOptions {
+            copies_per_positive: 1,
+            seed: 7,
+            mirror: false,
+            max_rotation_degrees: 0.,
+            max_scale: 0.,
+            max_translation: 0.,
+            max_brightness: 0.,
+            max_contrast: 0.,
+            max_gamma: 0.,
+            noise_sigma: 0.,
+        }
+    }
+
+    #[test]
+    fn geometric_transforms_move_pixels() {
+        let img = Matrix::from_shape_fn((3, 3), |(y, x)| (3 * y + x) as i64);
+
+        assert!(transform(&img, false, 0., 1., (0., 0.)) == img);
+        assert!(transform(&img, true, 0., 1., (0., 0.)) == array![[2, 1, 0], [5, 4, 3], [8, 7, 6]]);
+        // A quarter turn clockwise (in image coordinates, with y pointing down)
+        assert!(
+            transform(&img, false, 90., 1., (0., 0.)) == array![[6, 3, 0], [7, 4, 1], [8, 5, 2]]
+        );
+        // Shifting right replicates the left edge
+        assert!(
+            transform(&img, false, 0., 1., (1., 0.)) == array![[0, 0, 1], [3, 3, 4], [6, 6, 7]]
+        );
+    }
+
+    #[test]
+    fn augmentation_is_seeded_and_bounded() {
+        let faces = vec![Matrix::from_shape_fn((8, 8), |(y, x)| (30 * x + y) as i64)];
+
+        let none = augment_positives(faces.clone(), &disabled());
+        assert!(none.len() == 2);
+        assert!(none[0] == faces[0] && none[1] == faces[0]);
+
+        let options = AugmentOptions {
+            copies_per_positive: 3,
+            ..AugmentOptions::default()
+        };
+        let first = augment_positives(faces.clone(), &options);
+        let second = augment_positives(faces.clone(), &options);
+        assert!(first.len() == 4);
+        assert!(first == second);
+        assert!(first[1] != first[2]);
+        assert!(first
+            .iter()
+            .all(|img| img.iter().all(|px| 0 <= *px && *px <= 255)));
+
+        let reseeded = augment_positives(faces, &AugmentOptions { seed: 1, ..options });
+        assert!(reseeded[1..] != first[1..]);
+    }
+}
@@ -1,8 +1,227 @@
 extern crate viola_jones;
 
+use std::env;
+
 fn main() {
-    viola_jones::Learner::test_cascade("data/test_img.jpg", "saved_cascade.json");
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(|s| s.as_str()) {
+        Some("report") => {
+            if args.len() != 4 {
+                eprintln!(
+                    "Usage: {} report <training_log.jsonl> <report.html>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            viola_jones::training_log::write_report(&args[2], &args[3]);
+        }
+        Some("import") => {
+            let format = match args.get(2).map(|s| s.as_str()) {
+                Some("fddb") => viola_jones::datasets::AnnotationFormat::Fddb,
+                Some("wider") => viola_jones::datasets::AnnotationFormat::WiderFace,
+                Some("voc") => viola_jones::datasets::AnnotationFormat::PascalVoc,
+                _ => {
+                    eprintln!(
+                        "Usage: {} import <fddb|wider|voc> <annotations> <images_root> <out_dir>",
+                        args[0]
+                    );
+                    std::process::exit(1);
+                }
+            };
+            if args.len() != 6 {
+                eprintln!(
+                    "Usage: {} import <fddb|wider|voc> <annotations> <images_root> <out_dir>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            viola_jones::datasets::import_to_dir(
+                format,
+                &args[3],
+                &args[4],
+                &args[5],
+                &viola_jones::datasets::ImportOptions::default(),
+            );
+        }
+        Some("import-opencv") => {
+            if args.len() != 4 {
+                eprintln!(
+                    "Usage: {} import-opencv <haarcascade.xml> <saved_cascade.json>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            match viola_jones::opencv::import_cascade(&args[2], &args[3]) {
+                Ok(num_stages) => println!("Imported {} stages to {}", num_stages, args[3]),
+                Err(err) => {
+                    eprintln!("Failed to import the cascade: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("export-opencv") => {
+            if args.len() != 4 && args.len() != 5 {
+                eprintln!(
+                    "Usage: {} export-opencv <saved_cascade.json> <haarcascade.xml> [calibration_faces_dir]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let faces_dir = args.get(4).map(|s| s.as_str());
+            match viola_jones::opencv::export_cascade(&args[2], &args[3], faces_dir) {
+                Ok(num_stages) => {
+                    println!("Exported {} stages to {}", num_stages, args[3]);
+                    if faces_dir.is_some() {
+                        println!(
+                            "OpenCV normalizes each window's contrast, so windows unlike the calibration faces may be detected differently"
+                        );
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Failed to export the cascade: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("convert") => {
+            if args.len() != 4 {
+                eprintln!(
+                    "Usage: {} convert <saved_cascade.json|cascade.vjcb> <out>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            match viola_jones::compact::convert(&args[2], &args[3]) {
+                Ok(true) => println!("Wrote the compact cascade {}", args[3]),
+                Ok(false) => println!("Wrote the JSON cascade {}", args[3]),
+                Err(err) => {
+                    eprintln!("Failed to convert the cascade: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("inspect") => {
+            if args.len() < 3 || args.len() > 5 {
+                eprintln!(
+                    "Usage: {} inspect <saved_cascade> [windows_dir] [--json]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let json = args[3..].iter().any(|arg| arg == "--json");
+            let windows_dir = args[3..]
+                .iter()
+                .find(|arg| arg.as_str() != "--json")
+                .map(|s| s.as_str());
+            match viola_jones::inspect::inspect_cascade(&args[2], windows_dir) {
+                Ok(report) if json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("Failed to serialize the report")
+                ),
+                Ok(report) => print!("{}", report),
+                Err(err) => {
+                    eprintln!("Failed to inspect the cascade: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("train-manifest") => {
+            if args.len() != 4 {
+                eprintln!(
+                    "Usage: {} train-manifest <manifest.csv|json> <max_cascade_depth>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let depth = args[3].parse().expect("The cascade depth must be a number");
+            let mut learner = viola_jones::Learner::from_manifest(
+                &args[2],
+                depth,
+                viola_jones::PreprocessOptions::default(),
+            );
+            learner.train();
+        }
+        Some("evaluate") => {
+            let split = match args.get(4).map(|s| s.as_str()) {
+                Some("train") => viola_jones::manifest::Split::Train,
+                Some("val") => viola_jones::manifest::Split::Val,
+                Some("test") => viola_jones::manifest::Split::Test,
+                _ => {
+                    eprintln!(
+                        "Usage: {} evaluate <saved_cascade.json> <manifest.csv|json> <train|val|test>",
+                        args[0]
+                    );
+                    std::process::exit(1);
+                }
+            };
+            viola_jones::Learner::evaluate_manifest(
+                &args[2],
+                &args[3],
+                split,
+                &viola_jones::PreprocessOptions::default(),
+            );
+        }
+        Some("audit") => {
+            if args.len() < 5 {
+                eprintln!(
+                    "Usage: {} audit <faces_dir> <background_dir> <audit.json> [corrupt] [duplicates] [cross-class] [size]",
+                    args[0]
+                );
+                eprintln!(
+                    "Lists the conditions that fail the audit (corrupt and cross-class by default)"
+                );
+                std::process::exit(1);
+            }
+            let mut options = viola_jones::audit::AuditOptions::default();
+            if args.len() > 5 {
+                options.fail_on_corrupt = false;
+                options.fail_on_cross_class = false;
+                for condition in &args[5..] {
+                    match condition.as_str() {
+                        "corrupt" => options.fail_on_corrupt = true,
+                        "duplicates" => options.fail_on_duplicates = true,
+                        "cross-class" => options.fail_on_cross_class = true,
+                        "size" => options.fail_on_wrong_size = true,
+                        _ => {
+                            eprintln!("Unknown audit condition '{}'", condition);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+
+            let failures = viola_jones::audit::run_audit(&args[2], &args[3], &args[4], &options);
+            if !failures.is_empty() {
+                for failure in failures {
+                    eprintln!("Audit failed: {}", failure);
+                }
+                std::process::exit(1);
+            }
+        }
+        Some("visualize") => {
+            if args.len() != 5 {
+                eprintln!(
+                    "Usage: {} visualize <faces_dir> <saved_cascade.json> <out_dir>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            viola_jones::visualize::render_cascade(&args[2], &args[3], &args[4]);
+        }
+        _ => {
+            viola_jones::Learner::test_cascade("data/test_img.jpg", "saved_cascade.json");
 
-    // let mut learner = viola_jones::Learner::new("data/faces", "data/background", 4);
-    // learner.train();
+            // let mut learner = viola_jones::Learner::new("data/faces", "data/background", 4);
+            // learner.set_weak_learner(viola_jones::WeakLearnerKind::Tree { depth: 2 });
+            // learner.set_lbp_features(true);
+            // learner.set_haar_families(&[
+            //     (viola_jones::HaarFeatureType::ThreeHorizontal, Some(20000)),
+            //     (viola_jones::HaarFeatureType::CenterSurround, None),
+            //     (viola_jones::HaarFeatureType::TiltedTwoHorizontal, None),
+            // ]);
+            // learner.log_training_to("training_log.jsonl");
+            // learner.train();
+        }
+    }
 }
@@ -0,0 +1,344 @@
+/// An on-disk cache of preprocessed training data, so that repeated training runs
+/// on the same images skip decoding them and computing their integral images.
+use super::integral::{Accumulator, AnyIntegralImage, IntegralImage};
+use super::preprocess::{find_files, load_and_preprocess_data, IntegralImages, PreprocessOptions};
+use super::Classification;
+use ndarray::Array2;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 4] = b"VJIC";
+/// Bumped whenever the file layout or the preprocessing itself changes, which
+/// invalidates every existing cache file.
+const FORMAT_VERSION: u32 = 4;
+/// The smallest a sample can be: its label and two empty matrices (their
+/// dimensions and accumulator tag).
+const MIN_SAMPLE_LEN: u64 = 1 + 2 * 9;
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+pub struct Fnv1a(pub u64);
+
+impl Fnv1a {
+    pub fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Hash a field with its length, so that adjacent fields can't run together.
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+/// Hash the path, modification time and size of every file under both directories,
+/// with the preprocessing settings. Any change to the data or settings gives a new
+/// key, so stale cache files are never read.
+fn cache_key(
+    faces_dir: &str,
+    background_dir: &str,
+    options: &PreprocessOptions,
+) -> io::Result<u64> {
+    let mut hasher = Fnv1a::new();
+    hasher.write(&FORMAT_VERSION.to_le_bytes());
+    let settings = serde_json::to_string(options).expect("Failed to serialize the options");
+    hasher.write_field(settings.as_bytes());
+
+    for dir in &[faces_dir, background_dir] {
+        hasher.write_field(dir.as_bytes());
+        let mut paths = Vec::new();
+        find_files(Path::new(dir), &mut paths);
+        paths.sort();
+
+        for path in paths {
+            let metadata = fs::metadata(&path)?;
+            let mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            hasher.write_field(path.to_string_lossy().as_bytes());
+            hasher.write(&mtime.as_secs().to_le_bytes());
+            hasher.write(&mtime.subsec_nanos().to_le_bytes());
+            hasher.write(&metadata.len().to_le_bytes());
+        }
+    }
+
+    Ok(hasher.0)
+}
+
+fn write_matrix<W: Write>(out: &mut W, integral: &AnyIntegralImage) -> io::Result<()> {
+    let (rows, cols) = integral.dim();
+    out.write_all(&(rows as u32).to_le_bytes())?;
+    out.write_all(&(cols as u32).to_le_bytes())?;
+    // Each matrix keeps the accumulator it was built with, tagged by its width
+    match integral {
+        AnyIntegralImage::U32(img) => {
+            out.write_all(&[0])?;
+            for value in img.sums().iter() {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        AnyIntegralImage::I32(img) => {
+            out.write_all(&[1])?;
+            for value in img.sums().iter() {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        AnyIntegralImage::I64(img) => {
+            out.write_all(&[2])?;
+            for value in img.sums().iter() {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read `rows * cols` values of `width` bytes each from a file of `file_len` bytes,
+/// which bounds how much a corrupt size can make it allocate.
+fn read_sums<R: Read, T: Accumulator, F: Fn(&[u8]) -> T>(
+    input: &mut R,
+    (rows, cols): (usize, usize),
+    width: usize,
+    file_len: u64,
+    parse: F,
+) -> io::Result<IntegralImage<T>> {
+    let len = rows
+        .checked_mul(cols)
+        .and_then(|values| values.checked_mul(width))
+        .filter(|len| *len as u64 <= file_len)
+        .ok_or_else(|| invalid("matrix larger than the cache"))?;
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes)?;
+
+    let values = bytes.chunks(width).map(parse).collect();
+    let sums = Array2::from_shape_vec((rows, cols), values).expect("Matrix size was checked");
+    Ok(IntegralImage::from_sums_unchecked(sums))
+}
+
+fn read_matrix<R: Read>(input: &mut R, file_len: u64) -> io::Result<AnyIntegralImage> {
+    let dim = (read_u32(input)? as usize, read_u32(input)? as usize);
+    let mut tag = [0; 1];
+    input.read_exact(&mut tag)?;
+
+    Ok(match tag[0] {
+        0 => AnyIntegralImage::U32(read_sums(input, dim, 4, file_len, |b| {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })?),
+        1 => AnyIntegralImage::I32(read_sums(input, dim, 4, file_len, |b| {
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })?),
+        2 => AnyIntegralImage::I64(read_sums(input, dim, 8, file_len, |b| {
+            i64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+        })?),
+        _ => return Err(invalid("unknown accumulator")),
+    })
+}
+
+/// Write the training data to a cache file, through a temporary file so that an
+/// interrupted write never leaves a truncated cache behind.
+fn write_cache(path: &Path, inputs: &[(IntegralImages, Classification)]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut out = BufWriter::new(fs::File::create(&tmp_path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        out.write_all(&(inputs.len() as u64).to_le_bytes())?;
+        for (integral, label) in inputs {
+            out.write_all(&[match label {
+                Classification::Face => 1,
+                Classification::NonFace => 0,
+            }])?;
+            write_matrix(&mut out, &integral.upright)?;
+            write_matrix(&mut out, &integral.tilted)?;
+        }
+        out.flush()?;
+    }
+
+    fs::rename(tmp_path, path)
+}
+
+fn read_cache(path: &Path) -> io::Result<Vec<(IntegralImages, Classification)>> {
+    let file = fs::File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut input = BufReader::new(file);
+
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut input)? != FORMAT_VERSION {
+        return Err(invalid("not a training data cache of this version"));
+    }
+
+    let mut count = [0; 8];
+    input.read_exact(&mut count)?;
+    let count = u64::from_le_bytes(count);
+    if count
+        .checked_mul(MIN_SAMPLE_LEN)
+        .is_none_or(|len| len > file_len)
+    {
+        return Err(invalid("more samples than fit in the cache"));
+    }
+
+    let mut inputs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut label = [0; 1];
+        input.read_exact(&mut label)?;
+        let label = match label[0] {
+            1 => Classification::Face,
+            0 => Classification::NonFace,
+            _ => return Err(invalid("bad label")),
+        };
+        let upright = read_matrix(&mut input, file_len)?;
+        let tilted = read_matrix(&mut input, file_len)?;
+        if upright.dim() != tilted.dim() {
+            return Err(invalid("mismatched integral images"));
+        }
+        inputs.push((
+            IntegralImages {
+                upright,
+                tilted,
+                squared: None,
+            },
+            label,
+        ));
+    }
+
+    Ok(inputs)
+}
+
+/// The cache file for a set of training data, if its key could be computed.
+fn cache_path(
+    faces_dir: &str,
+    background_dir: &str,
+    options: &PreprocessOptions,
+    cache_dir: &Path,
+) -> io::Result<PathBuf> {
+    let key = cache_key(faces_dir, background_dir, options)?;
+    Ok(cache_dir.join(format!("{:016x}.bin", key)))
+}
+
+/// `load_and_preprocess_data`, reading the result from `cache_dir` if the same
+/// images were preprocessed with the same settings before, and caching it there
+/// otherwise. Failing to read or write the cache only costs the time saved.
+pub fn load_and_preprocess_data_cached(
+    faces_dir: &str,
+    background_dir: &str,
+    options: &PreprocessOptions,
+    cache_dir: &Path,
+) -> Vec<(IntegralImages, Classification)> {
+    let path = match cache_path(faces_dir, background_dir, options, cache_dir) {
+        Ok(path) => path,
+        Err(err) => {
+            println!("Not caching the training data: {}", err);
+            return load_and_preprocess_data(faces_dir, background_dir, options);
+        }
+    };
+
+    if path.exists() {
+        match read_cache(&path) {
+            Ok(inputs) => {
+                println!(
+                    "Loaded {} cached training images from {}",
+                    inputs.len(),
+                    path.display()
+                );
+                return inputs;
+            }
+            Err(err) => println!("Ignoring unreadable cache {}: {}", path.display(), err),
+        }
+    }
+
+    let inputs = load_and_preprocess_data(faces_dir, background_dir, options);
+    match fs::create_dir_all(cache_dir).and_then(|_| write_cache(&path, &inputs)) {
+        Ok(()) => println!("Cached the training data in {}", path.display()),
+        Err(err) => println!("Failed to cache the training data: {}", err),
+    }
+
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+    use image::{GrayImage, Luma};
+
+    fn save_window(path: &Path, value: u8) {
+        GrayImage::from_pixel(2, 2, Luma([value]))
+            .save(path)
+            .unwrap();
+    }
+
+    #[test]
+    fn cache_round_trips_and_invalidates() {
+        let dir = test_dir("cache");
+        let (faces, backgrounds, cache_dir) =
+            (dir.join("faces"), dir.join("background"), dir.join("cache"));
+        fs::create_dir_all(&faces).unwrap();
+        fs::create_dir_all(&backgrounds).unwrap();
+        save_window(&faces.join("a.png"), 200);
+        save_window(&backgrounds.join("b.png"), 20);
+
+        let (faces_dir, background_dir) = (faces.to_str().unwrap(), backgrounds.to_str().unwrap());
+        let options = PreprocessOptions {
+            window_size: 2,
+            ..PreprocessOptions::default()
+        };
+        let key = cache_key(faces_dir, background_dir, &options).unwrap();
+
+        let fresh =
+            load_and_preprocess_data_cached(faces_dir, background_dir, &options, &cache_dir);
+        let cache_file = cache_dir.join(format!("{:016x}.bin", key));
+        let cached = read_cache(&cache_file).unwrap();
+        assert!(cached.len() == 2);
+        for ((a, a_label), (b, b_label)) in fresh.iter().zip(&cached) {
+            assert!(a.upright == b.upright && a.tilted == b.tilted && a_label == b_label);
+        }
+
+        // Changing the settings or the data changes the key
+        let resized = PreprocessOptions {
+            window_size: 3,
+            ..options
+        };
+        assert!(cache_key(faces_dir, background_dir, &resized).unwrap() != key);
+        save_window(&faces.join("c.png"), 100);
+        assert!(cache_key(faces_dir, background_dir, &options).unwrap() != key);
+
+        // Corrupt caches are ignored rather than trusted
+        fs::write(&cache_file, b"VJIC\x04\0\0\0\x05").unwrap();
+        assert!(read_cache(&cache_file).is_err());
+
+        // Sizes past the end of the file fail before anything is allocated
+        let mut header = b"VJIC\x04\0\0\0".to_vec();
+        fs::write(&cache_file, [&header[..], &[0xff; 8]].concat()).unwrap();
+        assert!(read_cache(&cache_file).err().unwrap().kind() == io::ErrorKind::InvalidData);
+        header.extend_from_slice(&1u64.to_le_bytes());
+        header.push(1);
+        header.extend_from_slice(&[0xff; 8]);
+        header.push(2);
+        header.resize(header.len() + 64, 0);
+        fs::write(&cache_file, &header).unwrap();
+        assert!(read_cache(&cache_file).err().unwrap().kind() == io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1118 @@
+/// A compact binary format for saved cascades. Stages, weak learners, tree nodes,
+/// features, their rectangles and MB-LBP lookup tables are each flattened into a
+/// table of fixed-size little-endian records, which `CompactCascade` evaluates in
+/// place: a memory-mapped file runs without being parsed into classifiers.
+///
+/// The file is a 40-byte header (the magic `VJCB`, the format version, the window
+/// size or 0 if unknown, the length of the metadata and the number of records in
+/// each table), the cascade's `ModelMetadata` as JSON padded to 8 bytes, and then
+/// the tables in the order below.
+use super::decision_tree::{DecisionTree, Node as TreeNode};
+use super::feature::AnyFeature;
+use super::features::{HaarFeature, HaarFeatureType, Sign};
+use super::lbp::{LbpClassifier, MbLbpFeature};
+use super::model::{CascadeModel, ModelMetadata};
+use super::opencv::{self, Branch, OpenCvFeature, OpenCvNode, OpenCvTree, WeightedRect};
+use super::strong_classifier::{StrongClassifier, StumpScore};
+use super::util::{Rectangle, TiltedRectangle};
+use super::weak_classifier::WeakClassifier;
+use super::weak_learner::WeakLearner;
+use super::IntegralView;
+use memmap2::Mmap;
+use std::convert::TryInto;
+use std::fs::{self, File};
+
+pub const MAGIC: &[u8; 4] = b"VJCB";
+pub const FORMAT_VERSION: u32 = 1;
+
+const HEADER_LEN: usize = 40;
+/// The first weak learner, their number and the threshold.
+const STAGE_LEN: usize = 16;
+/// The kind, the root node (or lookup table, or `LEAF`), the depth of a tree or
+/// the number of nodes of an OpenCV tree, padding, the weight and the value of a
+/// root leaf.
+const LEARNER_LEN: usize = 32;
+/// The feature, the test, the threshold (an i64, or an f64 for `TEST_BELOW_NORM`),
+/// the nodes taken when the test passes and fails (or `LEAF`), and their leaf values.
+const NODE_LEN: usize = 40;
+/// The kind, the geometry (w, h, x, y) of Haar and MB-LBP features, the sign of a
+/// Haar feature's top-left cell, and the first rectangle and their number.
+const FEATURE_LEN: usize = 32;
+/// (x, y, w, h) and the weight.
+const RECT_LEN: usize = 24;
+/// The feature and the bit set of face codes.
+const LUT_LEN: usize = 36;
+
+/// Stumps scored by their margins.
+const LEARNER_MARGIN_STUMP: u32 = 0;
+const LEARNER_TREE: u32 = 1;
+const LEARNER_LUT: u32 = 2;
+const LEARNER_OPENCV: u32 = 3;
+/// Stumps voting +/-1.
+const LEARNER_STUMP: u32 = 4;
+
+/// Stump tests pass (send a tree to its face branch) when the toggled margin over
+/// the threshold is non-negative.
+const TEST_POSITIVE: u32 = 0;
+const TEST_NEGATIVE: u32 = 1;
+/// OpenCV tests pass (go left) when the feature is below the threshold times the
+/// window's variance normalization.
+const TEST_BELOW_NORM: u32 = 2;
+
+/// Feature kinds below 15 are Haar features of the type at that index of
+/// `haar_types()`.
+const FEATURE_LBP: u32 = 15;
+const FEATURE_OPENCV: u32 = 16;
+const FEATURE_OPENCV_TILTED: u32 = 17;
+
+const LEAF: i32 = -1;
+
+fn haar_types() -> Vec<HaarFeatureType> {
+    HaarFeatureType::UPRIGHT
+        .iter()
+        .chain(HaarFeatureType::TILTED.iter())
+        .cloned()
+        .collect()
+}
+
+/// Haar features of the tilted types, and tilted OpenCV features.
+fn is_tilted(kind: u32) -> bool {
+    match kind {
+        FEATURE_OPENCV_TILTED => true,
+        FEATURE_LBP | FEATURE_OPENCV => false,
+        kind => (kind as usize) >= HaarFeatureType::UPRIGHT.len(),
+    }
+}
+
+fn is_opencv(kind: u32) -> bool {
+    kind == FEATURE_OPENCV || kind == FEATURE_OPENCV_TILTED
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_i32(buf: &mut Vec<u8>, value: i32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f64(buf: &mut Vec<u8>, value: f64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn count(len: usize, what: &str) -> Result<u32, String> {
+    len.try_into()
+        .map_err(|_| format!("too many {} for the compact format", what))
+}
+
+fn pad8(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+struct NodeRecord {
+    feature: u32,
+    test: u32,
+    /// The bits of the i64 or f64 threshold.
+    threshold: [u8; 8],
+    pass: (i32, f64),
+    fail: (i32, f64),
+}
+
+/// The tables of a cascade being encoded.
+#[derive(Default)]
+struct Encoder {
+    stages: Vec<u8>,
+    learners: Vec<u8>,
+    nodes: Vec<NodeRecord>,
+    features: Vec<u8>,
+    rects: Vec<u8>,
+    luts: Vec<u8>,
+}
+
+impl Encoder {
+    fn push_feature(
+        &mut self,
+        kind: u32,
+        geometry: (usize, usize, usize, usize),
+        tl_sign: Sign,
+        rects: &[(usize, usize, usize, usize, f64)],
+    ) -> Result<u32, String> {
+        let index = count(self.features.len() / FEATURE_LEN, "features")?;
+        let first_rect = count(self.rects.len() / RECT_LEN, "rectangles")?;
+        let (w, h, x, y) = geometry;
+        put_u32(&mut self.features, kind);
+        for value in &[w, h, x, y] {
+            put_u32(&mut self.features, count(*value, "pixels")?);
+        }
+        put_u32(&mut self.features, (tl_sign == Sign::Negative) as u32);
+        put_u32(&mut self.features, first_rect);
+        put_u32(&mut self.features, count(rects.len(), "rectangles")?);
+
+        for (x, y, w, h, weight) in rects {
+            for value in &[x, y, w, h] {
+                put_u32(&mut self.rects, count(**value, "pixels")?);
+            }
+            put_f64(&mut self.rects, *weight);
+        }
+
+        Ok(index)
+    }
+
+    fn feature(&mut self, feature: &AnyFeature) -> Result<u32, String> {
+        match feature {
+            AnyFeature::Haar(haar) => {
+                let kind = haar_types()
+                    .iter()
+                    .position(|t| *t == haar.feature_type())
+                    .unwrap();
+                let rects: Vec<_> = haar
+                    .rects()
+                    .into_iter()
+                    .map(|((x, y, w, h), sign)| (x, y, w, h, (sign * 1) as f64))
+                    .collect();
+                self.push_feature(kind as u32, haar.geometry(), haar.tl_sign, &rects)
+            }
+            AnyFeature::Lbp(lbp) => {
+                let rects: Vec<_> = lbp
+                    .rects()
+                    .into_iter()
+                    .map(|(x, y, w, h)| (x, y, w, h, 1.))
+                    .collect();
+                self.push_feature(FEATURE_LBP, lbp.geometry(), Sign::Positive, &rects)
+            }
+        }
+    }
+
+    fn opencv_feature(&mut self, feature: &OpenCvFeature) -> Result<u32, String> {
+        let kind = if feature.tilted {
+            FEATURE_OPENCV_TILTED
+        } else {
+            FEATURE_OPENCV
+        };
+        let rects: Vec<_> = feature
+            .rects
+            .iter()
+            .map(|r| (r.x, r.y, r.w, r.h, r.weight))
+            .collect();
+        self.push_feature(kind, (0, 0, 0, 0), Sign::Positive, &rects)
+    }
+
+    fn push_node(&mut self, node: NodeRecord) -> Result<i32, String> {
+        let index = (self.nodes.len().try_into())
+            .map_err(|_| "too many nodes for the compact format".to_string())?;
+        self.nodes.push(node);
+        Ok(index)
+    }
+
+    fn stump(&mut self, stump: &WeakClassifier) -> Result<i32, String> {
+        let test = match stump.toggle() {
+            Sign::Positive => TEST_POSITIVE,
+            Sign::Negative => TEST_NEGATIVE,
+        };
+        let feature = self.feature(stump.feature())?;
+        self.push_node(NodeRecord {
+            feature,
+            test,
+            threshold: stump.threshold().to_le_bytes(),
+            pass: (LEAF, 0.),
+            fail: (LEAF, 0.),
+        })
+    }
+
+    /// Nodes are pushed in preorder, so every branch leads to a later node.
+    fn tree_branch(&mut self, node: &TreeNode) -> Result<(i32, f64), String> {
+        match node {
+            TreeNode::Leaf(value) => Ok((LEAF, *value)),
+            TreeNode::Split {
+                stump,
+                face,
+                non_face,
+            } => {
+                let index = self.stump(stump)?;
+                let pass = self.tree_branch(face)?;
+                let fail = self.tree_branch(non_face)?;
+                let record = &mut self.nodes[index as usize];
+                record.pass = pass;
+                record.fail = fail;
+                Ok((index, 0.))
+            }
+        }
+    }
+
+    /// The nodes of an OpenCV tree are kept together and in order.
+    fn opencv_tree(&mut self, tree: &OpenCvTree) -> Result<i32, String> {
+        let root = self.nodes.len() as i32;
+        let branch = |branch: Branch| match branch {
+            Branch::Node(i) => (root + i as i32, 0.),
+            Branch::Leaf(value) => (LEAF, value),
+        };
+        for node in &tree.nodes {
+            let feature = self.opencv_feature(&node.feature)?;
+            self.push_node(NodeRecord {
+                feature,
+                test: TEST_BELOW_NORM,
+                threshold: node.threshold.to_le_bytes(),
+                pass: branch(node.left),
+                fail: branch(node.right),
+            })?;
+        }
+
+        Ok(root)
+    }
+
+    fn learner(
+        &mut self,
+        learner: &WeakLearner,
+        weight: f64,
+        stump_score: StumpScore,
+    ) -> Result<(), String> {
+        let (kind, (root, leaf), aux) = match learner {
+            WeakLearner::Stump(stump) => {
+                let kind = match stump_score {
+                    StumpScore::Vote => LEARNER_STUMP,
+                    StumpScore::Margin => LEARNER_MARGIN_STUMP,
+                };
+                (kind, (self.stump(stump)?, 0.), 0)
+            }
+            WeakLearner::Tree(tree) => (LEARNER_TREE, self.tree_branch(tree.root())?, tree.depth()),
+            WeakLearner::Lbp(lut) => {
+                let feature = self.feature(&AnyFeature::Lbp(*lut.feature()))?;
+                let index = count(self.luts.len() / LUT_LEN, "lookup tables")?;
+                put_u32(&mut self.luts, feature);
+                for bits in &lut.face_codes() {
+                    put_u32(&mut self.luts, *bits);
+                }
+                (LEARNER_LUT, (index as i32, 0.), 0)
+            }
+            WeakLearner::OpenCv(tree) => (
+                LEARNER_OPENCV,
+                (self.opencv_tree(tree)?, 0.),
+                tree.nodes.len(),
+            ),
+        };
+
+        put_u32(&mut self.learners, kind);
+        put_i32(&mut self.learners, root);
+        put_u32(&mut self.learners, count(aux, "nodes")?);
+        put_u32(&mut self.learners, 0);
+        put_f64(&mut self.learners, weight);
+        put_f64(&mut self.learners, leaf);
+        Ok(())
+    }
+}
+
+/// Encode a cascade in the compact format.
+pub fn encode(model: &CascadeModel) -> Result<Vec<u8>, String> {
+    let mut encoder = Encoder::default();
+    for stage in &model.stages {
+        put_u32(
+            &mut encoder.stages,
+            count(encoder.learners.len() / LEARNER_LEN, "weak learners")?,
+        );
+        put_u32(
+            &mut encoder.stages,
+            count(stage.classifiers.len(), "weak learners")?,
+        );
+        put_f64(&mut encoder.stages, stage.threshold());
+        for (learner, weight) in stage.classifiers.iter().zip(&stage.weights) {
+            encoder.learner(learner, *weight, stage.stump_score)?;
+        }
+    }
+
+    let mut nodes = Vec::with_capacity(encoder.nodes.len() * NODE_LEN);
+    for node in &encoder.nodes {
+        put_u32(&mut nodes, node.feature);
+        put_u32(&mut nodes, node.test);
+        nodes.extend_from_slice(&node.threshold);
+        put_i32(&mut nodes, node.pass.0);
+        put_i32(&mut nodes, node.fail.0);
+        put_f64(&mut nodes, node.pass.1);
+        put_f64(&mut nodes, node.fail.1);
+    }
+
+    let metadata = serde_json::to_vec(&model.metadata).expect("Failed to serialize the metadata");
+    let window_size = model.metadata.window_size().unwrap_or(0);
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    put_u32(&mut bytes, FORMAT_VERSION);
+    put_u32(&mut bytes, count(window_size, "pixels")?);
+    put_u32(&mut bytes, count(metadata.len(), "bytes of metadata")?);
+    put_u32(&mut bytes, count(model.stages.len(), "stages")?);
+    for (table, len) in &[
+        (&encoder.learners, LEARNER_LEN),
+        (&nodes, NODE_LEN),
+        (&encoder.features, FEATURE_LEN),
+        (&encoder.rects, RECT_LEN),
+        (&encoder.luts, LUT_LEN),
+    ] {
+        put_u32(&mut bytes, count(table.len() / len, "records")?);
+    }
+    bytes.extend_from_slice(&metadata);
+    bytes.resize(pad8(bytes.len()), 0);
+    for table in &[
+        encoder.stages,
+        encoder.learners,
+        nodes,
+        encoder.features,
+        encoder.rects,
+        encoder.luts,
+    ] {
+        bytes.extend_from_slice(table);
+    }
+
+    Ok(bytes)
+}
+
+/// Where a table starts, and its number of records.
+#[derive(Copy, Clone, Debug, Default)]
+struct Table {
+    offset: usize,
+    len: usize,
+}
+
+/// A cascade in the compact format, evaluated directly from its bytes.
+pub struct CompactCascade<B: AsRef<[u8]>> {
+    bytes: B,
+    window_size: usize,
+    metadata: Table,
+    stages: Table,
+    learners: Table,
+    nodes: Table,
+    features: Table,
+    rects: Table,
+    luts: Table,
+}
+
+/// Whether a file's contents are in the compact format.
+pub fn is_compact(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+impl CompactCascade<Mmap> {
+    /// Memory-map a compact cascade. The file must not change while it's mapped.
+    pub fn open(path: &str) -> Result<CompactCascade<Mmap>, String> {
+        let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+        let bytes = unsafe { Mmap::map(&file) }.map_err(|err| format!("{}: {}", path, err))?;
+        CompactCascade::from_bytes(bytes).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+impl<B: AsRef<[u8]>> CompactCascade<B> {
+    /// Check the header and every index in the tables, so that evaluating the
+    /// cascade only reads within them and always reaches a leaf.
+    pub fn from_bytes(bytes: B) -> Result<CompactCascade<B>, String> {
+        let len = bytes.as_ref().len();
+        if len < HEADER_LEN || !is_compact(bytes.as_ref()) {
+            return Err("not a compact cascade".to_string());
+        }
+
+        let mut cascade = CompactCascade {
+            bytes,
+            window_size: 0,
+            metadata: Table::default(),
+            stages: Table::default(),
+            learners: Table::default(),
+            nodes: Table::default(),
+            features: Table::default(),
+            rects: Table::default(),
+            luts: Table::default(),
+        };
+        let version = cascade.u32_at(4);
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(format!(
+                "unsupported compact format version {} (this build reads versions 1 to {})",
+                version, FORMAT_VERSION
+            ));
+        }
+        cascade.window_size = cascade.u32_at(8) as usize;
+        cascade.metadata = Table {
+            offset: HEADER_LEN,
+            len: cascade.u32_at(12) as usize,
+        };
+
+        let mut offset = pad8(HEADER_LEN + cascade.metadata.len);
+        let mut tables = [Table::default(); 6];
+        let record_lens = [
+            STAGE_LEN,
+            LEARNER_LEN,
+            NODE_LEN,
+            FEATURE_LEN,
+            RECT_LEN,
+            LUT_LEN,
+        ];
+        for (i, (table, record_len)) in tables.iter_mut().zip(&record_lens).enumerate() {
+            let num_records = cascade.u32_at(16 + 4 * i) as usize;
+            *table = Table {
+                offset,
+                len: num_records,
+            };
+            offset += num_records * record_len;
+        }
+        if offset != len {
+            return Err(format!(
+                "the tables take {} bytes but the file has {}",
+                offset, len
+            ));
+        }
+        cascade.stages = tables[0];
+        cascade.learners = tables[1];
+        cascade.nodes = tables[2];
+        cascade.features = tables[3];
+        cascade.rects = tables[4];
+        cascade.luts = tables[5];
+
+        cascade.validate()?;
+        Ok(cascade)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let side = self.window_size().unwrap_or(usize::MAX);
+        for i in 0..self.features.len {
+            let (kind, first, num_rects) = self.feature(i);
+            if kind > FEATURE_OPENCV_TILTED {
+                return Err(format!("feature {} has unknown kind {}", i, kind));
+            }
+            if first + num_rects > self.rects.len || (kind == FEATURE_LBP && num_rects != 9) {
+                return Err(format!("feature {} has the wrong rectangles", i));
+            }
+            for r in first..first + num_rects {
+                let (x, y, w, h, _) = self.rect(r);
+                let fits = if is_tilted(kind) {
+                    x >= h && x.saturating_add(w) <= side && y.saturating_add(w + h) <= side
+                } else {
+                    x.saturating_add(w) <= side && y.saturating_add(h) <= side
+                };
+                if !fits {
+                    return Err(format!("feature {} doesn't fit in the window", i));
+                }
+            }
+        }
+
+        // OpenCV tests are exactly those on OpenCV features, and every branch leads
+        // to a later node, so walks end
+        for i in 0..self.nodes.len {
+            let (feature, test, pass, fail) = self.node(i);
+            let forward = |branch: i32| {
+                branch == LEAF || branch as usize > i && (branch as usize) < self.nodes.len
+            };
+            let valid = feature < self.features.len
+                && test <= TEST_BELOW_NORM
+                && (test == TEST_BELOW_NORM) == is_opencv(self.feature(feature).0)
+                && forward(pass)
+                && forward(fail);
+            if !valid {
+                return Err(format!("node {} is malformed", i));
+            }
+        }
+
+        for i in 0..self.learners.len {
+            let (kind, root, aux) = self.learner(i);
+            let valid = match kind {
+                LEARNER_STUMP | LEARNER_MARGIN_STUMP => self.is_stump_node(root),
+                LEARNER_TREE => root == LEAF || self.is_stump_tree(root as usize),
+                LEARNER_LUT => {
+                    root >= 0
+                        && (root as usize) < self.luts.len
+                        && self.lut_feature(root as usize) < self.features.len
+                        && self.feature(self.lut_feature(root as usize)).0 == FEATURE_LBP
+                }
+                LEARNER_OPENCV => {
+                    root >= 0
+                        && aux > 0
+                        && (root as usize).checked_add(aux).is_some_and(|end| {
+                            end <= self.nodes.len
+                                && (root as usize..end).all(|n| {
+                                    let (_, test, pass, fail) = self.node(n);
+                                    test == TEST_BELOW_NORM
+                                        && pass < end as i32
+                                        && fail < end as i32
+                                })
+                        })
+                }
+                _ => false,
+            };
+            if !valid {
+                return Err(format!("weak learner {} is malformed", i));
+            }
+        }
+
+        for i in 0..self.stages.len {
+            let (first, num_learners, _) = self.stage(i);
+            if first + num_learners > self.learners.len {
+                return Err(format!("stage {} has learners past the table", i));
+            }
+            let kinds: Vec<u32> = (first..first + num_learners)
+                .map(|l| self.learner(l).0)
+                .collect();
+            if kinds.contains(&LEARNER_STUMP) && kinds.contains(&LEARNER_MARGIN_STUMP) {
+                return Err(format!("stage {} mixes stump votes and margins", i));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_stump_node(&self, index: i32) -> bool {
+        index >= 0 && (index as usize) < self.nodes.len && {
+            let test = self.node(index as usize).1;
+            test == TEST_POSITIVE || test == TEST_NEGATIVE
+        }
+    }
+
+    /// Whether every node of a tree is a stump (nodes having been validated).
+    fn is_stump_tree(&self, root: usize) -> bool {
+        let mut nodes = vec![root as i32];
+        while let Some(index) = nodes.pop() {
+            if index == LEAF {
+                continue;
+            }
+            if !self.is_stump_node(index) {
+                return false;
+            }
+            let (_, _, pass, fail) = self.node(index as usize);
+            nodes.push(pass);
+            nodes.push(fail);
+        }
+
+        true
+    }
+
+    fn slice(&self, offset: usize, len: usize) -> &[u8] {
+        &self.bytes.as_ref()[offset..offset + len]
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.slice(offset, 4).try_into().unwrap())
+    }
+
+    fn i32_at(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.slice(offset, 4).try_into().unwrap())
+    }
+
+    fn i64_at(&self, offset: usize) -> i64 {
+        i64::from_le_bytes(self.slice(offset, 8).try_into().unwrap())
+    }
+
+    fn f64_at(&self, offset: usize) -> f64 {
+        f64::from_le_bytes(self.slice(offset, 8).try_into().unwrap())
+    }
+
+    /// (first learner, number of learners, threshold)
+    fn stage(&self, i: usize) -> (usize, usize, f64) {
+        let at = self.stages.offset + i * STAGE_LEN;
+        (
+            self.u32_at(at) as usize,
+            self.u32_at(at + 4) as usize,
+            self.f64_at(at + 8),
+        )
+    }
+
+    /// (kind, root, depth or number of nodes)
+    fn learner(&self, i: usize) -> (u32, i32, usize) {
+        let at = self.learners.offset + i * LEARNER_LEN;
+        (
+            self.u32_at(at),
+            self.i32_at(at + 4),
+            self.u32_at(at + 8) as usize,
+        )
+    }
+
+    /// (weight, value of a root leaf)
+    fn learner_values(&self, i: usize) -> (f64, f64) {
+        let at = self.learners.offset + i * LEARNER_LEN;
+        (self.f64_at(at + 16), self.f64_at(at + 24))
+    }
+
+    /// (feature, test, pass branch, fail branch)
+    fn node(&self, i: usize) -> (usize, u32, i32, i32) {
+        let at = self.nodes.offset + i * NODE_LEN;
+        (
+            self.u32_at(at) as usize,
+            self.u32_at(at + 4),
+            self.i32_at(at + 16),
+            self.i32_at(at + 20),
+        )
+    }
+
+    fn node_threshold_bits(&self, i: usize) -> usize {
+        self.nodes.offset + i * NODE_LEN + 8
+    }
+
+    /// The leaf values of the pass and fail branches.
+    fn node_leaves(&self, i: usize) -> (f64, f64) {
+        let at = self.nodes.offset + i * NODE_LEN;
+        (self.f64_at(at + 24), self.f64_at(at + 32))
+    }
+
+    /// (kind, first rectangle, number of rectangles)
+    fn feature(&self, i: usize) -> (u32, usize, usize) {
+        let at = self.features.offset + i * FEATURE_LEN;
+        (
+            self.u32_at(at),
+            self.u32_at(at + 24) as usize,
+            self.u32_at(at + 28) as usize,
+        )
+    }
+
+    /// (w, h, x, y, sign of the top-left cell)
+    fn feature_geometry(&self, i: usize) -> (usize, usize, usize, usize, Sign) {
+        let at = self.features.offset + i * FEATURE_LEN;
+        let sign = match self.u32_at(at + 20) {
+            0 => Sign::Positive,
+            _ => Sign::Negative,
+        };
+        (
+            self.u32_at(at + 4) as usize,
+            self.u32_at(at + 8) as usize,
+            self.u32_at(at + 12) as usize,
+            self.u32_at(at + 16) as usize,
+            sign,
+        )
+    }
+
+    /// (x, y, w, h, weight)
+    fn rect(&self, i: usize) -> (usize, usize, usize, usize, f64) {
+        let at = self.rects.offset + i * RECT_LEN;
+        (
+            self.u32_at(at) as usize,
+            self.u32_at(at + 4) as usize,
+            self.u32_at(at + 8) as usize,
+            self.u32_at(at + 12) as usize,
+            self.f64_at(at + 16),
+        )
+    }
+
+    fn lut_feature(&self, i: usize) -> usize {
+        self.u32_at(self.luts.offset + i * LUT_LEN) as usize
+    }
+
+    fn lut_codes(&self, i: usize) -> [u32; 8] {
+        let at = self.luts.offset + i * LUT_LEN + 4;
+        let mut codes = [0; 8];
+        for (j, bits) in codes.iter_mut().enumerate() {
+            *bits = self.u32_at(at + 4 * j);
+        }
+        codes
+    }
+
+    /// The window size the cascade was trained at, if recorded.
+    pub fn window_size(&self) -> Option<usize> {
+        if self.window_size == 0 {
+            None
+        } else {
+            Some(self.window_size)
+        }
+    }
+
+    pub fn num_stages(&self) -> usize {
+        self.stages.len
+    }
+
+    pub fn metadata(&self) -> Result<ModelMetadata, String> {
+        serde_json::from_slice(self.slice(self.metadata.offset, self.metadata.len))
+            .map_err(|err| format!("bad metadata: {}", err))
+    }
+
+    fn area(&self, rect: usize, tilted: bool, img: &IntegralView) -> i64 {
+        let (x, y, w, h, _) = self.rect(rect);
+        if tilted {
+            img.tilted
+                .compute_tilted_area(&TiltedRectangle::new(x, y, w, h))
+        } else {
+            img.upright
+                .compute_area(&Rectangle::new((x, y), (x + w, y + h)))
+        }
+    }
+
+    /// The response of a feature: the weighted sum of its rectangles, or the code
+    /// of an MB-LBP feature.
+    fn feature_value(&self, i: usize, img: &IntegralView) -> f64 {
+        let (kind, first, num_rects) = self.feature(i);
+        if kind == FEATURE_LBP {
+            let centre = self.area(first, false, img);
+            let mut code = 0;
+            for r in first + 1..first + num_rects {
+                code <<= 1;
+                if self.area(r, false, img) >= centre {
+                    code |= 1;
+                }
+            }
+            return f64::from(code);
+        }
+
+        (first..first + num_rects)
+            .map(|r| self.rect(r).4 * self.area(r, is_tilted(kind), img) as f64)
+            .sum()
+    }
+
+    /// The toggled margin of a stump's feature over its threshold.
+    fn margin(&self, node: usize, img: &IntegralView) -> i64 {
+        let (feature, test, _, _) = self.node(node);
+        let value = self.feature_value(feature, img) as i64;
+        let threshold = self.i64_at(self.node_threshold_bits(node));
+        match test {
+            TEST_NEGATIVE => threshold - value,
+            _ => value - threshold,
+        }
+    }
+
+    /// The value of the leaf a window ends up in, from a root node.
+    fn walk(&self, root: usize, img: &IntegralView, norm: f64) -> f64 {
+        let mut index = root;
+        loop {
+            let (feature, test, pass, fail) = self.node(index);
+            let passes = if test == TEST_BELOW_NORM {
+                let threshold = self.f64_at(self.node_threshold_bits(index));
+                self.feature_value(feature, img) < threshold * norm
+            } else {
+                self.margin(index, img) >= 0
+            };
+
+            let (pass_leaf, fail_leaf) = self.node_leaves(index);
+            let (next, leaf) = if passes {
+                (pass, pass_leaf)
+            } else {
+                (fail, fail_leaf)
+            };
+            if next == LEAF {
+                return leaf;
+            }
+            index = next as usize;
+        }
+    }
+
+    /// The score of a weak learner, as `WeakLearner::evaluate_raw` computes it.
+    fn learner_score(&self, i: usize, img: &IntegralView) -> f64 {
+        let (kind, root, _) = self.learner(i);
+        match kind {
+            LEARNER_STUMP if self.margin(root as usize, img) >= 0 => 1.,
+            LEARNER_STUMP => -1.,
+            LEARNER_MARGIN_STUMP => self.margin(root as usize, img) as f64,
+            LEARNER_TREE if root == LEAF => self.learner_values(i).1,
+            LEARNER_TREE => self.walk(root as usize, img, 1.),
+            LEARNER_LUT => {
+                let code = self.feature_value(self.lut_feature(root as usize), img) as usize;
+                if self.lut_codes(root as usize)[code / 32] & (1 << (code % 32)) != 0 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+            _ => self.walk(root as usize, img, opencv::variance_norm(img, 1.)),
+        }
+    }
+
+    /// Whether a stage accepts a window of the cascade's size.
+    pub fn stage_accepts(&self, stage: usize, img: &IntegralView) -> bool {
+        let (first, num_learners, threshold) = self.stage(stage);
+        let mut score = 0.;
+        for i in first..first + num_learners {
+            score += self.learner_values(i).0 * self.learner_score(i, img);
+        }
+
+        score - threshold >= 0.
+    }
+
+    /// Whether every stage accepts a window of the cascade's size.
+    pub fn detects(&self, img: &IntegralView) -> bool {
+        (0..self.stages.len).all(|stage| self.stage_accepts(stage, img))
+    }
+
+    fn decode_feature(&self, i: usize) -> AnyFeature {
+        let (kind, _, _) = self.feature(i);
+        let (w, h, x, y, tl_sign) = self.feature_geometry(i);
+        if kind == FEATURE_LBP {
+            AnyFeature::Lbp(MbLbpFeature::new(w, h, x, y))
+        } else {
+            let mut haar = HaarFeature::new(haar_types()[kind as usize], w, h, x, y);
+            haar.tl_sign = tl_sign;
+            AnyFeature::Haar(haar)
+        }
+    }
+
+    fn decode_opencv_feature(&self, i: usize) -> OpenCvFeature {
+        let (kind, first, num_rects) = self.feature(i);
+        OpenCvFeature {
+            rects: (first..first + num_rects)
+                .map(|r| {
+                    let (x, y, w, h, weight) = self.rect(r);
+                    WeightedRect { x, y, w, h, weight }
+                })
+                .collect(),
+            tilted: kind == FEATURE_OPENCV_TILTED,
+        }
+    }
+
+    fn decode_stump(&self, node: usize) -> WeakClassifier {
+        let (feature, test, _, _) = self.node(node);
+        let toggle = match test {
+            TEST_NEGATIVE => Sign::Negative,
+            _ => Sign::Positive,
+        };
+        let threshold = self.i64_at(self.node_threshold_bits(node));
+        WeakClassifier::new(&self.decode_feature(feature), threshold, toggle)
+    }
+
+    fn decode_tree_branch(&self, branch: i32, leaf: f64) -> TreeNode {
+        if branch == LEAF {
+            return TreeNode::Leaf(leaf);
+        }
+
+        let index = branch as usize;
+        let (_, _, pass, fail) = self.node(index);
+        let (pass_leaf, fail_leaf) = self.node_leaves(index);
+        TreeNode::Split {
+            stump: self.decode_stump(index),
+            face: Box::new(self.decode_tree_branch(pass, pass_leaf)),
+            non_face: Box::new(self.decode_tree_branch(fail, fail_leaf)),
+        }
+    }
+
+    fn decode_learner(&self, i: usize) -> WeakLearner {
+        let (kind, root, aux) = self.learner(i);
+        match kind {
+            LEARNER_STUMP | LEARNER_MARGIN_STUMP => {
+                WeakLearner::Stump(self.decode_stump(root as usize))
+            }
+            LEARNER_TREE => WeakLearner::Tree(DecisionTree::from_parts(
+                aux,
+                self.decode_tree_branch(root, self.learner_values(i).1),
+            )),
+            LEARNER_LUT => {
+                let feature = match self.decode_feature(self.lut_feature(root as usize)) {
+                    AnyFeature::Lbp(feature) => feature,
+                    AnyFeature::Haar(_) => unreachable!("lookup tables are over MB-LBP features"),
+                };
+                WeakLearner::Lbp(LbpClassifier::new(feature, self.lut_codes(root as usize)))
+            }
+            _ => {
+                let root = root as usize;
+                let branch = |branch: i32, leaf: f64| {
+                    if branch == LEAF {
+                        Branch::Leaf(leaf)
+                    } else {
+                        Branch::Node(branch as usize - root)
+                    }
+                };
+                let nodes = (root..root + aux)
+                    .map(|n| {
+                        let (feature, _, pass, fail) = self.node(n);
+                        let (pass_leaf, fail_leaf) = self.node_leaves(n);
+                        OpenCvNode {
+                            feature: self.decode_opencv_feature(feature),
+                            threshold: self.f64_at(self.node_threshold_bits(n)),
+                            left: branch(pass, pass_leaf),
+                            right: branch(fail, fail_leaf),
+                        }
+                    })
+                    .collect();
+                WeakLearner::OpenCv(OpenCvTree { nodes })
+            }
+        }
+    }
+
+    /// Decode the whole cascade, such as to save it as JSON.
+    pub fn to_model(&self) -> Result<CascadeModel, String> {
+        let mut stages = Vec::with_capacity(self.stages.len);
+        for s in 0..self.stages.len {
+            let (first, num_learners, threshold) = self.stage(s);
+            let mut classifiers = Vec::with_capacity(num_learners);
+            let mut weights = Vec::with_capacity(num_learners);
+            let mut stump_score = StumpScore::Margin;
+            for i in first..first + num_learners {
+                classifiers.push(self.decode_learner(i));
+                weights.push(self.learner_values(i).0);
+                if self.learner(i).0 == LEARNER_STUMP {
+                    stump_score = StumpScore::Vote;
+                }
+            }
+            let mut stage = StrongClassifier::from_parts(classifiers, weights, threshold);
+            stage.stump_score = stump_score;
+            stages.push(stage);
+        }
+
+        Ok(CascadeModel::new(self.metadata()?, stages))
+    }
+}
+
+/// Convert a saved cascade between JSON and the compact format, whichever it isn't
+/// in. Returns whether the output is compact.
+pub fn convert(in_path: &str, out_path: &str) -> Result<bool, String> {
+    let bytes = fs::read(in_path).map_err(|err| format!("{}: {}", in_path, err))?;
+    let model = CascadeModel::load(in_path)?;
+    if is_compact(&bytes) {
+        model.save(out_path)?;
+        Ok(false)
+    } else {
+        fs::write(out_path, encode(&model)?).map_err(|err| format!("{}: {}", out_path, err))?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ModelSource;
+    use crate::preprocess::{IntegralImages, PreprocessOptions};
+    use crate::Matrix;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// A cascade with a stage of stumps and a tree, a stage of a lookup table and an
+    /// OpenCV tree, and a stage scoring stumps by their margins, on 8x8 windows.
+    fn mixed_model() -> CascadeModel {
+        let haar =
+            |feature_type, w, h, x, y| AnyFeature::Haar(HaarFeature::new(feature_type, w, h, x, y));
+        let stump = WeakClassifier::new(
+            &haar(HaarFeatureType::TwoVertical, 2, 2, 1, 1),
+            3,
+            Sign::Positive,
+        );
+        let tilted = WeakClassifier::new(
+            &haar(HaarFeatureType::TiltedTwoHorizontal, 1, 2, 3, 0),
+            -2,
+            Sign::Negative,
+        );
+        let surround = WeakClassifier::new(
+            &haar(HaarFeatureType::CenterSurround, 2, 2, 0, 0),
+            10,
+            Sign::Positive,
+        );
+        let tree = DecisionTree::from_parts(
+            2,
+            TreeNode::Split {
+                stump: tilted,
+                face: Box::new(TreeNode::Split {
+                    stump: surround,
+                    face: Box::new(TreeNode::Leaf(0.75)),
+                    non_face: Box::new(TreeNode::Leaf(-0.25)),
+                }),
+                non_face: Box::new(TreeNode::Leaf(-1.)),
+            },
+        );
+        let lut = LbpClassifier::new(MbLbpFeature::new(2, 2, 1, 1), [0xdead_beef; 8]);
+        let rect = |x, y, w, h, weight| WeightedRect { x, y, w, h, weight };
+        let opencv_tree = OpenCvTree {
+            nodes: vec![
+                OpenCvNode {
+                    feature: OpenCvFeature {
+                        rects: vec![rect(0, 0, 8, 4, -1.), rect(0, 4, 8, 4, 1.)],
+                        tilted: false,
+                    },
+                    threshold: 0.01,
+                    left: Branch::Node(1),
+                    right: Branch::Leaf(0.5),
+                },
+                OpenCvNode {
+                    feature: OpenCvFeature {
+                        rects: vec![rect(4, 0, 2, 2, -1.), rect(4, 1, 1, 1, 2.)],
+                        tilted: true,
+                    },
+                    threshold: -0.02,
+                    left: Branch::Leaf(-0.5),
+                    right: Branch::Leaf(0.25),
+                },
+            ],
+        };
+
+        let votes = StrongClassifier::from_parts(
+            vec![
+                WeakLearner::Stump(stump),
+                WeakLearner::Tree(tree),
+                WeakLearner::Tree(DecisionTree::from_parts(1, TreeNode::Leaf(0.5))),
+            ],
+            vec![0.4, 1.1, 0.3],
+            -20.,
+        );
+        let mut margins = StrongClassifier::from_parts(
+            vec![WeakLearner::Stump(stump), WeakLearner::Lbp(lut)],
+            vec![0.5, 2.],
+            -1.5,
+        );
+        margins.stump_score = StumpScore::Margin;
+
+        let stages = vec![
+            votes,
+            StrongClassifier::from_parts(
+                vec![WeakLearner::Lbp(lut), WeakLearner::OpenCv(opencv_tree)],
+                vec![0.8, 0.6],
+                -0.1,
+            ),
+            margins,
+        ];
+        let options = PreprocessOptions {
+            window_size: 8,
+            ..PreprocessOptions::default()
+        };
+        CascadeModel::new(ModelMetadata::new(ModelSource::Trained, options), stages)
+    }
+
+    #[test]
+    // Decoding and re-encoding gives the same bytes, and the same JSON
+    fn compact_cascades_round_trip() {
+        let model = mixed_model();
+        let bytes = encode(&model).unwrap();
+        let compact = CompactCascade::from_bytes(&bytes[..]).unwrap();
+        assert!(compact.window_size() == Some(8) && compact.num_stages() == 3);
+
+        let decoded = compact.to_model().unwrap();
+        assert!(encode(&decoded).unwrap() == bytes);
+        assert!(
+            serde_json::to_string(&decoded.stages).unwrap()
+                == serde_json::to_string(&model.stages).unwrap()
+        );
+        assert!(decoded.metadata.created_at == model.metadata.created_at);
+    }
+
+    #[test]
+    // Compact cascades score every window as the stages they were encoded from
+    fn compact_cascades_evaluate_like_stages() {
+        let model = mixed_model();
+        let bytes = encode(&model).unwrap();
+        let compact = CompactCascade::from_bytes(&bytes[..]).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let img = Matrix::from_shape_fn((8, 8), |_| rng.gen_range(0, 256));
+            let img = IntegralImages::with_squares(&img);
+            for (s, stage) in model.stages.iter().enumerate() {
+                let accepts = stage.evaluate(&img.view()) == crate::Classification::Face;
+                assert!(compact.stage_accepts(s, &img.view()) == accepts);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_compact_cascades_are_rejected() {
+        let bytes = encode(&mixed_model()).unwrap();
+        assert!(CompactCascade::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(CompactCascade::from_bytes(&b"{\"stages\": []}"[..]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let err = CompactCascade::from_bytes(&newer[..]).err().unwrap();
+        assert!(err.contains("unsupported compact format version"));
+
+        // A stage reaching past the learners
+        let compact = CompactCascade::from_bytes(&bytes[..]).unwrap();
+        let mut broken = bytes.clone();
+        let at = compact.stages.offset + STAGE_LEN + 4;
+        broken[at..at + 4].copy_from_slice(&100u32.to_le_bytes());
+        assert!(CompactCascade::from_bytes(&broken[..]).is_err());
+
+        // An OpenCV tree rooted before the first node
+        let opencv = (0..compact.learners.len)
+            .find(|i| compact.learner(*i).0 == LEARNER_OPENCV)
+            .unwrap();
+        let mut broken = bytes.clone();
+        let at = compact.learners.offset + opencv * LEARNER_LEN + 4;
+        broken[at..at + 4].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(CompactCascade::from_bytes(&broken[..]).is_err());
+
+        // A tree node branching back to itself
+        let mut broken = bytes.clone();
+        let at = compact.nodes.offset + NODE_LEN + 16;
+        broken[at..at + 4].copy_from_slice(&1i32.to_le_bytes());
+        assert!(CompactCascade::from_bytes(&broken[..]).is_err());
+    }
+}
@@ -0,0 +1,519 @@
+/// Importers that crop training windows out of annotated face datasets.
+use super::preprocess::{load_image, resize_matrix, LumaStandard, PreprocessOptions, ResizeFilter};
+use super::Matrix;
+use image::{GrayImage, Luma};
+use roxmltree::{Document, Node};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A face's bounding box in image coordinates: (x, y, w, h).
+pub type FaceBox = (f64, f64, f64, f64);
+
+/// The faces annotated in one image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub image: PathBuf,
+    pub faces: Vec<FaceBox>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    /// FDDB ellipse lists (`FDDB-fold-*-ellipseList.txt`), with image paths
+    /// relative to the image root and missing their `.jpg` extension.
+    Fddb,
+    /// WIDER FACE ground truth (`wider_face_*_bbx_gt.txt`).
+    WiderFace,
+    /// Pascal VOC XML, one file per image.
+    PascalVoc,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    pub window_size: usize,
+    /// Faces whose longer side is smaller than this (in pixels) are skipped.
+    pub min_face_size: f64,
+    /// The margin added around each squared-up face, as a fraction of its side.
+    pub padding: f64,
+    pub filter: ResizeFilter,
+    pub luma: LumaStandard,
+    /// The object name marking faces in Pascal VOC annotations.
+    pub voc_label: String,
+}
+
+impl Default for ImportOptions {
+    fn default() -> ImportOptions {
+        ImportOptions {
+            window_size: 64,
+            min_face_size: 24.,
+            padding: 0.1,
+            filter: ResizeFilter::Triangle,
+            luma: LumaStandard::Rec601,
+            voc_label: "face".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub images: usize,
+    pub faces: usize,
+    /// Faces skipped for being smaller than the minimum size.
+    pub too_small: usize,
+    /// Images that couldn't be read, with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Imported {} faces from {} images, skipped {} small faces, failed on {} images",
+            self.faces,
+            self.images,
+            self.too_small,
+            self.failed.len()
+        )?;
+        for (path, err) in &self.failed {
+            write!(f, "\n  failed {}: {}", path.display(), err)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse whitespace-separated numbers, failing on the first that isn't one.
+fn parse_numbers(line: &str, line_num: usize) -> Result<Vec<f64>, String> {
+    line.split_whitespace()
+        .map(|field| {
+            field
+                .parse()
+                .map_err(|_| format!("line {}: expected a number, got '{}'", line_num, field))
+        })
+        .collect()
+}
+
+/// The non-empty lines of an annotation file, numbered from 1.
+fn numbered_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn next_count<'a, I: Iterator<Item = (usize, &'a str)>>(
+    lines: &mut I,
+    image: &str,
+) -> Result<usize, String> {
+    match lines.next() {
+        Some((line_num, line)) => line
+            .parse()
+            .map_err(|_| format!("line {}: expected a face count, got '{}'", line_num, line)),
+        None => Err(format!("missing face count for {}", image)),
+    }
+}
+
+/// Parse an FDDB ellipse list. Each face is the bounding box of its ellipse.
+pub fn parse_fddb(text: &str, images_root: &Path) -> Result<Vec<Annotation>, String> {
+    let mut lines = numbered_lines(text);
+    let mut annotations = Vec::new();
+    while let Some((_, image)) = lines.next() {
+        let count = next_count(&mut lines, image)?;
+        let mut faces = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (line_num, line) = lines
+                .next()
+                .ok_or_else(|| format!("missing faces for {}", image))?;
+            let fields = parse_numbers(line, line_num)?;
+            if fields.len() < 5 {
+                return Err(format!("line {}: expected an ellipse", line_num));
+            }
+
+            // (major radius, minor radius, angle of the major axis, centre x, centre y)
+            let (a, b, angle, cx, cy) = (fields[0], fields[1], fields[2], fields[3], fields[4]);
+            let (sin, cos) = angle.sin_cos();
+            let half_w = (a * a * cos * cos + b * b * sin * sin).sqrt();
+            let half_h = (a * a * sin * sin + b * b * cos * cos).sqrt();
+            faces.push((cx - half_w, cy - half_h, 2. * half_w, 2. * half_h));
+        }
+
+        annotations.push(Annotation {
+            image: images_root.join(format!("{}.jpg", image)),
+            faces,
+        });
+    }
+
+    Ok(annotations)
+}
+
+/// Parse WIDER FACE ground truth, leaving out boxes flagged as invalid.
+pub fn parse_wider_face(text: &str, images_root: &Path) -> Result<Vec<Annotation>, String> {
+    let mut lines = numbered_lines(text).peekable();
+    let mut annotations = Vec::new();
+    while let Some((_, image)) = lines.next() {
+        let count = next_count(&mut lines, image)?;
+        // Images without faces are followed by a single line of zeros
+        let rows = if count == 0 {
+            match lines.peek() {
+                Some((line_num, line)) if parse_numbers(line, *line_num).is_ok() => 1,
+                _ => 0,
+            }
+        } else {
+            count
+        };
+
+        let mut faces = Vec::with_capacity(count);
+        for _ in 0..rows {
+            let (line_num, line) = lines
+                .next()
+                .ok_or_else(|| format!("missing faces for {}", image))?;
+            let fields = parse_numbers(line, line_num)?;
+            if fields.len() < 4 {
+                return Err(format!("line {}: expected a bounding box", line_num));
+            }
+
+            // x, y, w, h, blur, expression, illumination, invalid, occlusion, pose
+            let invalid = fields.get(7).is_some_and(|flag| *flag != 0.);
+            if count > 0 && !invalid && fields[2] > 0. && fields[3] > 0. {
+                faces.push((fields[0], fields[1], fields[2], fields[3]));
+            }
+        }
+
+        annotations.push(Annotation {
+            image: images_root.join(image),
+            faces,
+        });
+    }
+
+    Ok(annotations)
+}
+
+/// The first child element of a VOC node with the given tag.
+fn voc_child<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(tag))
+}
+
+/// The text of the first child element with the given tag, trimmed. Comments
+/// inside the element are skipped, and entities and CDATA sections are decoded.
+fn voc_text(node: Node, tag: &str) -> Option<String> {
+    voc_child(node, tag).map(|child| {
+        let text: String = child
+            .children()
+            .filter(|n| n.is_text())
+            .filter_map(|n| n.text())
+            .collect();
+        text.trim().to_string()
+    })
+}
+
+/// Parse a Pascal VOC annotation, keeping the objects named `label`.
+pub fn parse_voc(xml: &str, images_root: &Path, label: &str) -> Result<Annotation, String> {
+    let doc = Document::parse(xml).map_err(|err| err.to_string())?;
+    let annotation = doc.root_element();
+    let filename = voc_text(annotation, "filename").ok_or("missing <filename>")?;
+
+    let mut faces = Vec::new();
+    for object in annotation.children().filter(|n| n.has_tag_name("object")) {
+        if voc_text(object, "name").as_deref() != Some(label) {
+            continue;
+        }
+
+        let bndbox = voc_child(object, "bndbox").ok_or("object without a <bndbox>")?;
+        let mut coords = [0.; 4];
+        for (coord, tag) in coords.iter_mut().zip(&["xmin", "ymin", "xmax", "ymax"]) {
+            let value = voc_text(bndbox, tag).ok_or_else(|| format!("missing <{}>", tag))?;
+            *coord = value
+                .parse()
+                .map_err(|_| format!("<{}> isn't a number: '{}'", tag, value))?;
+        }
+
+        // VOC boxes are 1-based and include both edges
+        let [xmin, ymin, xmax, ymax] = coords;
+        faces.push((xmin - 1., ymin - 1., xmax - xmin + 1., ymax - ymin + 1.));
+    }
+
+    Ok(Annotation {
+        image: images_root.join(filename),
+        faces,
+    })
+}
+
+/// Whether a file found in an annotations directory holds annotations: FDDB's
+/// ellipse lists (not the image lists next to them), VOC's XML files, or for WIDER
+/// FACE anything else.
+fn is_annotation_file(format: AnnotationFormat, file: &Path) -> bool {
+    let name = file.file_name().map(|name| name.to_string_lossy());
+    let is_xml = file.extension().is_some_and(|ext| ext == "xml");
+    match format {
+        AnnotationFormat::Fddb => name.is_some_and(|name| name.ends_with("-ellipseList.txt")),
+        AnnotationFormat::WiderFace => !is_xml,
+        AnnotationFormat::PascalVoc => is_xml,
+    }
+}
+
+/// Read the annotations at `path`: a single file, or every annotation file
+/// (recursively, in sorted order) under a directory, e.g. the `FDDB-folds`
+/// directory with all ten folds or a VOC `Annotations` directory.
+pub fn read_annotations(
+    format: AnnotationFormat,
+    path: &Path,
+    images_root: &Path,
+    options: &ImportOptions,
+) -> Result<Vec<Annotation>, String> {
+    let mut files = Vec::new();
+    collect_files(path, &mut files).map_err(|err| format!("{}: {}", path.display(), err))?;
+    files.sort();
+
+    let mut annotations = Vec::new();
+    for file in files {
+        if path.is_dir() && !is_annotation_file(format, &file) {
+            continue;
+        }
+
+        let text =
+            fs::read_to_string(&file).map_err(|err| format!("{}: {}", file.display(), err))?;
+        let parsed = match format {
+            AnnotationFormat::Fddb => parse_fddb(&text, images_root),
+            AnnotationFormat::WiderFace => parse_wider_face(&text, images_root),
+            AnnotationFormat::PascalVoc => {
+                parse_voc(&text, images_root, &options.voc_label).map(|a| vec![a])
+            }
+        };
+        annotations.extend(parsed.map_err(|err| format!("{}: {}", file.display(), err))?);
+    }
+
+    Ok(annotations)
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        collect_files(&entry?.path(), files)?;
+    }
+
+    Ok(())
+}
+
+/// Square up a face box about its centre, pad it, and crop it from the image,
+/// replicating edge pixels where the crop leaves the image. The crop is resized to
+/// the window.
+pub fn crop_face(img: &Matrix, face: FaceBox, options: &ImportOptions) -> Matrix {
+    let (x, y, w, h) = face;
+    let side = w.max(h) * (1. + 2. * options.padding);
+    let (left, top) = (x + w / 2. - side / 2., y + h / 2. - side / 2.);
+    let pixels = (side.round() as usize).max(1);
+
+    let (rows, cols) = img.dim();
+    let crop = Matrix::from_shape_fn((pixels, pixels), |(r, c)| {
+        let sy = (top + r as f64).round().max(0.).min((rows - 1) as f64);
+        let sx = (left + c as f64).round().max(0.).min((cols - 1) as f64);
+        img[[sy as usize, sx as usize]]
+    });
+
+    resize_matrix(&crop, options.window_size, options.filter)
+}
+
+/// Crop a training window for every large enough face in the annotated images.
+pub fn import_faces(
+    annotations: &[Annotation],
+    options: &ImportOptions,
+) -> (Vec<Matrix>, ImportReport) {
+    let preprocess_options = PreprocessOptions {
+        luma: options.luma,
+        ..PreprocessOptions::default()
+    };
+
+    let mut windows = Vec::new();
+    let mut report = ImportReport::default();
+    for annotation in annotations {
+        let img = match load_image(&annotation.image, &preprocess_options) {
+            Ok(img) => img,
+            Err(err) => {
+                report
+                    .failed
+                    .push((annotation.image.clone(), err.to_string()));
+                continue;
+            }
+        };
+        report.images += 1;
+
+        for face in &annotation.faces {
+            if face.2.max(face.3) < options.min_face_size {
+                report.too_small += 1;
+                continue;
+            }
+            windows.push(crop_face(&img, *face, options));
+        }
+    }
+    report.faces = windows.len();
+
+    (windows, report)
+}
+
+/// Import the faces of an annotated dataset into a directory of PNG windows that
+/// can be used as the faces directory of a `Learner`.
+pub fn import_to_dir(
+    format: AnnotationFormat,
+    annotations_path: &str,
+    images_root: &str,
+    out_dir: &str,
+    options: &ImportOptions,
+) -> ImportReport {
+    let annotations = read_annotations(
+        format,
+        Path::new(annotations_path),
+        Path::new(images_root),
+        options,
+    )
+    .unwrap_or_else(|err| panic!("Failed to read annotations: {}", err));
+    let (windows, report) = import_faces(&annotations, options);
+
+    fs::create_dir_all(out_dir).expect("Failed to create the output directory");
+    for (i, window) in windows.iter().enumerate() {
+        let (rows, cols) = window.dim();
+        GrayImage::from_fn(cols as u32, rows as u32, |x, y| {
+            Luma([window[[y as usize, x as usize]] as u8])
+        })
+        .save(Path::new(out_dir).join(format!("face_{:06}.png", i)))
+        .expect("Failed to write a face window");
+    }
+
+    println!("{}", report);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+
+    #[test]
+    fn fddb_ellipses_become_boxes() {
+        let text = "2002/08/11/big/img_591\n1\n20 10 0 50 40  1\n\n2002/08/26/big/img_265\n2\n\
+                    10 10 1.5707963 30 30 1\n20 10 1.5707963 60 60 1\n";
+        let annotations = parse_fddb(text, Path::new("fddb")).unwrap();
+
+        assert!(annotations.len() == 2);
+        assert!(annotations[0].image == Path::new("fddb/2002/08/11/big/img_591.jpg"));
+        assert!(annotations[0].faces == vec![(30., 30., 40., 20.)]);
+
+        // A vertical major axis makes the box taller than it is wide
+        let (x, y, w, h) = annotations[1].faces[1];
+        assert!((x - 50.).abs() < 1e-6 && (y - 40.).abs() < 1e-6);
+        assert!((w - 20.).abs() < 1e-6 && (h - 40.).abs() < 1e-6);
+
+        assert!(parse_fddb("img\n2\n1 1 0 5 5 1\n", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn wider_face_skips_invalid_boxes() {
+        let text = "0--Parade/0_Parade_1.jpg\n2\n\
+                    10 20 30 40 0 0 0 0 0 0\n1 2 3 4 0 0 0 1 0 0\n\
+                    0--Parade/0_Parade_2.jpg\n0\n0 0 0 0 0 0 0 0 0 0\n\
+                    0--Parade/0_Parade_3.jpg\n1\n5 5 8 9 0 0 0 0 0 0\n";
+        let annotations = parse_wider_face(text, Path::new("wider")).unwrap();
+
+        assert!(annotations.len() == 3);
+        assert!(annotations[0].image == Path::new("wider/0--Parade/0_Parade_1.jpg"));
+        assert!(annotations[0].faces == vec![(10., 20., 30., 40.)]);
+        assert!(annotations[1].faces.is_empty());
+        assert!(annotations[2].faces == vec![(5., 5., 8., 9.)]);
+    }
+
+    #[test]
+    fn voc_objects_are_filtered_by_label() {
+        let xml = "<annotation>\n  <filename>party.jpg</filename>\n\
+                   <object><name>face</name><bndbox><xmin>11</xmin><ymin>21</ymin>\
+                   <xmax>40</xmax><ymax>60</ymax></bndbox></object>\n\
+                   <object><name>person</name><bndbox><xmin>1</xmin><ymin>1</ymin>\
+                   <xmax>100</xmax><ymax>100</ymax></bndbox></object>\n\
+                   <object>\n<name> face </name>\n<pose>Frontal</pose>\n<bndbox>\n<xmin>1</xmin>\
+                   <ymin>1</ymin><xmax>10</xmax><ymax>10</ymax></bndbox>\n</object>\n</annotation>";
+        let annotation = parse_voc(xml, Path::new("voc"), "face").unwrap();
+
+        assert!(annotation.image == Path::new("voc/party.jpg"));
+        assert!(annotation.faces == vec![(10., 20., 30., 40.), (0., 0., 10., 10.)]);
+        assert!(parse_voc("<annotation></annotation>", Path::new(""), "face").is_err());
+        assert!(parse_voc("<annotation><filename>a</name>", Path::new(""), "face").is_err());
+    }
+
+    #[test]
+    // Comments, CDATA, attributes and entities are read as XML
+    fn voc_is_parsed_as_xml() {
+        let xml = "<?xml version=\"1.0\"?>\n<annotation verified=\"yes\">\n\
+                   <!-- <filename>wrong.jpg</filename> -->\n\
+                   <filename><![CDATA[a&b.jpg]]></filename>\n\
+                   <object><name>f&#97;ce</name><!-- <name>face</name> -->\
+                   <bndbox><xmin>11</xmin><ymin>21</ymin><xmax>40</xmax>\
+                   <ymax><!-- 1 -->60</ymax></bndbox></object>\n\
+                   <object><name>person</name><bndbox><xmin>1</xmin><ymin>1</ymin>\
+                   <xmax>100</xmax><ymax>100</ymax></bndbox></object></annotation>";
+        let annotation = parse_voc(xml, Path::new("voc"), "face").unwrap();
+
+        assert!(annotation.image == Path::new("voc/a&b.jpg"));
+        assert!(annotation.faces == vec![(10., 20., 30., 40.)]);
+    }
+
+    #[test]
+    // FDDB's image lists sit next to the ellipse lists, and are left out
+    fn fddb_directories_read_ellipse_lists() {
+        let dir = test_dir("fddb-folds");
+        for fold in &["01", "02"] {
+            fs::write(
+                dir.join(format!("FDDB-fold-{}.txt", fold)),
+                format!("2002/08/11/big/img_{}\n", fold),
+            )
+            .unwrap();
+            fs::write(
+                dir.join(format!("FDDB-fold-{}-ellipseList.txt", fold)),
+                format!("2002/08/11/big/img_{}\n1\n20 10 0 50 40  1\n", fold),
+            )
+            .unwrap();
+        }
+
+        let options = ImportOptions::default();
+        let annotations =
+            read_annotations(AnnotationFormat::Fddb, &dir, Path::new("fddb"), &options).unwrap();
+        assert!(annotations.len() == 2);
+        assert!(annotations[1].image == Path::new("fddb/2002/08/11/big/img_02.jpg"));
+
+        // A single file is read whatever its name
+        let fold = dir.join("FDDB-fold-01.txt");
+        assert!(read_annotations(AnnotationFormat::Fddb, &fold, Path::new(""), &options).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    // A 2x4 box at the corner of an image squares up to 4x4 and replicates the edge
+    fn faces_are_squared_padded_and_resized() {
+        let img = Matrix::from_shape_fn((6, 6), |(y, x)| (10 * y + x) as i64);
+        let options = ImportOptions {
+            window_size: 4,
+            padding: 0.,
+            filter: ResizeFilter::Nearest,
+            ..ImportOptions::default()
+        };
+
+        let window = crop_face(&img, (0., 0., 2., 4.), &options);
+        assert!(
+            window
+                == array![
+                    [0, 0, 1, 2],
+                    [10, 10, 11, 12],
+                    [20, 20, 21, 22],
+                    [30, 30, 31, 32]
+                ]
+        );
+
+        let annotations = vec![Annotation {
+            image: PathBuf::from("missing.jpg"),
+            faces: vec![(0., 0., 30., 30.)],
+        }];
+        let (windows, report) = import_faces(&annotations, &options);
+        assert!(windows.is_empty() && report.failed.len() == 1);
+    }
+}
@@ -0,0 +1,238 @@
+/// Decision trees over Haar features, grown from decision stumps.
+use serde::{Deserialize, Serialize};
+
+use super::feature::AnyFeature;
+
+type WeakClassifier = super::weak_classifier::WeakClassifier;
+type IntegralImages = super::IntegralImages;
+type Classification = super::Classification;
+type IntegralView<'a> = super::IntegralView<'a>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Node {
+    /// Samples the stump classifies as faces go down the `face` branch.
+    Split {
+        stump: WeakClassifier,
+        face: Box<Node>,
+        non_face: Box<Node>,
+    },
+    /// The weighted mean label (in [-1, 1]) of the training samples reaching the leaf.
+    Leaf(f64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecisionTree {
+    depth: usize,
+    root: Node,
+}
+
+impl DecisionTree {
+    /// Grows a tree of at most `depth` splits from the root to any leaf, choosing
+    /// the best stump for the samples reaching each node. Returns the tree and its
+    /// weighted error.
+    pub fn train(
+        depth: usize,
+        features: &Vec<AnyFeature>,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
+    ) -> (DecisionTree, f64) {
+        assert!(depth >= 1);
+
+        let root = Self::grow(
+            depth,
+            features,
+            training_samples,
+            distribution_t.to_vec(),
+            Classification::Face,
+        );
+        let tree = DecisionTree { depth, root };
+
+        let mut error = 0.;
+        for ((sample, label), dist) in training_samples.iter().zip(distribution_t.iter()) {
+            if tree.evaluate(&sample.view()) != *label {
+                error += dist;
+            }
+        }
+
+        (tree, error)
+    }
+
+    /// Samples that don't reach the node have a weight of zero in `distribution`,
+    /// which lets every node reuse the stump search over the full training set.
+    fn grow(
+        depth: usize,
+        features: &Vec<AnyFeature>,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution: Vec<f64>,
+        side: Classification,
+    ) -> Node {
+        let mut w_pos = 0.;
+        let mut w_neg = 0.;
+        for ((_, label), dist) in training_samples.iter().zip(distribution.iter()) {
+            match label {
+                Classification::Face => w_pos += dist,
+                Classification::NonFace => w_neg += dist,
+            }
+        }
+
+        // Nothing reached this node, so fall back to the decision of the parent
+        if w_pos + w_neg == 0. {
+            return Node::Leaf(side * 1.);
+        }
+        if depth == 0 || w_pos == 0. || w_neg == 0. {
+            return Node::Leaf((w_pos - w_neg) / (w_pos + w_neg));
+        }
+
+        let (stump, _) = WeakClassifier::best_stump(features, training_samples, &distribution);
+
+        let mut face_distribution = distribution.clone();
+        let mut non_face_distribution = distribution;
+        for (i, (sample, _)) in training_samples.iter().enumerate() {
+            match stump.evaluate(&sample.view()) {
+                Classification::Face => non_face_distribution[i] = 0.,
+                Classification::NonFace => face_distribution[i] = 0.,
+            }
+        }
+
+        Node::Split {
+            stump,
+            face: Box::new(Self::grow(
+                depth - 1,
+                features,
+                training_samples,
+                face_distribution,
+                Classification::Face,
+            )),
+            non_face: Box::new(Self::grow(
+                depth - 1,
+                features,
+                training_samples,
+                non_face_distribution,
+                Classification::NonFace,
+            )),
+        }
+    }
+
+    /// A tree with the given splits, such as a loaded one.
+    pub fn from_parts(depth: usize, root: Node) -> DecisionTree {
+        DecisionTree { depth, root }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// The stumps at every split of the tree.
+    pub fn stumps(&self) -> Vec<&WeakClassifier> {
+        let mut stumps = Vec::new();
+        let mut nodes = vec![&self.root];
+        while let Some(node) = nodes.pop() {
+            if let Node::Split {
+                stump,
+                face,
+                non_face,
+            } = node
+            {
+                stumps.push(stump);
+                nodes.push(face);
+                nodes.push(non_face);
+            }
+        }
+
+        stumps
+    }
+
+    /// Evaluate the tree on an input image.
+    pub fn evaluate(&self, img: &IntegralView) -> Classification {
+        self.evaluate_scaled(img, 1.)
+    }
+
+    /// Evaluate the tree on a window `factor` times the training size.
+    pub fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> Classification {
+        if self.evaluate_raw_scaled(img, factor) >= 0. {
+            Classification::Face
+        } else {
+            Classification::NonFace
+        }
+    }
+
+    /// Return the value of the leaf the image ends up in.
+    pub fn evaluate_raw(&self, img: &IntegralView) -> f64 {
+        self.evaluate_raw_scaled(img, 1.)
+    }
+
+    /// Return the value of the leaf a window `factor` times the training size ends
+    /// up in.
+    pub fn evaluate_raw_scaled(&self, img: &IntegralView, factor: f64) -> f64 {
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Split {
+                    stump,
+                    face,
+                    non_face,
+                } => {
+                    node = match stump.evaluate_scaled(img, factor) {
+                        Classification::Face => face,
+                        Classification::NonFace => non_face,
+                    }
+                }
+                Node::Leaf(value) => return *value,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::{HaarFeature, HaarFeatureType};
+    use crate::Matrix;
+
+    #[test]
+    // Faces are images that are brighter on the right and on the top, which a single
+    // stump can't separate but a tree of depth 2 can.
+    fn depth_two_tree_separates_conjunction() {
+        let features = vec![
+            AnyFeature::Haar(HaarFeature::new(HaarFeatureType::TwoHorizontal, 2, 4, 0, 0)),
+            AnyFeature::Haar(HaarFeature::new(HaarFeatureType::TwoVertical, 4, 2, 0, 0)),
+        ];
+
+        let mut samples = Vec::new();
+        for &(right, top, label, copies) in &[
+            (false, false, Classification::NonFace, 1),
+            (true, false, Classification::NonFace, 1),
+            (false, true, Classification::NonFace, 1),
+            (true, true, Classification::Face, 2),
+        ] {
+            let mut img = Matrix::ones((4, 4));
+            for y in 0..4 {
+                for x in 0..4 {
+                    if right && x >= 2 {
+                        img[[y, x]] += 1;
+                    }
+                    if top && y < 2 {
+                        img[[y, x]] += 1;
+                    }
+                }
+            }
+            for _ in 0..copies {
+                samples.push((IntegralImages::from_image(&img), label));
+            }
+        }
+        let distribution = vec![1. / samples.len() as f64; samples.len()];
+
+        let (_, stump_error) = WeakClassifier::best_stump(&features, &samples, &distribution);
+        let (tree, tree_error) = DecisionTree::train(2, &features, &samples, &distribution);
+
+        assert!(stump_error > 0.);
+        assert!(tree_error == 0.);
+        for (sample, label) in &samples {
+            assert!(tree.evaluate(&sample.view()) == *label);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+/// The interface shared by every feature family, and the enum used to mix families
+/// within a single cascade.
+use super::features::HaarFeature;
+use super::lbp::MbLbpFeature;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+type IntegralView<'a> = super::IntegralView<'a>;
+
+pub trait Feature: Copy + Serialize + DeserializeOwned {
+    /// Evaluate the feature on the integral images of a window (in constant time).
+    fn evaluate(&self, img: &IntegralView) -> i64;
+
+    /// The same feature for a window `factor` times the size of the one it was
+    /// created for, with its geometry rounded to whole pixels.
+    fn scale(&self, factor: f64) -> Self;
+
+    /// Evaluate the feature scaled to a window `factor` times the size of the one
+    /// it was created for, on the integral images of that larger window. The
+    /// response is on the scale of the unscaled feature's, so thresholds learned on
+    /// training windows still apply.
+    fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> i64 {
+        self.scale(factor).evaluate(img)
+    }
+
+    /// The bounding box (x, y, w, h) of the feature within its window.
+    fn bounds(&self) -> (usize, usize, usize, usize);
+
+    /// A short human-readable description of the feature's geometry.
+    fn describe(&self) -> String;
+}
+
+/// Untagged so that cascades saved when stumps could only hold Haar features still
+/// load.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnyFeature {
+    /// Upright or tilted Haar features, whose responses are thresholded.
+    Haar(HaarFeature),
+    /// Multi-Block LBP features, whose responses are categorical codes.
+    Lbp(MbLbpFeature),
+}
+
+impl Feature for AnyFeature {
+    fn evaluate(&self, img: &IntegralView) -> i64 {
+        match self {
+            AnyFeature::Haar(feature) => Feature::evaluate(feature, img),
+            AnyFeature::Lbp(feature) => Feature::evaluate(feature, img),
+        }
+    }
+
+    fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> i64 {
+        match self {
+            AnyFeature::Haar(feature) => feature.evaluate_scaled(img, factor),
+            AnyFeature::Lbp(feature) => feature.evaluate_scaled(img, factor),
+        }
+    }
+
+    fn scale(&self, factor: f64) -> AnyFeature {
+        match self {
+            AnyFeature::Haar(feature) => AnyFeature::Haar(feature.scale(factor)),
+            AnyFeature::Lbp(feature) => AnyFeature::Lbp(feature.scale(factor)),
+        }
+    }
+
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        match self {
+            AnyFeature::Haar(feature) => feature.bounds(),
+            AnyFeature::Lbp(feature) => feature.bounds(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            AnyFeature::Haar(feature) => feature.describe(),
+            AnyFeature::Lbp(feature) => feature.describe(),
+        }
+    }
+}
+
+/// Round a scaled coordinate or length to the nearest pixel.
+pub fn scale_length(length: usize, factor: f64) -> usize {
+    (length as f64 * factor).round() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::HaarFeatureType;
+    use crate::preprocess::IntegralImages;
+    use ndarray::Array;
+
+    #[test]
+    // Checks that each family deserializes back into its own variant
+    fn features_round_trip() {
+        let haar = HaarFeature::new(HaarFeatureType::TiltedTwoVertical, 2, 3, 6, 1);
+        let lbp = MbLbpFeature::new(2, 3, 1, 4);
+
+        let json = serde_json::to_string(&haar).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            AnyFeature::Haar(feature) => assert!(feature.describe() == haar.describe()),
+            AnyFeature::Lbp(_) => panic!("Haar feature deserialized as an MB-LBP feature"),
+        }
+
+        let json = serde_json::to_string(&AnyFeature::Lbp(lbp)).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            AnyFeature::Haar(_) => panic!("MB-LBP feature deserialized as a Haar feature"),
+            AnyFeature::Lbp(feature) => assert!(feature.describe() == lbp.describe()),
+        }
+    }
+
+    #[test]
+    fn features_dispatch_correctly() {
+        let haar = HaarFeature::new(HaarFeatureType::TwoVertical, 2, 2, 1, 1);
+        let tilted = HaarFeature::new(HaarFeatureType::TiltedTwoVertical, 2, 3, 6, 1);
+        let lbp = MbLbpFeature::new(1, 2, 3, 0);
+
+        let img = IntegralImages::from_image(&Array::ones((12, 12)));
+        assert!(AnyFeature::Haar(haar).evaluate(&img.view()) == 0);
+        assert!(AnyFeature::Lbp(lbp).evaluate(&img.view()) == 255);
+
+        assert!(AnyFeature::Haar(haar).bounds() == (1, 1, 2, 4));
+        assert!(AnyFeature::Haar(tilted).bounds() == (0, 1, 8, 8));
+        assert!(AnyFeature::Lbp(lbp).bounds() == (3, 0, 3, 6));
+
+        assert!(AnyFeature::Haar(haar).scale(1.5).bounds() == (2, 2, 3, 6));
+        assert!(AnyFeature::Lbp(lbp).scale(2.).bounds() == (6, 0, 6, 12));
+    }
+}
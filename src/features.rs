@@ -1,10 +1,11 @@
 /// Haar Feature definitions and computation methods.
 /// Design is based on PistonDevelopers/imageproc.
-use super::util::{compute_area, Rectangle};
+use super::feature::{scale_length, Feature};
+use super::util::{Rectangle, TiltedRectangle};
 use serde::{Deserialize, Serialize};
 use std::ops::{Mul, Not};
 
-type MatrixView<'a> = ndarray::ArrayView2<'a, i64>;
+type IntegralView<'a> = super::IntegralView<'a>;
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct HaarFeature {
@@ -16,12 +17,91 @@ pub struct HaarFeature {
     y: usize,
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+/// The feature families of Lienhart & Maydt (edge, line, centre-surround) plus the
+/// diagonal feature of Viola & Jones. `w` and `h` are the size of a single cell of
+/// the feature; `extent` gives the number of cells it spans.
+///
+/// Tilted features are rotated by 45 degrees and evaluated on the tilted integral
+/// image. Their cells extend w pixels down and to the right ("horizontally") and h
+/// pixels down and to the left ("vertically") from a top corner at (x, y).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HaarFeatureType {
     TwoVertical,
     TwoHorizontal,
     ThreeHorizontal,
+    ThreeVertical,
+    /// A 1-2-1 line of cells, with the double-width centre of opposite sign.
+    FourHorizontal,
+    FourVertical,
     TwoByTwo,
+    /// A 3x3 block of cells with the centre of opposite sign to the surround.
+    CenterSurround,
+    TiltedTwoHorizontal,
+    TiltedTwoVertical,
+    TiltedThreeHorizontal,
+    TiltedThreeVertical,
+    TiltedFourHorizontal,
+    TiltedFourVertical,
+    TiltedCenterSurround,
+}
+
+impl HaarFeatureType {
+    pub const UPRIGHT: [HaarFeatureType; 8] = [
+        HaarFeatureType::TwoVertical,
+        HaarFeatureType::TwoHorizontal,
+        HaarFeatureType::ThreeHorizontal,
+        HaarFeatureType::ThreeVertical,
+        HaarFeatureType::FourHorizontal,
+        HaarFeatureType::FourVertical,
+        HaarFeatureType::TwoByTwo,
+        HaarFeatureType::CenterSurround,
+    ];
+
+    pub const TILTED: [HaarFeatureType; 7] = [
+        HaarFeatureType::TiltedTwoHorizontal,
+        HaarFeatureType::TiltedTwoVertical,
+        HaarFeatureType::TiltedThreeHorizontal,
+        HaarFeatureType::TiltedThreeVertical,
+        HaarFeatureType::TiltedFourHorizontal,
+        HaarFeatureType::TiltedFourVertical,
+        HaarFeatureType::TiltedCenterSurround,
+    ];
+
+    pub fn is_tilted(&self) -> bool {
+        HaarFeatureType::TILTED.contains(self)
+    }
+
+    /// The number of cells the feature spans horizontally and vertically.
+    pub fn extent(&self) -> (usize, usize) {
+        match self {
+            HaarFeatureType::TwoVertical => (1, 2),
+            HaarFeatureType::TwoHorizontal => (2, 1),
+            HaarFeatureType::ThreeHorizontal => (3, 1),
+            HaarFeatureType::ThreeVertical => (1, 3),
+            HaarFeatureType::FourHorizontal => (4, 1),
+            HaarFeatureType::FourVertical => (1, 4),
+            HaarFeatureType::TwoByTwo => (2, 2),
+            HaarFeatureType::CenterSurround => (3, 3),
+            HaarFeatureType::TiltedTwoHorizontal => (2, 1),
+            HaarFeatureType::TiltedTwoVertical => (1, 2),
+            HaarFeatureType::TiltedThreeHorizontal => (3, 1),
+            HaarFeatureType::TiltedThreeVertical => (1, 3),
+            HaarFeatureType::TiltedFourHorizontal => (4, 1),
+            HaarFeatureType::TiltedFourVertical => (1, 4),
+            HaarFeatureType::TiltedCenterSurround => (3, 3),
+        }
+    }
+
+    /// Whether a feature with w x h cells and its top-left (or, for tilted features,
+    /// top) corner at (x, y) fits within an integral image of size maxw x maxh.
+    pub fn fits(&self, w: usize, h: usize, x: usize, y: usize, maxw: usize, maxh: usize) -> bool {
+        let (cells_x, cells_y) = self.extent();
+        if self.is_tilted() {
+            x >= cells_y * h && x + cells_x * w < maxw && y + cells_x * w + cells_y * h < maxh
+        } else {
+            x + cells_x * w < maxw && y + cells_y * h < maxh
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -67,7 +147,18 @@ impl HaarFeature {
             HaarFeatureType::TwoVertical => Sign::Positive,
             HaarFeatureType::TwoHorizontal => Sign::Negative,
             HaarFeatureType::ThreeHorizontal => Sign::Negative,
+            HaarFeatureType::ThreeVertical => Sign::Negative,
+            HaarFeatureType::FourHorizontal => Sign::Negative,
+            HaarFeatureType::FourVertical => Sign::Negative,
             HaarFeatureType::TwoByTwo => Sign::Negative,
+            HaarFeatureType::CenterSurround => Sign::Negative,
+            HaarFeatureType::TiltedTwoHorizontal => Sign::Negative,
+            HaarFeatureType::TiltedTwoVertical => Sign::Positive,
+            HaarFeatureType::TiltedThreeHorizontal => Sign::Negative,
+            HaarFeatureType::TiltedThreeVertical => Sign::Negative,
+            HaarFeatureType::TiltedFourHorizontal => Sign::Negative,
+            HaarFeatureType::TiltedFourVertical => Sign::Negative,
+            HaarFeatureType::TiltedCenterSurround => Sign::Negative,
         };
         HaarFeature {
             feature_type: feature_type,
@@ -81,19 +172,168 @@ impl HaarFeature {
 
     /// Evaluate the Haar feature on the integral image (in constant time).
     /// Bound are assumed to be correct.
-    pub fn evaluate(&self, img: &MatrixView) -> i64 {
-        let rects = self.to_rectangles();
+    pub fn evaluate(&self, img: &IntegralView) -> i64 {
         let mut score = 0;
 
-        for (rect, sgn) in rects {
-            score += sgn * compute_area(img, &rect);
+        if self.feature_type.is_tilted() {
+            for (rect, sgn) in self.tilted_rectangles() {
+                score += sgn * img.tilted.compute_tilted_area(&rect);
+            }
+        } else {
+            for (rect, sgn) in self.to_rectangles() {
+                score += sgn * img.upright.compute_area(&rect);
+            }
         }
 
         score
     }
 
+    /// The signed number of times pixel (px, py) of the window is summed by the
+    /// feature (the centre of centre-surround features is summed twice).
+    pub fn pixel_weight(&self, px: usize, py: usize) -> i64 {
+        if self.feature_type.is_tilted() {
+            self.tilted_rectangles()
+                .iter()
+                .filter(|(rect, _)| rect.contains(px, py))
+                .map(|(_, sign)| *sign * 1)
+                .sum()
+        } else {
+            self.to_rectangles()
+                .iter()
+                .filter(|(rect, _)| rect.contains(px, py))
+                .map(|(_, sign)| *sign * 1)
+                .sum()
+        }
+    }
+
+    /// The corners of each rectangle the feature sums, clockwise from the top-left
+    /// (or for tilted rectangles, the top), with the sign it's summed with.
+    pub fn polygons(&self) -> Vec<([(usize, usize); 4], Sign)> {
+        if self.feature_type.is_tilted() {
+            self.tilted_rectangles()
+                .into_iter()
+                .map(|(r, sign)| {
+                    (
+                        [
+                            (r.x, r.y),
+                            (r.x + r.w, r.y + r.w),
+                            (r.x + r.w - r.h, r.y + r.w + r.h),
+                            (r.x - r.h, r.y + r.h),
+                        ],
+                        sign,
+                    )
+                })
+                .collect()
+        } else {
+            self.to_rectangles()
+                .into_iter()
+                .map(|(r, sign)| {
+                    (
+                        [
+                            (r.xmin, r.ymin),
+                            (r.xmax, r.ymin),
+                            (r.xmax, r.ymax),
+                            (r.xmin, r.ymax),
+                        ],
+                        sign,
+                    )
+                })
+                .collect()
+        }
+    }
+
+    pub fn feature_type(&self) -> HaarFeatureType {
+        self.feature_type
+    }
+
+    /// The cell size and corner (w, h, x, y), as passed to `HaarFeature::new`.
+    pub fn geometry(&self) -> (usize, usize, usize, usize) {
+        (self.w, self.h, self.x, self.y)
+    }
+
+    pub fn is_tilted(&self) -> bool {
+        self.feature_type.is_tilted()
+    }
+
+    /// Each rectangle the feature sums as (x, y, w, h), with its sign. Tilted
+    /// rectangles have their top corner at (x, y).
+    pub fn rects(&self) -> Vec<((usize, usize, usize, usize), Sign)> {
+        if self.feature_type.is_tilted() {
+            self.tilted_rectangles()
+                .into_iter()
+                .map(|(r, sign)| ((r.x, r.y, r.w, r.h), sign))
+                .collect()
+        } else {
+            self.to_rectangles()
+                .into_iter()
+                .map(|(r, sign)| ((r.xmin, r.ymin, r.xmax - r.xmin, r.ymax - r.ymin), sign))
+                .collect()
+        }
+    }
+
+    /// Turn the cells of a tilted feature into rotated rectangles
+    fn tilted_rectangles(&self) -> Vec<(TiltedRectangle, Sign)> {
+        // (first cell along w, first cell along h, cells along w, cells along h, sign)
+        let tl = self.tl_sign;
+        let blocks = match &self.feature_type {
+            HaarFeatureType::TiltedTwoHorizontal => vec![(0, 0, 1, 1, tl), (1, 0, 1, 1, !tl)],
+            HaarFeatureType::TiltedTwoVertical => vec![(0, 0, 1, 1, tl), (0, 1, 1, 1, !tl)],
+            HaarFeatureType::TiltedThreeHorizontal => {
+                vec![(0, 0, 1, 1, tl), (1, 0, 1, 1, !tl), (2, 0, 1, 1, tl)]
+            }
+            HaarFeatureType::TiltedThreeVertical => {
+                vec![(0, 0, 1, 1, tl), (0, 1, 1, 1, !tl), (0, 2, 1, 1, tl)]
+            }
+            HaarFeatureType::TiltedFourHorizontal => {
+                vec![(0, 0, 1, 1, tl), (1, 0, 2, 1, !tl), (3, 0, 1, 1, tl)]
+            }
+            HaarFeatureType::TiltedFourVertical => {
+                vec![(0, 0, 1, 1, tl), (0, 1, 1, 2, !tl), (0, 3, 1, 1, tl)]
+            }
+            // As with the upright version, the whole block minus twice the centre
+            HaarFeatureType::TiltedCenterSurround => {
+                vec![(0, 0, 3, 3, tl), (1, 1, 1, 1, !tl), (1, 1, 1, 1, !tl)]
+            }
+            _ => unreachable!("Upright features have no tilted rectangles"),
+        };
+
+        blocks
+            .into_iter()
+            .map(|(i, j, cells_w, cells_h, sign)| {
+                (
+                    TiltedRectangle::new(
+                        self.x + i * self.w - j * self.h,
+                        self.y + i * self.w + j * self.h,
+                        cells_w * self.w,
+                        cells_h * self.h,
+                    ),
+                    sign,
+                )
+            })
+            .collect()
+    }
+
     /// Turn width-height into rectangle
     fn to_rectangles(&self) -> Vec<(Rectangle, Sign)> {
+        // The centre-surround feature is the whole block minus twice the centre,
+        // which leaves the surround and the centre with opposite signs.
+        if self.feature_type == HaarFeatureType::CenterSurround {
+            let centre = || {
+                Rectangle::new(
+                    (self.x + self.w, self.y + self.h),
+                    (self.x + 2 * self.w, self.y + 2 * self.h),
+                )
+            };
+            return vec![
+                (
+                    Rectangle::new((self.x, self.y), (self.x + 3 * self.w, self.y + 3 * self.h)),
+                    self.tl_sign,
+                ),
+                (centre(), !self.tl_sign),
+                (centre(), !self.tl_sign),
+            ];
+        }
+
         let mut rects = vec![(
             Rectangle::new((self.x, self.y), (self.x + self.w, self.y + self.h)),
             self.tl_sign,
@@ -134,6 +374,54 @@ impl HaarFeature {
                     self.tl_sign,
                 ));
             }
+            HaarFeatureType::ThreeVertical => {
+                rects.push((
+                    Rectangle::new(
+                        (self.x, self.y + self.h),
+                        (self.x + self.w, self.y + 2 * self.h),
+                    ),
+                    !self.tl_sign,
+                ));
+                rects.push((
+                    Rectangle::new(
+                        (self.x, self.y + 2 * self.h),
+                        (self.x + self.w, self.y + 3 * self.h),
+                    ),
+                    self.tl_sign,
+                ));
+            }
+            HaarFeatureType::FourHorizontal => {
+                rects.push((
+                    Rectangle::new(
+                        (self.x + self.w, self.y),
+                        (self.x + 3 * self.w, self.y + self.h),
+                    ),
+                    !self.tl_sign,
+                ));
+                rects.push((
+                    Rectangle::new(
+                        (self.x + 3 * self.w, self.y),
+                        (self.x + 4 * self.w, self.y + self.h),
+                    ),
+                    self.tl_sign,
+                ));
+            }
+            HaarFeatureType::FourVertical => {
+                rects.push((
+                    Rectangle::new(
+                        (self.x, self.y + self.h),
+                        (self.x + self.w, self.y + 3 * self.h),
+                    ),
+                    !self.tl_sign,
+                ));
+                rects.push((
+                    Rectangle::new(
+                        (self.x, self.y + 3 * self.h),
+                        (self.x + self.w, self.y + 4 * self.h),
+                    ),
+                    self.tl_sign,
+                ));
+            }
             HaarFeatureType::TwoByTwo => {
                 rects.push((
                     Rectangle::new(
@@ -157,12 +445,72 @@ impl HaarFeature {
                     self.tl_sign,
                 ));
             }
+            _ => unreachable!("Tilted features have no upright rectangles"),
         }
 
         rects
     }
 }
 
+impl Feature for HaarFeature {
+    fn evaluate(&self, img: &IntegralView) -> i64 {
+        HaarFeature::evaluate(self, img)
+    }
+
+    /// Every cell keeps the same size, so the cells still balance each other. Cells
+    /// are shrunk where rounding would otherwise push the feature past the scaled
+    /// edges of the original, which keeps it within the scaled window.
+    fn scale(&self, factor: f64) -> HaarFeature {
+        let (cells_x, cells_y) = self.feature_type.extent();
+        let x = scale_length(self.x, factor);
+        let y = scale_length(self.y, factor);
+        let right = scale_length(self.x + cells_x * self.w, factor);
+        let w = scale_length(self.w, factor).min(right.saturating_sub(x) / cells_x);
+        let mut h = scale_length(self.h, factor);
+
+        if self.feature_type.is_tilted() {
+            let bottom = scale_length(self.y + cells_x * self.w + cells_y * self.h, factor);
+            h = h
+                .min(x / cells_y)
+                .min(bottom.saturating_sub(y + cells_x * w) / cells_y);
+        } else {
+            let bottom = scale_length(self.y + cells_y * self.h, factor);
+            h = h.min(bottom.saturating_sub(y) / cells_y);
+        }
+
+        HaarFeature::new(self.feature_type, w.max(1), h.max(1), x, y)
+    }
+
+    /// The response of the scaled feature, multiplied by the ratio of the original
+    /// cell area to the rounded scaled one.
+    fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> i64 {
+        if factor == 1. {
+            return HaarFeature::evaluate(self, img);
+        }
+
+        let scaled = self.scale(factor);
+        let area_ratio = (self.w * self.h) as f64 / (scaled.w * scaled.h) as f64;
+        (HaarFeature::evaluate(&scaled, img) as f64 * area_ratio).round() as i64
+    }
+
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        let (cells_x, cells_y) = self.feature_type.extent();
+        let (w, h) = (cells_x * self.w, cells_y * self.h);
+        if self.feature_type.is_tilted() {
+            (self.x - h, self.y, w + h, w + h)
+        } else {
+            (self.x, self.y, w, h)
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{:?} with {}x{} cells at ({}, {})",
+            self.feature_type, self.w, self.h, self.x, self.y
+        )
+    }
+}
+
 /// Create a set of features that can be applies to every training input image.
 /// Assumes that all training examples are the same shape.
 pub fn init_haar_features(
@@ -170,47 +518,68 @@ pub fn init_haar_features(
     maxh: usize,
     stride: usize,
     step: usize,
+) -> Vec<HaarFeature> {
+    init_haar_feature_families(
+        maxw,
+        maxh,
+        stride,
+        step,
+        &[
+            (HaarFeatureType::TwoHorizontal, None),
+            (HaarFeatureType::TwoVertical, None),
+        ],
+    )
+}
+
+/// Create the features of the given families, each paired with an optional cap on
+/// the number of features kept from it. Capped families are subsampled evenly
+/// across sizes and positions.
+pub fn init_haar_feature_families(
+    maxw: usize,
+    maxh: usize,
+    stride: usize,
+    step: usize,
+    families: &[(HaarFeatureType, Option<usize>)],
 ) -> Vec<HaarFeature> {
     let mut haar_features = Vec::new();
     for w in (1..=maxw).step_by(step) {
         for h in (1..=maxh).step_by(step) {
             for x in (0..(maxw - w)).step_by(stride) {
                 for y in (0..(maxh - h)).step_by(stride) {
-                    if x + 2 * w < maxw {
-                        haar_features.push(HaarFeature::new(
-                            HaarFeatureType::TwoHorizontal,
-                            w,
-                            h,
-                            x,
-                            y,
-                        ));
+                    for (feature_type, _) in families {
+                        if feature_type.fits(w, h, x, y, maxw, maxh) {
+                            haar_features.push(HaarFeature::new(*feature_type, w, h, x, y));
+                        }
                     }
-                    if y + 2 * h < maxh {
-                        haar_features.push(HaarFeature::new(
-                            HaarFeatureType::TwoVertical,
-                            w,
-                            h,
-                            x,
-                            y,
-                        ));
-                    }
-                    // if x + 3 * w < maxw {
-                    //     haar_features.push(HaarFeature::new(
-                    //         HaarFeatureType::ThreeHorizontal,
-                    //         w,
-                    //         h,
-                    //         x,
-                    //         y,
-                    //     ));
-                    // }
-                    // if x + 2 * w < maxw && y + 2 * h < maxh {
-                    //     haar_features.push(HaarFeature::new(HaarFeatureType::TwoByTwo, w, h, x, y));
-                    // }
                 }
             }
         }
     }
 
+    for (feature_type, max_count) in families {
+        if let Some(max_count) = max_count {
+            let total = haar_features
+                .iter()
+                .filter(|f| f.feature_type == *feature_type)
+                .count();
+            if total <= *max_count {
+                continue;
+            }
+
+            // Keep the i-th feature of the family whenever i * max_count / total
+            // steps up, which keeps exactly max_count of them.
+            let mut i = 0;
+            haar_features.retain(|f| {
+                if f.feature_type != *feature_type {
+                    return true;
+                }
+                let keep = (i + 1) * max_count / total > i * max_count / total;
+                i += 1;
+                keep
+            });
+        }
+    }
+
     println!("Starting with {} Haar features", haar_features.len());
     haar_features
 }
@@ -219,7 +588,8 @@ pub fn init_haar_features(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::preprocess::compute_integral_image;
+    use crate::preprocess::IntegralImages;
+    use crate::Matrix;
     use ndarray::Array;
 
     #[test]
@@ -227,7 +597,7 @@ mod tests {
         let two_vert1 = HaarFeature::new(HaarFeatureType::TwoVertical, 1, 1, 0, 2);
         let two_vert2 = HaarFeature::new(HaarFeatureType::TwoVertical, 2, 2, 0, 0);
 
-        let m1 = compute_integral_image(&Array::ones((4, 4)));
+        let m1 = IntegralImages::from_image(&Array::ones((4, 4)));
         assert!(two_vert1.evaluate(&m1.view()) == 0);
         assert!(two_vert2.evaluate(&m1.view()) == 0);
 
@@ -237,7 +607,7 @@ mod tests {
                 m2[[y, x]] = -1;
             }
         }
-        let m2 = compute_integral_image(&m2);
+        let m2 = IntegralImages::from_image(&m2);
         assert!(two_vert1.evaluate(&m2.view()) == 0);
         assert!(two_vert2.evaluate(&m2.view()) == 8);
     }
@@ -248,7 +618,7 @@ mod tests {
         let two_horiz2 = HaarFeature::new(HaarFeatureType::TwoHorizontal, 2, 2, 0, 0);
         let two_horiz3 = HaarFeature::new(HaarFeatureType::TwoHorizontal, 1, 1, 1, 0);
 
-        let m1 = compute_integral_image(&Array::ones((4, 4)));
+        let m1 = IntegralImages::from_image(&Array::ones((4, 4)));
         assert!(two_horiz1.evaluate(&m1.view()) == 0);
         assert!(two_horiz2.evaluate(&m1.view()) == 0);
         assert!(two_horiz3.evaluate(&m1.view()) == 0);
@@ -260,7 +630,7 @@ mod tests {
             }
         }
 
-        let m2 = compute_integral_image(&m2);
+        let m2 = IntegralImages::from_image(&m2);
         assert!(two_horiz1.evaluate(&m2.view()) == 0);
         assert!(two_horiz2.evaluate(&m2.view()) == -8);
         assert!(two_horiz3.evaluate(&m2.view()) == -2);
@@ -274,7 +644,7 @@ mod tests {
         let three_horiz4 = HaarFeature::new(HaarFeatureType::ThreeHorizontal, 1, 2, 0, 0);
         let three_horiz5 = HaarFeature::new(HaarFeatureType::ThreeHorizontal, 2, 2, 0, 3);
 
-        let m1 = compute_integral_image(&Array::ones((4, 4)));
+        let m1 = IntegralImages::from_image(&Array::ones((4, 4)));
         assert!(three_horiz1.evaluate(&m1.view()) == -1);
         assert!(three_horiz2.evaluate(&m1.view()) == -1);
 
@@ -285,7 +655,7 @@ mod tests {
             }
         }
 
-        let m2 = compute_integral_image(&m2);
+        let m2 = IntegralImages::from_image(&m2);
         assert!(three_horiz1.evaluate(&m2.view()) == 1);
         assert!(three_horiz2.evaluate(&m2.view()) == -1);
         assert!(three_horiz3.evaluate(&m2.view()) == 0);
@@ -301,7 +671,7 @@ mod tests {
         let two_by_two4 = HaarFeature::new(HaarFeatureType::TwoByTwo, 1, 2, 0, 0);
         let two_by_two5 = HaarFeature::new(HaarFeatureType::TwoByTwo, 2, 2, 2, 2);
 
-        let m1 = compute_integral_image(&Array::ones((4, 4)));
+        let m1 = IntegralImages::from_image(&Array::ones((4, 4)));
         assert!(two_by_two1.evaluate(&m1.view()) == 0);
         assert!(two_by_two2.evaluate(&m1.view()) == 0);
 
@@ -312,11 +682,276 @@ mod tests {
             }
         }
 
-        let m2 = compute_integral_image(&m2);
+        let m2 = IntegralImages::from_image(&m2);
         assert!(two_by_two1.evaluate(&m2.view()) == 0);
         assert!(two_by_two2.evaluate(&m2.view()) == 0);
         assert!(two_by_two3.evaluate(&m2.view()) == 0);
         assert!(two_by_two4.evaluate(&m2.view()) == 0);
         assert!(two_by_two5.evaluate(&m2.view()) == 0);
     }
+
+    #[test]
+    fn three_vert_evaluates_correctly() {
+        let three_vert1 = HaarFeature::new(HaarFeatureType::ThreeVertical, 1, 1, 1, 1);
+        let three_vert2 = HaarFeature::new(HaarFeatureType::ThreeVertical, 1, 1, 0, 0);
+        let three_vert3 = HaarFeature::new(HaarFeatureType::ThreeVertical, 1, 2, 0, 0);
+        let three_vert4 = HaarFeature::new(HaarFeatureType::ThreeVertical, 2, 1, 0, 0);
+        let three_vert5 = HaarFeature::new(HaarFeatureType::ThreeVertical, 2, 2, 3, 0);
+
+        let m1 = IntegralImages::from_image(&Array::ones((4, 4)));
+        assert!(three_vert1.evaluate(&m1.view()) == -1);
+        assert!(three_vert2.evaluate(&m1.view()) == -1);
+
+        let mut m2 = Array::ones((6, 6));
+        for y in 3..6 {
+            for x in 0..6 {
+                m2[[y, x]] = -1;
+            }
+        }
+
+        let m2 = IntegralImages::from_image(&m2);
+        assert!(three_vert1.evaluate(&m2.view()) == 1);
+        assert!(three_vert2.evaluate(&m2.view()) == -1);
+        assert!(three_vert3.evaluate(&m2.view()) == 0);
+        assert!(three_vert4.evaluate(&m2.view()) == -2);
+        assert!(three_vert5.evaluate(&m2.view()) == 0);
+    }
+
+    #[test]
+    fn four_horiz_evaluates_correctly() {
+        let four_horiz1 = HaarFeature::new(HaarFeatureType::FourHorizontal, 1, 1, 0, 0);
+        let four_horiz2 = HaarFeature::new(HaarFeatureType::FourHorizontal, 1, 2, 0, 1);
+        let four_horiz3 = HaarFeature::new(HaarFeatureType::FourHorizontal, 1, 1, 1, 0);
+
+        let m1 = IntegralImages::from_image(&Array::ones((4, 5)));
+        assert!(four_horiz1.evaluate(&m1.view()) == 0);
+        assert!(four_horiz2.evaluate(&m1.view()) == 0);
+        assert!(four_horiz3.evaluate(&m1.view()) == 0);
+
+        let mut m2 = Array::ones((4, 5));
+        for y in 0..4 {
+            for x in 1..3 {
+                m2[[y, x]] = 3;
+            }
+        }
+
+        let m2 = IntegralImages::from_image(&m2);
+        assert!(four_horiz1.evaluate(&m2.view()) == 4);
+        assert!(four_horiz2.evaluate(&m2.view()) == 8);
+        assert!(four_horiz3.evaluate(&m2.view()) == 0);
+    }
+
+    #[test]
+    fn four_vert_evaluates_correctly() {
+        let four_vert1 = HaarFeature::new(HaarFeatureType::FourVertical, 1, 1, 0, 0);
+        let four_vert2 = HaarFeature::new(HaarFeatureType::FourVertical, 2, 1, 1, 0);
+        let four_vert3 = HaarFeature::new(HaarFeatureType::FourVertical, 1, 1, 0, 1);
+
+        let m1 = IntegralImages::from_image(&Array::ones((5, 4)));
+        assert!(four_vert1.evaluate(&m1.view()) == 0);
+        assert!(four_vert2.evaluate(&m1.view()) == 0);
+        assert!(four_vert3.evaluate(&m1.view()) == 0);
+
+        let mut m2 = Array::ones((5, 4));
+        for y in 1..3 {
+            for x in 0..4 {
+                m2[[y, x]] = 3;
+            }
+        }
+
+        let m2 = IntegralImages::from_image(&m2);
+        assert!(four_vert1.evaluate(&m2.view()) == 4);
+        assert!(four_vert2.evaluate(&m2.view()) == 8);
+        assert!(four_vert3.evaluate(&m2.view()) == 0);
+    }
+
+    #[test]
+    fn center_surround_evaluates_correctly() {
+        let center_surround1 = HaarFeature::new(HaarFeatureType::CenterSurround, 1, 1, 0, 0);
+        let center_surround2 = HaarFeature::new(HaarFeatureType::CenterSurround, 2, 2, 0, 0);
+
+        let m1 = IntegralImages::from_image(&Array::ones((6, 6)));
+        assert!(center_surround1.evaluate(&m1.view()) == -7);
+        assert!(center_surround2.evaluate(&m1.view()) == -28);
+
+        let mut m2 = Array::ones((6, 6));
+        for y in 2..4 {
+            for x in 2..4 {
+                m2[[y, x]] = 8;
+            }
+        }
+
+        let m2 = IntegralImages::from_image(&m2);
+        assert!(center_surround1.evaluate(&m2.view()) == -14);
+        assert!(center_surround2.evaluate(&m2.view()) == 0);
+    }
+
+    #[test]
+    // Checks that every feature fits in the image and that family caps are respected
+    fn feature_families_initialized_correctly() {
+        let families: Vec<(HaarFeatureType, Option<usize>)> = HaarFeatureType::UPRIGHT
+            .iter()
+            .chain(HaarFeatureType::TILTED.iter())
+            .map(|feature_type| (*feature_type, None))
+            .collect();
+        let all = init_haar_feature_families(25, 25, 2, 2, &families);
+
+        for (feature_type, _) in &families {
+            assert!(all.iter().any(|f| f.feature_type == *feature_type));
+        }
+        let m = IntegralImages::from_image(&Array::ones((24, 24)));
+        for feature in &all {
+            feature.evaluate(&m.view());
+        }
+
+        let capped = init_haar_feature_families(
+            25,
+            25,
+            2,
+            2,
+            &[
+                (HaarFeatureType::TwoByTwo, Some(10)),
+                (HaarFeatureType::CenterSurround, None),
+            ],
+        );
+        let count = |features: &Vec<HaarFeature>, feature_type| {
+            features
+                .iter()
+                .filter(|f| f.feature_type == feature_type)
+                .count()
+        };
+        assert!(count(&capped, HaarFeatureType::TwoByTwo) == 10);
+        assert!(
+            count(&capped, HaarFeatureType::CenterSurround)
+                == count(&all, HaarFeatureType::CenterSurround)
+        );
+    }
+
+    #[test]
+    // Uses an image whose pixels equal their column, so that a unit tilted cell with
+    // its top corner at (x, y) sums to 2 * (x - 1)
+    fn tilted_features_evaluate_correctly() {
+        let tilted = |feature_type, x, y| HaarFeature::new(feature_type, 1, 1, x, y);
+
+        let mut m = Array::zeros((8, 8));
+        for y in 0..8 {
+            for x in 0..8 {
+                m[[y, x]] = x as i64;
+            }
+        }
+        let m = IntegralImages::from_image(&m);
+        let ones = IntegralImages::from_image(&Array::ones((8, 8)));
+
+        let two_horiz = tilted(HaarFeatureType::TiltedTwoHorizontal, 2, 0);
+        assert!(two_horiz.evaluate(&m.view()) == 2);
+        assert!(two_horiz.evaluate(&ones.view()) == 0);
+
+        let two_vert = tilted(HaarFeatureType::TiltedTwoVertical, 2, 0);
+        assert!(two_vert.evaluate(&m.view()) == 2);
+        assert!(two_vert.evaluate(&ones.view()) == 0);
+
+        let three_horiz = tilted(HaarFeatureType::TiltedThreeHorizontal, 2, 0);
+        assert!(three_horiz.evaluate(&m.view()) == -4);
+        assert!(three_horiz.evaluate(&ones.view()) == -2);
+
+        let three_vert = tilted(HaarFeatureType::TiltedThreeVertical, 3, 0);
+        assert!(three_vert.evaluate(&m.view()) == -2);
+        assert!(three_vert.evaluate(&ones.view()) == -2);
+
+        let four_horiz = tilted(HaarFeatureType::TiltedFourHorizontal, 2, 0);
+        assert!(four_horiz.evaluate(&m.view()) == 0);
+        assert!(four_horiz.evaluate(&ones.view()) == 0);
+
+        let four_vert = tilted(HaarFeatureType::TiltedFourVertical, 4, 0);
+        assert!(four_vert.evaluate(&m.view()) == 0);
+        assert!(four_vert.evaluate(&ones.view()) == 0);
+
+        let center_surround = tilted(HaarFeatureType::TiltedCenterSurround, 3, 0);
+        assert!(center_surround.evaluate(&m.view()) == -28);
+        assert!(center_surround.evaluate(&ones.view()) == -14);
+    }
+
+    /// Upsample an image by an integer factor, repeating each pixel in a
+    /// factor x factor block.
+    fn upsample(m: &Matrix, factor: usize) -> Matrix {
+        let (rows, cols) = m.dim();
+        Array::from_shape_fn((rows * factor, cols * factor), |(y, x)| {
+            m[[y / factor, x / factor]]
+        })
+    }
+
+    #[test]
+    // Pixel replication scales every upright cell sum by exactly factor^2, so the
+    // compensated responses on the upsampled image match the originals
+    fn scaled_features_match_upsampled_image() {
+        let m = Array::from_shape_fn((12, 12), |(y, x)| ((7 * x + 3 * y * y + x * y) % 11) as i64);
+        let img = IntegralImages::from_image(&m);
+
+        for &feature_type in HaarFeatureType::UPRIGHT.iter() {
+            let feature = HaarFeature::new(feature_type, 2, 1, 1, 2);
+            let response = feature.evaluate(&img.view());
+            assert!(response != 0);
+
+            for &factor in &[2, 3] {
+                let upsampled = IntegralImages::from_image(&upsample(&m, factor));
+                let scaled = Feature::evaluate_scaled(&feature, &upsampled.view(), factor as f64);
+                assert!(scaled == response, "{:?} at {}x", feature_type, factor);
+            }
+        }
+    }
+
+    #[test]
+    // A linear ramp resampled by 1.5 (at pixel centres) is another linear ramp, 1.5
+    // times shallower
+    fn scaled_features_match_resampled_ramp() {
+        let m = Array::from_shape_fn((20, 20), |(y, x)| (6 * x + 12 * y) as i64);
+        let resized = Array::from_shape_fn((30, 30), |(y, x)| (4 * x + 8 * y) as i64 - 3);
+        let (m, resized) = (
+            IntegralImages::from_image(&m),
+            IntegralImages::from_image(&resized),
+        );
+
+        for &feature_type in HaarFeatureType::UPRIGHT.iter() {
+            let feature = HaarFeature::new(feature_type, 2, 2, 2, 4);
+            let response = feature.evaluate(&m.view());
+            let scaled = Feature::evaluate_scaled(&feature, &resized.view(), 1.5);
+            assert!(scaled == response, "{:?}", feature_type);
+        }
+
+        // Discrete tilted cells aren't exact under scaling, but stay close
+        for &feature_type in HaarFeatureType::TILTED.iter() {
+            let feature = HaarFeature::new(feature_type, 2, 2, 8, 0);
+            let response = feature.evaluate(&m.view()) as f64;
+            let scaled = Feature::evaluate_scaled(&feature, &resized.view(), 1.5) as f64;
+            assert!(
+                (scaled - response).abs() <= 0.1 * response.abs().max(100.),
+                "{:?}: {} vs {}",
+                feature_type,
+                scaled,
+                response
+            );
+        }
+    }
+
+    #[test]
+    // Rounding must shrink cells rather than let the feature leave the scaled window
+    fn scaled_features_stay_within_scaled_window() {
+        for &feature_type in HaarFeatureType::UPRIGHT
+            .iter()
+            .chain(HaarFeatureType::TILTED.iter())
+        {
+            for &factor in &[1.1, 1.25, 1.5, 1.75, 2.5] {
+                for &(w, h, x, y) in &[(1, 1, 3, 0), (3, 2, 3, 5), (5, 3, 9, 1)] {
+                    let feature = HaarFeature::new(feature_type, w, h, x, y);
+                    if !feature_type.fits(w, h, x, y, 24, 24) {
+                        continue;
+                    }
+
+                    let side = scale_length(24, factor);
+                    let scaled = feature.scale(factor);
+                    assert!(feature_type.fits(scaled.w, scaled.h, scaled.x, scaled.y, side, side));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,433 @@
+/// Summaries of saved cascades: their stages, the features they use and where, and
+/// how many features a window costs to evaluate.
+use super::decision_tree::Node as TreeNode;
+use super::feature::{AnyFeature, Feature};
+use super::model::{CascadeModel, ModelMetadata};
+use super::opencv::{Branch, OpenCvFeature, OpenCvTree};
+use super::preprocess::{load_imgs_from_dir, normalize_windows, IntegralImages};
+use super::strong_classifier::StrongClassifier;
+use super::weak_learner::WeakLearner;
+use super::Classification;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Windows are assumed to pass each stage with this probability when no windows
+/// are given to measure on.
+const ASSUMED_PASS_RATE: f64 = 0.5;
+
+#[derive(Debug, Serialize)]
+pub struct StageReport {
+    pub weak_learners: usize,
+    /// The features in the stage's weak learners.
+    pub features: usize,
+    pub threshold: f64,
+    /// The share of windows that reach the stage.
+    pub reached: f64,
+    /// The features evaluated on a window reaching the stage, taking both branches
+    /// of trees as equally likely.
+    pub features_per_window: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Distribution {
+    pub min: usize,
+    pub mean: f64,
+    pub max: usize,
+}
+
+impl Distribution {
+    fn of(values: &[usize]) -> Option<Distribution> {
+        Some(Distribution {
+            min: *values.iter().min()?,
+            mean: values.iter().sum::<usize>() as f64 / values.len() as f64,
+            max: *values.iter().max()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelReport {
+    pub metadata: ModelMetadata,
+    pub window_size: usize,
+    pub stages: Vec<StageReport>,
+    pub weak_learners: usize,
+    /// The number of features of each Haar type, of MB-LBP features ("MbLbp") and
+    /// of imported OpenCV features ("OpenCv", "OpenCvTilted").
+    pub feature_types: BTreeMap<String, usize>,
+    /// The width and height of the features' bounding boxes.
+    pub feature_widths: Option<Distribution>,
+    pub feature_heights: Option<Distribution>,
+    /// The number of features whose centre falls in each cell of a 3x3 grid over
+    /// the window, top row first.
+    pub feature_centres: [[usize; 3]; 3],
+    /// The expected number of features evaluated per window, stopping at the first
+    /// stage to reject it.
+    pub features_per_window: f64,
+    /// The number of windows the stages' reach was measured on, or None if each
+    /// stage was assumed to pass half the windows reaching it.
+    pub measured_on: Option<usize>,
+}
+
+/// The type name and bounding box (x, y, w, h) of a feature.
+fn feature_info(feature: &AnyFeature) -> (String, (usize, usize, usize, usize)) {
+    let name = match feature {
+        AnyFeature::Haar(haar) => format!("{:?}", haar.feature_type()),
+        AnyFeature::Lbp(_) => "MbLbp".to_string(),
+    };
+    (name, feature.bounds())
+}
+
+fn opencv_feature_info(feature: &OpenCvFeature) -> (String, (usize, usize, usize, usize)) {
+    let boxes: Vec<_> = feature
+        .rects
+        .iter()
+        .map(|r| {
+            if feature.tilted {
+                (r.x - r.h, r.y, r.x + r.w, r.y + r.w + r.h)
+            } else {
+                (r.x, r.y, r.x + r.w, r.y + r.h)
+            }
+        })
+        .collect();
+    let x = boxes.iter().map(|b| b.0).min().unwrap_or(0);
+    let y = boxes.iter().map(|b| b.1).min().unwrap_or(0);
+    let right = boxes.iter().map(|b| b.2).max().unwrap_or(0);
+    let bottom = boxes.iter().map(|b| b.3).max().unwrap_or(0);
+
+    let name = if feature.tilted {
+        "OpenCvTilted"
+    } else {
+        "OpenCv"
+    };
+    (name.to_string(), (x, y, right - x, bottom - y))
+}
+
+/// The features of a weak learner.
+fn learner_features(learner: &WeakLearner) -> Vec<(String, (usize, usize, usize, usize))> {
+    match learner {
+        WeakLearner::Stump(_) | WeakLearner::Tree(_) => learner
+            .stumps()
+            .iter()
+            .map(|stump| feature_info(stump.feature()))
+            .collect(),
+        WeakLearner::Lbp(lut) => vec![feature_info(&AnyFeature::Lbp(*lut.feature()))],
+        WeakLearner::OpenCv(tree) => tree
+            .nodes
+            .iter()
+            .map(|node| opencv_feature_info(&node.feature))
+            .collect(),
+    }
+}
+
+/// The expected number of splits from a node to a leaf, taking both branches as
+/// equally likely.
+fn tree_path(node: &TreeNode) -> f64 {
+    match node {
+        TreeNode::Leaf(_) => 0.,
+        TreeNode::Split { face, non_face, .. } => 1. + (tree_path(face) + tree_path(non_face)) / 2.,
+    }
+}
+
+fn opencv_path(tree: &OpenCvTree, index: usize) -> f64 {
+    let branch_path = |branch: Branch| match branch {
+        Branch::Node(i) => opencv_path(tree, i),
+        Branch::Leaf(_) => 0.,
+    };
+    let node = &tree.nodes[index];
+    1. + (branch_path(node.left) + branch_path(node.right)) / 2.
+}
+
+/// The expected number of features a weak learner evaluates.
+fn learner_cost(learner: &WeakLearner) -> f64 {
+    match learner {
+        WeakLearner::Stump(_) | WeakLearner::Lbp(_) => 1.,
+        WeakLearner::Tree(tree) => tree_path(tree.root()),
+        WeakLearner::OpenCv(tree) => opencv_path(tree, 0),
+    }
+}
+
+/// The share of windows reaching each stage, measured on `windows` or assumed.
+fn reach(stages: &[StrongClassifier], windows: Option<&[IntegralImages]>) -> Vec<f64> {
+    match windows {
+        Some(windows) => {
+            let mut passing: Vec<&IntegralImages> = windows.iter().collect();
+            stages
+                .iter()
+                .map(|stage| {
+                    let reached = passing.len() as f64 / windows.len() as f64;
+                    passing.retain(|window| stage.evaluate(&window.view()) == Classification::Face);
+                    reached
+                })
+                .collect()
+        }
+        None => (0..stages.len())
+            .map(|i| ASSUMED_PASS_RATE.powi(i as i32))
+            .collect(),
+    }
+}
+
+/// Summarize a cascade, measuring how far windows get through it on `windows` if
+/// given.
+fn report(model: &CascadeModel, windows: Option<&[IntegralImages]>) -> ModelReport {
+    let reached = reach(&model.stages, windows);
+    let mut stages = Vec::with_capacity(model.stages.len());
+    let mut features = Vec::new();
+    for (stage, reached) in model.stages.iter().zip(reached) {
+        let mut num_features = 0;
+        for learner in &stage.classifiers {
+            let learner_features = learner_features(learner);
+            num_features += learner_features.len();
+            features.extend(learner_features);
+        }
+        stages.push(StageReport {
+            weak_learners: stage.classifiers.len(),
+            features: num_features,
+            threshold: stage.threshold(),
+            reached,
+            features_per_window: stage.classifiers.iter().map(learner_cost).sum(),
+        });
+    }
+
+    // Cascades that don't record their window size are taken to end where their
+    // features do
+    let window_size = model.metadata.window_size().unwrap_or_else(|| {
+        features
+            .iter()
+            .map(|(_, (x, y, w, h))| (x + w).max(y + h))
+            .max()
+            .unwrap_or(0)
+    });
+
+    let mut feature_types = BTreeMap::new();
+    let mut feature_centres = [[0; 3]; 3];
+    for (name, (x, y, w, h)) in &features {
+        *feature_types.entry(name.clone()).or_insert(0) += 1;
+        let cell = |start: usize, len: usize| ((2 * start + len) * 3 / (2 * window_size)).min(2);
+        feature_centres[cell(*y, *h)][cell(*x, *w)] += 1;
+    }
+    let widths: Vec<usize> = features.iter().map(|(_, bounds)| bounds.2).collect();
+    let heights: Vec<usize> = features.iter().map(|(_, bounds)| bounds.3).collect();
+
+    ModelReport {
+        metadata: model.metadata.clone(),
+        window_size,
+        weak_learners: stages.iter().map(|stage| stage.weak_learners).sum(),
+        features_per_window: stages
+            .iter()
+            .map(|stage| stage.reached * stage.features_per_window)
+            .sum(),
+        stages,
+        feature_types,
+        feature_widths: Distribution::of(&widths),
+        feature_heights: Distribution::of(&heights),
+        feature_centres,
+        measured_on: windows.map(|windows| windows.len()),
+    }
+}
+
+/// Summarize a cascade, assuming each stage passes half the windows reaching it.
+pub fn inspect(model: &CascadeModel) -> ModelReport {
+    report(model, None)
+}
+
+/// Summarize a saved cascade in either format. If a directory of windows is given,
+/// such as backgrounds, how far they get through the cascade is measured on them
+/// instead of assumed.
+pub fn inspect_cascade(
+    saved_cascade_path: &str,
+    windows_dir: Option<&str>,
+) -> Result<ModelReport, String> {
+    let model = CascadeModel::load(saved_cascade_path)?;
+    match windows_dir {
+        Some(dir) => {
+            if !Path::new(dir).is_dir() {
+                return Err(format!("{}: not a directory", dir));
+            }
+            let options = model.metadata.preprocess.unwrap_or_default();
+            // Imported cascades normalize windows by their variance
+            let windows: Vec<IntegralImages> =
+                normalize_windows(load_imgs_from_dir(dir, &options), &options)
+                    .iter()
+                    .map(IntegralImages::with_squares)
+                    .collect();
+            if windows.is_empty() {
+                return Err(format!("{}: no windows to measure on", dir));
+            }
+            Ok(report(&model, Some(&windows)))
+        }
+        None => Ok(inspect(&model)),
+    }
+}
+
+impl fmt::Display for ModelReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} cascade: {} stages, {} weak learners, {}x{} windows",
+            self.metadata.source,
+            self.stages.len(),
+            self.weak_learners,
+            self.window_size,
+            self.window_size
+        )?;
+        if let Some(metrics) = &self.metadata.metrics {
+            writeln!(
+                f,
+                "Training detection rate {:.4}, false positive rate {:.4}",
+                metrics.detection_rate, metrics.false_positive_rate
+            )?;
+        }
+        match self.measured_on {
+            Some(windows) => writeln!(
+                f,
+                "Features per window: {:.2} (measured on {} windows)",
+                self.features_per_window, windows
+            )?,
+            None => writeln!(
+                f,
+                "Features per window: {:.2} (assuming each stage passes half the windows)",
+                self.features_per_window
+            )?,
+        }
+
+        writeln!(
+            f,
+            "\n{:>6} {:>9} {:>9} {:>12} {:>8} {:>16}",
+            "Stage", "Learners", "Features", "Threshold", "Reached", "Features/window"
+        )?;
+        for (i, stage) in self.stages.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>6} {:>9} {:>9} {:>12.4} {:>7.1}% {:>16.2}",
+                i + 1,
+                stage.weak_learners,
+                stage.features,
+                stage.threshold,
+                100. * stage.reached,
+                stage.features_per_window
+            )?;
+        }
+
+        writeln!(f, "\nFeature types:")?;
+        for (name, count) in &self.feature_types {
+            writeln!(f, "  {:>6} x {}", count, name)?;
+        }
+        for (name, distribution) in &[
+            ("widths", &self.feature_widths),
+            ("heights", &self.feature_heights),
+        ] {
+            if let Some(d) = distribution {
+                writeln!(
+                    f,
+                    "Feature {}: {} to {}, mean {:.1}",
+                    name, d.min, d.max, d.mean
+                )?;
+            }
+        }
+        writeln!(f, "Feature centres (3x3 grid over the window):")?;
+        for row in &self.feature_centres {
+            writeln!(f, "  {:>6} {:>6} {:>6}", row[0], row[1], row[2])?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision_tree::DecisionTree;
+    use crate::features::{HaarFeature, HaarFeatureType, Sign};
+    use crate::model::ModelSource;
+    use crate::weak_classifier::WeakClassifier;
+    use crate::{Matrix, PreprocessOptions};
+
+    fn stump(
+        feature_type: HaarFeatureType,
+        w: usize,
+        h: usize,
+        x: usize,
+        y: usize,
+    ) -> WeakClassifier {
+        let feature = AnyFeature::Haar(HaarFeature::new(feature_type, w, h, x, y));
+        WeakClassifier::new(&feature, 0, Sign::Positive)
+    }
+
+    /// A stage of two stumps and a stage with a depth-2 tree, on 12x12 windows.
+    fn model() -> CascadeModel {
+        let tree = DecisionTree::from_parts(
+            2,
+            TreeNode::Split {
+                stump: stump(HaarFeatureType::TwoHorizontal, 2, 2, 8, 8),
+                face: Box::new(TreeNode::Split {
+                    stump: stump(HaarFeatureType::TwoHorizontal, 1, 3, 0, 8),
+                    face: Box::new(TreeNode::Leaf(1.)),
+                    non_face: Box::new(TreeNode::Leaf(-1.)),
+                }),
+                non_face: Box::new(TreeNode::Leaf(-1.)),
+            },
+        );
+        let stages = vec![
+            StrongClassifier::from_parts(
+                vec![
+                    WeakLearner::Stump(stump(HaarFeatureType::TwoVertical, 2, 2, 0, 0)),
+                    WeakLearner::Stump(stump(HaarFeatureType::TwoVertical, 4, 1, 4, 5)),
+                ],
+                vec![1., 1.],
+                0.,
+            ),
+            StrongClassifier::from_parts(vec![WeakLearner::Tree(tree)], vec![1.], 0.),
+        ];
+        let options = PreprocessOptions {
+            window_size: 12,
+            ..PreprocessOptions::default()
+        };
+        CascadeModel::new(ModelMetadata::new(ModelSource::Trained, options), stages)
+    }
+
+    #[test]
+    fn reports_summarize_stages_and_features() {
+        let report = inspect(&model());
+        assert!(report.window_size == 12 && report.weak_learners == 3);
+        assert!(report.stages[0].features == 2 && report.stages[1].features == 2);
+        assert!(report.stages[1].features_per_window == 1.5);
+        // The tree stage is assumed to see half the windows
+        assert!(report.stages[1].reached == 0.5);
+        assert!(report.features_per_window == 2. + 0.5 * 1.5);
+        assert!(report.measured_on.is_none());
+
+        assert!(report.feature_types["TwoVertical"] == 2);
+        assert!(report.feature_types["TwoHorizontal"] == 2);
+        let widths = Distribution {
+            min: 2,
+            mean: 3.,
+            max: 4,
+        };
+        assert!(report.feature_widths.as_ref() == Some(&widths));
+        assert!(report.feature_centres == [[1, 0, 0], [0, 1, 0], [1, 0, 1]]);
+        assert!(!report.to_string().is_empty());
+        assert!(serde_json::to_string(&report).is_ok());
+    }
+
+    #[test]
+    // Windows rejected by the first stage never reach the second
+    fn reach_is_measured_on_windows() {
+        let blank = IntegralImages::from_image(&Matrix::zeros((12, 12)));
+        let mut dark_top = Matrix::ones((12, 12));
+        for x in 0..12 {
+            dark_top[[0, x]] = 0;
+            dark_top[[1, x]] = 0;
+        }
+        let dark_top = IntegralImages::from_image(&dark_top);
+
+        let model = model();
+        assert!(model.stages[0].evaluate(&blank.view()) == Classification::Face);
+        assert!(model.stages[0].evaluate(&dark_top.view()) == Classification::NonFace);
+
+        let report = report(&model, Some(&[blank, dark_top][..]));
+        assert!(report.measured_on == Some(2));
+        assert!(report.stages[0].reached == 1. && report.stages[1].reached == 0.5);
+    }
+}
@@ -0,0 +1,430 @@
+/// Integral images generic over the type their sums accumulate in, so that small
+/// windows can be stored compactly without ever overflowing.
+use super::preprocess::{compute_integral_image, compute_tilted_integral_image};
+use super::util::{Rectangle, TiltedRectangle};
+use super::Matrix;
+use ndarray::{Array2, ArrayView2};
+use std::error::Error;
+use std::fmt;
+
+/// A type integral image sums can be accumulated in.
+pub trait Accumulator: Copy + Default + PartialEq + fmt::Debug + Send + Sync {
+    const NAME: &'static str;
+    /// The range of integers the type holds exactly.
+    const MIN_EXACT: i64;
+    const MAX_EXACT: i64;
+
+    /// Convert a value within the exact range.
+    fn from_i64(value: i64) -> Self;
+    fn to_i64(self) -> i64;
+    /// `a + b - c - d`, exact whenever the result is within the exact range, even if
+    /// the intermediate sums aren't (integer types wrap).
+    fn combine(a: Self, b: Self, c: Self, d: Self) -> Self;
+}
+
+macro_rules! integer_accumulator {
+    ($t:ty) => {
+        impl Accumulator for $t {
+            const NAME: &'static str = stringify!($t);
+            const MIN_EXACT: i64 = <$t>::MIN as i64;
+            const MAX_EXACT: i64 = <$t>::MAX as i64;
+
+            fn from_i64(value: i64) -> $t {
+                value as $t
+            }
+
+            fn to_i64(self) -> i64 {
+                self as i64
+            }
+
+            fn combine(a: $t, b: $t, c: $t, d: $t) -> $t {
+                a.wrapping_add(b).wrapping_sub(c).wrapping_sub(d)
+            }
+        }
+    };
+}
+
+integer_accumulator!(u32);
+integer_accumulator!(i32);
+integer_accumulator!(i64);
+
+impl Accumulator for f64 {
+    const NAME: &'static str = "f64";
+    // Integers beyond 2^53 can't all be represented
+    const MIN_EXACT: i64 = -(1 << 53);
+    const MAX_EXACT: i64 = 1 << 53;
+
+    fn from_i64(value: i64) -> f64 {
+        value as f64
+    }
+
+    fn to_i64(self) -> i64 {
+        self as i64
+    }
+
+    fn combine(a: f64, b: f64, c: f64, d: f64) -> f64 {
+        a + b - c - d
+    }
+}
+
+/// An integral image's sums don't fit its accumulator type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverflowError {
+    pub accumulator: &'static str,
+    /// The range of the sums that didn't fit.
+    pub min: i64,
+    pub max: i64,
+}
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "integral image sums in [{}, {}] overflow {}",
+            self.min, self.max, self.accumulator
+        )
+    }
+}
+
+impl Error for OverflowError {}
+
+/// A zero-padded integral image, one row and column larger than its image.
+///
+/// For an upright integral image, entry (y, x) is the sum of the pixels in rows
+/// `0..y` and columns `0..x`, so row and column 0 are zero and the sum of the
+/// pixels in rows `ymin..ymax` and columns `xmin..xmax` is read from four entries
+/// with no special cases at the image's edges. For a tilted integral image, entry
+/// (y, x) is the sum of the triangle with its apex at pixel (y - 1, x - 1) that
+/// widens by a pixel on each side for every row above.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntegralImage<T> {
+    sums: Array2<T>,
+}
+
+/// A view into an integral image, or into a window of it. Areas are relative to
+/// the view's origin.
+#[derive(Clone, Copy)]
+pub struct IntegralImageView<'a, T> {
+    sums: ArrayView2<'a, T>,
+}
+
+impl<T: Accumulator> IntegralImage<T> {
+    /// Narrow the sums to `T`, failing if any doesn't fit.
+    fn from_sums(sums: Matrix) -> Result<IntegralImage<T>, OverflowError> {
+        let min = sums.iter().cloned().min().unwrap_or(0);
+        let max = sums.iter().cloned().max().unwrap_or(0);
+        if min < T::MIN_EXACT || max > T::MAX_EXACT {
+            return Err(OverflowError {
+                accumulator: T::NAME,
+                min,
+                max,
+            });
+        }
+
+        Ok(IntegralImage {
+            sums: sums.mapv(T::from_i64),
+        })
+    }
+
+    /// The upright integral image of an image.
+    pub fn upright(img: &Matrix) -> Result<IntegralImage<T>, OverflowError> {
+        IntegralImage::from_sums(compute_integral_image(img))
+    }
+
+    /// The 45-degree tilted integral image of an image.
+    pub fn tilted(img: &Matrix) -> Result<IntegralImage<T>, OverflowError> {
+        IntegralImage::from_sums(compute_tilted_integral_image(img))
+    }
+
+    /// Wrap sums that already form a padded integral image.
+    pub fn from_sums_unchecked(sums: Array2<T>) -> IntegralImage<T> {
+        IntegralImage { sums }
+    }
+
+    pub fn sums(&self) -> &Array2<T> {
+        &self.sums
+    }
+
+    /// The dimensions of the (padded) integral image.
+    pub fn dim(&self) -> (usize, usize) {
+        self.sums.dim()
+    }
+
+    pub fn view(&self) -> IntegralImageView<'_, T> {
+        IntegralImageView {
+            sums: self.sums.view(),
+        }
+    }
+
+    /// The integral image of a square window of `side_len` pixels with its top-left
+    /// pixel at (y, x): `side_len + 1` entries on a side, like the integral image of
+    /// the window itself.
+    pub fn window(&self, y: usize, x: usize, side_len: usize) -> IntegralImageView<'_, T> {
+        IntegralImageView {
+            sums: self
+                .sums
+                .slice(s![y..y + side_len + 1, x..x + side_len + 1]),
+        }
+    }
+
+    pub fn compute_area(&self, r: &Rectangle) -> i64 {
+        self.view().compute_area(r)
+    }
+
+    pub fn compute_tilted_area(&self, r: &TiltedRectangle) -> i64 {
+        self.view().compute_tilted_area(r)
+    }
+}
+
+impl<'a, T: Accumulator> IntegralImageView<'a, T> {
+    pub fn dim(&self) -> (usize, usize) {
+        self.sums.dim()
+    }
+
+    /// The sum of the pixels in a block of an upright integral image.
+    pub fn compute_area(&self, r: &Rectangle) -> i64 {
+        let s = &self.sums;
+        T::combine(
+            s[[r.ymax, r.xmax]],
+            s[[r.ymin, r.xmin]],
+            s[[r.ymin, r.xmax]],
+            s[[r.ymax, r.xmin]],
+        )
+        .to_i64()
+    }
+
+    /// The sum of the pixels in a rotated block of a tilted integral image.
+    pub fn compute_tilted_area(&self, r: &TiltedRectangle) -> i64 {
+        let s = &self.sums;
+        T::combine(
+            s[[r.y, r.x]],
+            s[[r.y + r.w + r.h, r.x + r.w - r.h]],
+            s[[r.y + r.h, r.x - r.h]],
+            s[[r.y + r.w, r.x + r.w]],
+        )
+        .to_i64()
+    }
+}
+
+/// An integral image in whichever accumulator it was built with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnyIntegralImage {
+    U32(IntegralImage<u32>),
+    I32(IntegralImage<i32>),
+    I64(IntegralImage<i64>),
+}
+
+/// A view into an `AnyIntegralImage`.
+#[derive(Clone, Copy)]
+pub enum AnyIntegralImageView<'a> {
+    U32(IntegralImageView<'a, u32>),
+    I32(IntegralImageView<'a, i32>),
+    I64(IntegralImageView<'a, i64>),
+}
+
+/// Apply the same expression to whichever integral image an enum holds.
+macro_rules! dispatch {
+    ($enum:ident, $value:expr, $img:ident => $body:expr) => {
+        match $value {
+            $enum::U32($img) => $body,
+            $enum::I32($img) => $body,
+            $enum::I64($img) => $body,
+        }
+    };
+}
+
+impl AnyIntegralImage {
+    /// The integral image of an image in the narrowest accumulator that can't
+    /// overflow for an image of its size and pixel range: no sum, upright or tilted,
+    /// can exceed the number of pixels times the largest pixel magnitude.
+    fn narrowest(img: &Matrix, tilted: bool) -> Result<AnyIntegralImage, OverflowError> {
+        fn build<T: Accumulator>(
+            img: &Matrix,
+            tilted: bool,
+        ) -> Result<IntegralImage<T>, OverflowError> {
+            if tilted {
+                IntegralImage::tilted(img)
+            } else {
+                IntegralImage::upright(img)
+            }
+        }
+
+        let max_magnitude = img.iter().map(|px| px.abs()).max().unwrap_or(0);
+        let any_negative = img.iter().any(|px| *px < 0);
+        let bound = (img.len() as i64)
+            .checked_mul(max_magnitude)
+            .ok_or(OverflowError {
+                accumulator: i64::NAME,
+                min: i64::MIN,
+                max: i64::MAX,
+            })?;
+
+        Ok(if !any_negative && bound <= u32::MAX_EXACT {
+            AnyIntegralImage::U32(build(img, tilted)?)
+        } else if bound <= i32::MAX_EXACT {
+            AnyIntegralImage::I32(build(img, tilted)?)
+        } else {
+            AnyIntegralImage::I64(build(img, tilted)?)
+        })
+    }
+
+    /// The upright integral image in the narrowest accumulator that fits it.
+    pub fn upright(img: &Matrix) -> Result<AnyIntegralImage, OverflowError> {
+        AnyIntegralImage::narrowest(img, false)
+    }
+
+    /// The tilted integral image in the narrowest accumulator that fits it.
+    pub fn tilted(img: &Matrix) -> Result<AnyIntegralImage, OverflowError> {
+        AnyIntegralImage::narrowest(img, true)
+    }
+
+    /// The name of the accumulator type.
+    pub fn accumulator(&self) -> &'static str {
+        match self {
+            AnyIntegralImage::U32(_) => u32::NAME,
+            AnyIntegralImage::I32(_) => i32::NAME,
+            AnyIntegralImage::I64(_) => i64::NAME,
+        }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        dispatch!(AnyIntegralImage, self, img => img.dim())
+    }
+
+    pub fn view(&self) -> AnyIntegralImageView<'_> {
+        match self {
+            AnyIntegralImage::U32(img) => AnyIntegralImageView::U32(img.view()),
+            AnyIntegralImage::I32(img) => AnyIntegralImageView::I32(img.view()),
+            AnyIntegralImage::I64(img) => AnyIntegralImageView::I64(img.view()),
+        }
+    }
+
+    /// The integral image of a square window with its top-left pixel at (y, x).
+    pub fn window(&self, y: usize, x: usize, side_len: usize) -> AnyIntegralImageView<'_> {
+        match self {
+            AnyIntegralImage::U32(img) => AnyIntegralImageView::U32(img.window(y, x, side_len)),
+            AnyIntegralImage::I32(img) => AnyIntegralImageView::I32(img.window(y, x, side_len)),
+            AnyIntegralImage::I64(img) => AnyIntegralImageView::I64(img.window(y, x, side_len)),
+        }
+    }
+
+    pub fn compute_area(&self, r: &Rectangle) -> i64 {
+        dispatch!(AnyIntegralImage, self, img => img.compute_area(r))
+    }
+
+    pub fn compute_tilted_area(&self, r: &TiltedRectangle) -> i64 {
+        dispatch!(AnyIntegralImage, self, img => img.compute_tilted_area(r))
+    }
+}
+
+impl<'a> AnyIntegralImageView<'a> {
+    pub fn dim(&self) -> (usize, usize) {
+        dispatch!(AnyIntegralImageView, self, img => img.dim())
+    }
+
+    pub fn compute_area(&self, r: &Rectangle) -> i64 {
+        dispatch!(AnyIntegralImageView, self, img => img.compute_area(r))
+    }
+
+    pub fn compute_tilted_area(&self, r: &TiltedRectangle) -> i64 {
+        dispatch!(AnyIntegralImageView, self, img => img.compute_tilted_area(r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // The same areas come out of every accumulator type
+    fn accumulators_agree() {
+        let img = Matrix::from_shape_fn((5, 7), |(y, x)| ((y * 7 + x) * 37 % 256) as i64);
+        let rect = Rectangle::new((1, 2), (6, 5));
+        let tilted = TiltedRectangle::new(3, 0, 2, 2);
+
+        let expected = IntegralImage::<i64>::upright(&img)
+            .unwrap()
+            .compute_area(&rect);
+        assert!(
+            IntegralImage::<u32>::upright(&img)
+                .unwrap()
+                .compute_area(&rect)
+                == expected
+        );
+        assert!(
+            IntegralImage::<i32>::upright(&img)
+                .unwrap()
+                .compute_area(&rect)
+                == expected
+        );
+        assert!(
+            IntegralImage::<f64>::upright(&img)
+                .unwrap()
+                .compute_area(&rect)
+                == expected
+        );
+
+        let expected = IntegralImage::<i64>::tilted(&img)
+            .unwrap()
+            .compute_tilted_area(&tilted);
+        assert!(
+            IntegralImage::<u32>::tilted(&img)
+                .unwrap()
+                .compute_tilted_area(&tilted)
+                == expected
+        );
+    }
+
+    #[test]
+    fn construction_checks_for_overflow() {
+        // Two pixels of 2^31 sum to 2^32, past u32::MAX
+        let big = Matrix::from_elem((1, 2), 1 << 31);
+        let err = IntegralImage::<u32>::upright(&big).unwrap_err();
+        assert!(err.accumulator == "u32" && err.max == 1 << 32);
+        assert!(IntegralImage::<i64>::upright(&big).is_ok());
+
+        assert!(IntegralImage::<u32>::upright(&Matrix::from_elem((2, 2), -1)).is_err());
+        assert!(IntegralImage::<i32>::upright(&Matrix::from_elem((2, 2), -1)).is_ok());
+    }
+
+    #[test]
+    fn narrowest_accumulator_fits_the_image() {
+        let window = Matrix::from_elem((64, 64), 255);
+        assert!(AnyIntegralImage::upright(&window).unwrap().accumulator() == "u32");
+
+        let signed = Matrix::from_elem((64, 64), -255);
+        let narrowest = AnyIntegralImage::upright(&signed).unwrap();
+        assert!(narrowest.accumulator() == "i32");
+        assert!(narrowest.compute_area(&Rectangle::new((0, 0), (64, 64))) == -255 * 64 * 64);
+
+        // Four pixels of 2^31 can sum past 2^32
+        let huge = Matrix::from_elem((2, 2), 1 << 31);
+        assert!(AnyIntegralImage::tilted(&huge).unwrap().accumulator() == "i64");
+    }
+
+    #[test]
+    // Wrapping intermediate sums still give exact areas
+    fn areas_survive_wrapping_corners() {
+        let img = Matrix::from_elem((2, 2), (1 << 30) - 1);
+        let integral = IntegralImage::<u32>::upright(&img).unwrap();
+        assert!(integral.compute_area(&Rectangle::new((0, 0), (2, 2))) == 4 * ((1 << 30) - 1));
+        assert!(integral.compute_area(&Rectangle::new((1, 1), (2, 2))) == (1 << 30) - 1);
+    }
+
+    #[test]
+    // A window of an image's integral image measures the window's pixels like the
+    // window's own integral image
+    fn windows_cover_their_last_row_and_column() {
+        let img = Matrix::from_shape_fn((6, 6), |(y, x)| (y * 6 + x) as i64);
+        let window = img.slice(s![1..5, 2..6]).to_owned();
+        let own = IntegralImage::<i64>::upright(&window).unwrap();
+        let integral = IntegralImage::<i64>::upright(&img).unwrap();
+        let view = integral.window(1, 2, 4);
+        assert!(view.dim() == own.dim());
+
+        let whole = Rectangle::new((0, 0), (4, 4));
+        let corner = Rectangle::new((3, 3), (4, 4));
+        assert!(view.compute_area(&whole) == own.compute_area(&whole));
+        assert!(view.compute_area(&corner) == own.compute_area(&corner));
+    }
+}
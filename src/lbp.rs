@@ -0,0 +1,295 @@
+/// Multi-Block Local Binary Pattern features and the lookup-table weak classifiers
+/// trained on them.
+use super::feature::{scale_length, Feature};
+use super::util::Rectangle;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+
+type IntegralImages = super::IntegralImages;
+type Classification = super::Classification;
+type IntegralView<'a> = super::IntegralView<'a>;
+
+/// A 3x3 grid of w x h blocks with its top-left corner at (x, y). The feature value
+/// is an 8-bit code with one bit per outer block, set when the block's sum is at
+/// least that of the centre block.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct MbLbpFeature {
+    w: usize,
+    h: usize,
+    x: usize,
+    y: usize,
+}
+
+/// Offsets (in blocks) of the outer blocks, from the most significant bit of the
+/// code to the least (clockwise from the top-left, as in OpenCV).
+const NEIGHBOURS: [(usize, usize); 8] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (2, 1),
+    (2, 2),
+    (1, 2),
+    (0, 2),
+    (0, 1),
+];
+
+impl MbLbpFeature {
+    pub fn new(w: usize, h: usize, x: usize, y: usize) -> MbLbpFeature {
+        MbLbpFeature { w, h, x, y }
+    }
+
+    /// The block size and corner (w, h, x, y), as passed to `MbLbpFeature::new`.
+    pub fn geometry(&self) -> (usize, usize, usize, usize) {
+        (self.w, self.h, self.x, self.y)
+    }
+
+    /// The centre block as (x, y, w, h), followed by the outer blocks from the most
+    /// significant bit of the code to the least.
+    pub fn rects(&self) -> Vec<(usize, usize, usize, usize)> {
+        let mut rects = vec![(self.x + self.w, self.y + self.h, self.w, self.h)];
+        for (bx, by) in NEIGHBOURS.iter() {
+            rects.push((self.x + bx * self.w, self.y + by * self.h, self.w, self.h));
+        }
+
+        rects
+    }
+
+    fn block(&self, bx: usize, by: usize) -> Rectangle {
+        let x = self.x + bx * self.w;
+        let y = self.y + by * self.h;
+        Rectangle::new((x, y), (x + self.w, y + self.h))
+    }
+
+    /// Evaluate the LBP code on the integral image (in constant time).
+    pub fn evaluate(&self, img: &IntegralView) -> u8 {
+        let centre = img.upright.compute_area(&self.block(1, 1));
+
+        let mut code = 0;
+        for (bx, by) in NEIGHBOURS.iter() {
+            code <<= 1;
+            if img.upright.compute_area(&self.block(*bx, *by)) >= centre {
+                code |= 1;
+            }
+        }
+
+        code
+    }
+}
+
+impl Feature for MbLbpFeature {
+    fn evaluate(&self, img: &IntegralView) -> i64 {
+        MbLbpFeature::evaluate(self, img) as i64
+    }
+
+    fn scale(&self, factor: f64) -> MbLbpFeature {
+        MbLbpFeature::new(
+            scale_length(self.w, factor).max(1),
+            scale_length(self.h, factor).max(1),
+            scale_length(self.x, factor),
+            scale_length(self.y, factor),
+        )
+    }
+
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        (self.x, self.y, 3 * self.w, 3 * self.h)
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MB-LBP with {}x{} blocks at ({}, {})",
+            self.w, self.h, self.x, self.y
+        )
+    }
+}
+
+/// Create the set of MB-LBP features that fit within the training images.
+pub fn init_mb_lbp_features(
+    maxw: usize,
+    maxh: usize,
+    stride: usize,
+    step: usize,
+) -> Vec<MbLbpFeature> {
+    let mut lbp_features = Vec::new();
+    for w in (1..=maxw / 3).step_by(step) {
+        for h in (1..=maxh / 3).step_by(step) {
+            for x in (0..(maxw - 3 * w)).step_by(stride) {
+                for y in (0..(maxh - 3 * h)).step_by(stride) {
+                    lbp_features.push(MbLbpFeature::new(w, h, x, y));
+                }
+            }
+        }
+    }
+
+    println!("Starting with {} MB-LBP features", lbp_features.len());
+    lbp_features
+}
+
+/// A weak classifier mapping each of the 256 LBP codes to a class.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct LbpClassifier {
+    feature: MbLbpFeature,
+    /// A bit set of the codes classified as faces.
+    face_codes: [u32; 8],
+}
+
+impl LbpClassifier {
+    /// A lookup table classifying the codes in the `face_codes` bit set as faces.
+    pub fn new(feature: MbLbpFeature, face_codes: [u32; 8]) -> LbpClassifier {
+        LbpClassifier {
+            feature,
+            face_codes,
+        }
+    }
+
+    pub fn feature(&self) -> &MbLbpFeature {
+        &self.feature
+    }
+
+    pub fn face_codes(&self) -> [u32; 8] {
+        self.face_codes
+    }
+
+    /// Finds the lookup table with the lowest weighted error for a feature. Each
+    /// code is assigned the class with the most weight among the samples having it.
+    fn get_optimal(
+        feature: &MbLbpFeature,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
+    ) -> (LbpClassifier, f64) {
+        let mut pos = [0.; 256];
+        let mut neg = [0.; 256];
+        for ((sample, label), dist) in training_samples.iter().zip(distribution_t.iter()) {
+            let code = feature.evaluate(&sample.view()) as usize;
+            match label {
+                Classification::Face => pos[code] += dist,
+                Classification::NonFace => neg[code] += dist,
+            }
+        }
+
+        let mut face_codes = [0; 8];
+        let mut error = 0.;
+        for code in 0..256 {
+            if pos[code] > neg[code] {
+                face_codes[code / 32] |= 1 << (code % 32);
+                error += neg[code];
+            } else {
+                error += pos[code];
+            }
+        }
+
+        (
+            LbpClassifier {
+                feature: *feature,
+                face_codes,
+            },
+            error,
+        )
+    }
+
+    /// Returns the lookup-table classifier with the lowest weighted error over all
+    /// features.
+    pub fn best_lut(
+        features: &Vec<MbLbpFeature>,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
+    ) -> (LbpClassifier, f64) {
+        assert!(training_samples.len() == distribution_t.len());
+
+        println!(
+            "Running a search over {} MB-LBP features and {} training samples...",
+            features.len(),
+            training_samples.len()
+        );
+        let pb = ProgressBar::new(features.len() as u64);
+        pb.set_style(
+            ProgressStyle::default_bar().template("[{elapsed_precise}] {wide_bar} ({eta})"),
+        );
+
+        let mut best: Option<(LbpClassifier, f64)> = None;
+        for feature in features {
+            let (classifier, error) = Self::get_optimal(feature, training_samples, distribution_t);
+            if best.is_none_or(|(_, best_error)| error < best_error) {
+                best = Some((classifier, error));
+            }
+
+            pb.inc(1);
+        }
+
+        pb.finish_with_message("done");
+
+        best.expect("No MB-LBP features to search over")
+    }
+
+    /// Evaluate the classifier on an input image.
+    pub fn evaluate(&self, img: &IntegralView) -> Classification {
+        self.evaluate_scaled(img, 1.)
+    }
+
+    /// Evaluate the classifier on a window `factor` times the training size. Codes
+    /// compare blocks of equal size, so they need no area compensation.
+    pub fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> Classification {
+        let code = self.feature.evaluate_scaled(img, factor) as usize;
+        if self.face_codes[code / 32] & (1 << (code % 32)) != 0 {
+            Classification::Face
+        } else {
+            Classification::NonFace
+        }
+    }
+
+    /// Returns 1 for faces and -1 for non-faces.
+    pub fn evaluate_raw(&self, img: &IntegralView) -> f64 {
+        self.evaluate(img) * 1.
+    }
+
+    /// Returns 1 for faces and -1 for non-faces, on a window `factor` times the
+    /// training size.
+    pub fn evaluate_raw_scaled(&self, img: &IntegralView, factor: f64) -> f64 {
+        self.evaluate_scaled(img, factor) * 1.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Matrix;
+
+    #[test]
+    fn codes_computed_correctly() {
+        let feature = MbLbpFeature::new(2, 2, 0, 0);
+
+        let m1 = IntegralImages::from_image(&Matrix::ones((6, 6)));
+        assert!(feature.evaluate(&m1.view()) == 0b1111_1111);
+
+        // Brighten the centre block, the top row of blocks and the right-hand block
+        let mut m2 = Matrix::ones((6, 6));
+        for y in 0..6 {
+            for x in 0..6 {
+                if y < 2 || (y < 4 && x >= 2) {
+                    m2[[y, x]] = 2;
+                }
+            }
+        }
+        let m2 = IntegralImages::from_image(&m2);
+        assert!(feature.evaluate(&m2.view()) == 0b1111_0000);
+    }
+
+    #[test]
+    fn lookup_table_learns_codes() {
+        let features = vec![MbLbpFeature::new(1, 1, 0, 0)];
+
+        // Faces have a bright centre (code 0), non-faces a dark one (code 255)
+        let mut samples = Vec::new();
+        for &(centre, label) in &[(5, Classification::Face), (0, Classification::NonFace)] {
+            let mut img = Matrix::ones((3, 3));
+            img[[1, 1]] = centre;
+            samples.push((IntegralImages::from_image(&img), label));
+        }
+        let distribution = vec![0.5; 2];
+
+        let (classifier, error) = LbpClassifier::best_lut(&features, &samples, &distribution);
+        assert!(error == 0.);
+        for (sample, label) in &samples {
+            assert!(classifier.evaluate(&sample.view()) == *label);
+        }
+    }
+}
@@ -5,30 +5,71 @@ extern crate indicatif;
 extern crate ndarray;
 extern crate serde;
 
+pub mod audit;
+mod augment;
+mod cache;
+pub mod compact;
+pub mod datasets;
+mod decision_tree;
+mod feature;
 mod features;
+pub mod inspect;
+pub mod integral;
+mod lbp;
+pub mod manifest;
+pub mod model;
+mod negatives;
+pub mod opencv;
+mod photometric;
 mod preprocess;
 mod strong_classifier;
+pub mod training_log;
 mod util;
+pub mod visualize;
 mod weak_classifier;
+mod weak_learner;
 
-use features::HaarFeature;
+pub use augment::AugmentOptions;
+use compact::CompactCascade;
+use feature::AnyFeature;
+pub use feature::Feature;
+pub use features::HaarFeatureType;
 use image::Rgba;
 use imageproc::rect::Rect;
 use indicatif::{ProgressBar, ProgressStyle};
+use manifest::{Manifest, Split};
+use memmap2::Mmap;
+use model::{
+    CascadeModel, FeatureSet, ModelMetadata, ModelSource, TrainingConfig, TrainingMetrics,
+};
+pub use negatives::NegativeSampling;
+pub use photometric::{Normalization, NormalizationScope, PhotometricOptions};
+use preprocess::{IntegralImages, IntegralView};
+pub use preprocess::{LumaStandard, PreprocessOptions, ResizeFilter, WindowFit};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::f64;
-use std::fs;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::Read;
 use std::ops::Mul;
+use std::path::Path;
+use std::time::Instant;
 use strong_classifier::StrongClassifier;
-use weak_classifier::WeakClassifier;
+use training_log::{LogRecord, TrainingLog};
+pub use util::{Rectangle, TiltedRectangle};
+use weak_learner::WeakLearner;
+pub use weak_learner::WeakLearnerKind;
 
 pub type Matrix = ndarray::Array2<i64>;
 
+/// Where preprocessed training data is cached, relative to the working directory.
+const CACHE_DIR: &str = ".viola_jones_cache";
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Classification {
+    #[serde(alias = "face")]
     Face,
+    #[serde(alias = "nonface")]
     NonFace,
 }
 
@@ -54,26 +95,180 @@ impl Mul for Classification {
     }
 }
 
+/// Load a cascade saved by `Learner::train`, with its preprocessing if recorded.
+fn load_cascade_with_options(
+    saved_cascade_path: &str,
+) -> (Vec<StrongClassifier>, Option<PreprocessOptions>) {
+    let model = CascadeModel::load(saved_cascade_path)
+        .unwrap_or_else(|err| panic!("Failed to load the cascade: {}", err));
+    (model.stages, model.metadata.preprocess)
+}
+
+/// Load a cascade saved by `Learner::train`.
+fn load_cascade(saved_cascade_path: &str) -> Vec<StrongClassifier> {
+    load_cascade_with_options(saved_cascade_path).0
+}
+
+/// A saved cascade to detect with. Compact cascades are memory-mapped and run in
+/// place, without decoding their stages.
+enum LoadedCascade {
+    Stages(Vec<StrongClassifier>),
+    Compact(CompactCascade<Mmap>),
+}
+
+impl LoadedCascade {
+    /// Open a saved cascade in either format, with its preprocessing if recorded.
+    fn open(saved_cascade_path: &str) -> (LoadedCascade, Option<PreprocessOptions>) {
+        let mut magic = [0; 4];
+        let is_compact = File::open(saved_cascade_path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+            && compact::is_compact(&magic);
+        if !is_compact {
+            let (stages, recorded) = load_cascade_with_options(saved_cascade_path);
+            return (LoadedCascade::Stages(stages), recorded);
+        }
+
+        let cascade = CompactCascade::open(saved_cascade_path)
+            .unwrap_or_else(|err| panic!("Failed to load the cascade: {}", err));
+        let metadata = cascade
+            .metadata()
+            .unwrap_or_else(|err| panic!("Failed to load the cascade: {}", err));
+        (LoadedCascade::Compact(cascade), metadata.preprocess)
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            LoadedCascade::Stages(stages) => stages.is_empty(),
+            LoadedCascade::Compact(cascade) => cascade.num_stages() == 0,
+        }
+    }
+
+    /// Whether every stage accepts a window.
+    fn detects(&self, img: &IntegralView) -> bool {
+        match self {
+            LoadedCascade::Stages(stages) => stages
+                .iter()
+                .all(|stage| stage.evaluate(img) == Classification::Face),
+            LoadedCascade::Compact(cascade) => cascade.detects(img),
+        }
+    }
+}
+
+/// The options to detect with: the caller's, but always with the window size and
+/// photometric normalization the cascade was trained with, since features trained
+/// on normalized windows are meaningless on raw ones.
+fn detection_options(
+    options: &PreprocessOptions,
+    recorded: Option<PreprocessOptions>,
+) -> PreprocessOptions {
+    match recorded {
+        Some(recorded) => PreprocessOptions {
+            window_size: recorded.window_size,
+            photometric: recorded.photometric,
+            ..*options
+        },
+        None => *options,
+    }
+}
+
 /// A cascaded learner.
 #[derive(Serialize, Deserialize)]
 pub struct Learner {
     max_cascade_depth: u8,
+    weak_learner_kind: WeakLearnerKind,
+    preprocess_options: PreprocessOptions,
 
     #[serde(skip)]
-    training_inputs: Vec<(Matrix, Classification)>,
+    training_inputs: Vec<(IntegralImages, Classification)>,
     #[serde(skip)]
-    original_training_inputs: Vec<(Matrix, Classification)>,
+    original_training_inputs: Vec<(IntegralImages, Classification)>,
+    /// The initial boosting weight of each training input.
+    #[serde(skip)]
+    sample_weights: Vec<f64>,
+
+    features: Vec<AnyFeature>,
 
-    haar_features: Vec<HaarFeature>,
+    #[serde(skip)]
+    training_log: Option<TrainingLog>,
 }
 
 impl Learner {
     pub fn new(faces_dir: &str, background_dir: &str, max_cascade_depth: u8) -> Learner {
+        Learner::with_options(
+            faces_dir,
+            background_dir,
+            max_cascade_depth,
+            PreprocessOptions::default(),
+        )
+    }
+
+    /// Create a learner whose training images are preprocessed with `options`. The
+    /// same options should be passed to `test_cascade_with_options`.
+    pub fn with_options(
+        faces_dir: &str,
+        background_dir: &str,
+        max_cascade_depth: u8,
+        preprocess_options: PreprocessOptions,
+    ) -> Learner {
         // Load the data (faces followed by background, in tuples with class labels)
-        let training_inputs = preprocess::load_and_preprocess_data(faces_dir, background_dir);
-        let original_training_inputs =
-            preprocess::load_and_preprocess_data(faces_dir, background_dir);
+        let training_inputs = if preprocess_options.cache {
+            cache::load_and_preprocess_data_cached(
+                faces_dir,
+                background_dir,
+                &preprocess_options,
+                Path::new(CACHE_DIR),
+            )
+        } else {
+            preprocess::load_and_preprocess_data(faces_dir, background_dir, &preprocess_options)
+        };
+        let original_training_inputs = training_inputs.clone();
+        let sample_weights = vec![1.; training_inputs.len()];
+
+        Learner::from_inputs(
+            training_inputs,
+            original_training_inputs,
+            sample_weights,
+            max_cascade_depth,
+            preprocess_options,
+        )
+    }
+
+    /// Create a learner from the training split of a dataset manifest, with each
+    /// sample starting out with its manifest weight.
+    pub fn from_manifest(
+        manifest_path: &str,
+        max_cascade_depth: u8,
+        preprocess_options: PreprocessOptions,
+    ) -> Learner {
+        let manifest = Manifest::load(manifest_path).expect("Failed to load the manifest");
+        let data = manifest::load_split(&manifest, Split::Train, &preprocess_options, false);
+        println!("{}: {}", manifest_path, data.report);
+
+        Learner::from_inputs(
+            data.inputs.clone(),
+            data.inputs,
+            data.weights,
+            max_cascade_depth,
+            preprocess_options,
+        )
+    }
 
+    fn from_inputs(
+        training_inputs: Vec<(IntegralImages, Classification)>,
+        original_training_inputs: Vec<(IntegralImages, Classification)>,
+        sample_weights: Vec<f64>,
+        max_cascade_depth: u8,
+        preprocess_options: PreprocessOptions,
+    ) -> Learner {
+        assert!(
+            !training_inputs.is_empty(),
+            "No training images were loaded"
+        );
+        assert!(
+            sample_weights.iter().all(|w| *w > 0.),
+            "Sample weights must be positive"
+        );
         let (maxw, maxh) = training_inputs[0].0.dim();
 
         // Note that the stride and step size are arbitrarily set to 4 and 4.
@@ -81,38 +276,132 @@ impl Learner {
         // space.
         Learner {
             max_cascade_depth,
+            weak_learner_kind: WeakLearnerKind::Stump,
+            preprocess_options,
             training_inputs,
             original_training_inputs,
-            haar_features: features::init_haar_features(maxw, maxh, 4, 4),
+            sample_weights,
+            features: features::init_haar_features(maxw, maxh, 4, 4)
+                .into_iter()
+                .map(AnyFeature::Haar)
+                .collect(),
+            training_log: None,
+        }
+    }
+
+    /// Choose the weak learner trained in each boosting round (stumps by default).
+    #[allow(deprecated)]
+    pub fn set_weak_learner(&mut self, kind: WeakLearnerKind) {
+        self.weak_learner_kind = kind;
+        if kind == WeakLearnerKind::Lbp {
+            self.set_lbp_features(true);
+        }
+    }
+
+    /// Replace the Haar features searched while boosting with those of the given
+    /// families, each with an optional cap on how many of its features are kept.
+    pub fn set_haar_families(&mut self, families: &[(HaarFeatureType, Option<usize>)]) {
+        let (maxw, maxh) = self.training_inputs[0].0.dim();
+        let mut haar_features: Vec<AnyFeature> =
+            features::init_haar_feature_families(maxw, maxh, 4, 4, families)
+                .into_iter()
+                .map(AnyFeature::Haar)
+                .collect();
+
+        self.features.retain(|feature| match feature {
+            AnyFeature::Haar(_) => false,
+            AnyFeature::Lbp(_) => true,
+        });
+        haar_features.append(&mut self.features);
+        self.features = haar_features;
+    }
+
+    /// Add MB-LBP features to (or remove them from) the features searched while
+    /// boosting. They're mixed with the Haar features, so to train a pure MB-LBP
+    /// cascade also call `set_haar_families(&[])`.
+    pub fn set_lbp_features(&mut self, enabled: bool) {
+        self.features.retain(|feature| match feature {
+            AnyFeature::Haar(_) => true,
+            AnyFeature::Lbp(_) => false,
+        });
+
+        if enabled {
+            let (maxw, maxh) = self.training_inputs[0].0.dim();
+            self.features.extend(
+                lbp::init_mb_lbp_features(maxw, maxh, 4, 4)
+                    .into_iter()
+                    .map(AnyFeature::Lbp),
+            );
+        }
+    }
+
+    /// Write a JSONL record of every boosting round and cascade stage to `log_path`
+    /// while training. Render it with `training_log::write_report`.
+    pub fn log_training_to(&mut self, log_path: &str) {
+        self.training_log = Some(TrainingLog::create(log_path));
+    }
+
+    fn log(&mut self, record: LogRecord) {
+        if let Some(training_log) = self.training_log.as_mut() {
+            training_log.record(&record);
         }
     }
 
     /// Creates a strong classifier from a single round of boosting.
     /// Returns a strong learner/committee.
-    fn run_boosting(&self) -> StrongClassifier {
+    fn run_boosting(&mut self, stage: usize, training_start: Instant) -> StrongClassifier {
         let mut strong = StrongClassifier::new();
+        let num_positives = self
+            .training_inputs
+            .iter()
+            .filter(|(_, label)| *label == Classification::Face)
+            .count();
+        let num_negatives = self.training_inputs.len() - num_positives;
 
         // To avoid getting stuck to do outliers, we limit the number of total weak
         // learners we add to the classifier in a given boosting.
         let mut boosting_round = 0;
-        let mut distribution =
-            vec![1. / self.training_inputs.len() as f64; self.training_inputs.len()];
+        let total_weight: f64 = self.sample_weights.iter().sum();
+        let mut distribution: Vec<f64> = self
+            .sample_weights
+            .iter()
+            .map(|w| w / total_weight)
+            .collect();
         loop {
             boosting_round += 1;
+            let round_start = Instant::now();
 
-            let (best_classifier, best_error): (WeakClassifier, f64) = WeakClassifier::best_stump(
-                &self.haar_features,
+            let (best_classifier, best_error): (WeakLearner, f64) = WeakLearner::train(
+                self.weak_learner_kind,
+                &self.features,
                 &self.training_inputs,
                 &distribution,
             );
 
             let alpha_t = (0.5) * ((1. - best_error) / best_error).ln();
-            strong.add_weak_classifier(best_classifier, alpha_t, &self.training_inputs);
+            strong.add_weak_classifier(best_classifier.clone(), alpha_t, &self.training_inputs);
+
+            let (fpr, fnr, overall) = strong.compute_error(&self.training_inputs);
+            self.log(LogRecord::Round {
+                stage,
+                round: boosting_round,
+                weak_classifier: best_classifier,
+                alpha: alpha_t,
+                weighted_error: best_error,
+                stage_fpr: fpr,
+                stage_fnr: fnr,
+                stage_error: overall,
+                num_weak_classifiers: strong.classifiers.len(),
+                num_positives,
+                num_negatives,
+                round_secs: round_start.elapsed().as_secs_f64(),
+                elapsed_secs: training_start.elapsed().as_secs_f64(),
+            });
 
             // Turn this into a strong learner by itself and return
             if best_error == 0. {
                 println!("Found a single weak classifier that had 0 error, returning early");
-                return strong
+                return strong;
             }
 
             // Update the distribution weights
@@ -132,8 +421,6 @@ impl Learner {
 
             distribution = distribution.iter().map(|x| x / newtot).collect();
 
-            let (fpr, fnr, overall) = strong.compute_error(&self.training_inputs);
-
             println!("Finished boosting round {}", boosting_round);
             println!(
                 "Currently have {} weak classifiers with FPR {} and FNR {} and overall error {}",
@@ -152,12 +439,22 @@ impl Learner {
     }
 
     pub fn train(&mut self) {
-        assert!(self.training_inputs.len() == 4000);
+        assert!(
+            self.training_inputs
+                .iter()
+                .any(|(_, label)| *label == Classification::Face)
+                && self
+                    .training_inputs
+                    .iter()
+                    .any(|(_, label)| *label == Classification::NonFace),
+            "Training needs both faces and backgrounds"
+        );
         println!("Beginning training...");
 
         let mut cascade: Vec<StrongClassifier> =
             Vec::with_capacity(self.max_cascade_depth as usize);
 
+        let training_start = Instant::now();
         let mut cascade_round = 0;
         loop {
             if cascade_round == self.max_cascade_depth {
@@ -170,18 +467,40 @@ impl Learner {
             println!("Starting cascade round {}", cascade_round);
             println!("-------------------------");
 
-            cascade.push(self.run_boosting());
+            let stage_start = Instant::now();
+            cascade.push(self.run_boosting(cascade_round as usize, training_start));
 
             // Remove examples that are classified as negative from the set of inputs
             // that gets fed into the next layer in the cascade. This removes a trivial
             // amount of false negatives (2), which isn't a big deal.
             let mut new_inputs = Vec::new();
-            for (sample, label) in &self.training_inputs {
+            let mut new_weights = Vec::new();
+            for ((sample, label), weight) in self.training_inputs.iter().zip(&self.sample_weights) {
                 if cascade.last().unwrap().evaluate(&sample.view()) == Classification::Face {
                     new_inputs.push((sample.clone(), *label));
+                    new_weights.push(*weight);
                 }
             }
+            let (stage_fpr, stage_fnr, _) =
+                cascade.last().unwrap().compute_error(&self.training_inputs);
             self.training_inputs = new_inputs;
+            self.sample_weights = new_weights;
+
+            let remaining_positives = self
+                .training_inputs
+                .iter()
+                .filter(|(_, label)| *label == Classification::Face)
+                .count();
+            self.log(LogRecord::Stage {
+                stage: cascade_round as usize,
+                num_weak_classifiers: cascade.last().unwrap().classifiers.len(),
+                stage_fpr,
+                stage_fnr,
+                remaining_positives,
+                remaining_negatives: self.training_inputs.len() - remaining_positives,
+                stage_secs: stage_start.elapsed().as_secs_f64(),
+                elapsed_secs: training_start.elapsed().as_secs_f64(),
+            });
         }
 
         self.evaluate_and_save_cascade(cascade);
@@ -229,50 +548,129 @@ impl Learner {
             num_true_positives, num_positive_examples, detection_rate
         );
 
-        // Serialize and save the cascade
-        fs::write(
-            "saved_cascade.json",
-            serde_json::to_string(&cascade).expect("Failed to serialize cascade to string"),
-        )
-        .expect("Failed to write serialized cascade to file");
+        // Save the cascade with how it was trained and how it did
+        let mut metadata = ModelMetadata::new(ModelSource::Trained, self.preprocess_options);
+        metadata.dataset_digest = Some(model::dataset_digest(&self.original_training_inputs));
+        metadata.training = Some(TrainingConfig {
+            max_cascade_depth: self.max_cascade_depth,
+            weak_learner: self.weak_learner_kind,
+            features: FeatureSet::of(&self.features),
+        });
+        metadata.metrics = Some(TrainingMetrics {
+            detection_rate,
+            false_positive_rate,
+            num_faces: num_positive_examples as usize,
+            num_backgrounds: num_negative_examples as usize,
+        });
+        CascadeModel::new(metadata, cascade)
+            .save("saved_cascade.json")
+            .expect("Failed to write serialized cascade to file");
 
         println!("Saved results to 'saved_cascade.json'");
     }
 
-    /// Run a saved cascade on a test image.
+    /// Print the detection and false positive rates of a saved cascade on a split
+    /// of a dataset manifest. A window is detected if every stage accepts it.
+    pub fn evaluate_manifest(
+        saved_cascade_path: &str,
+        manifest_path: &str,
+        split: Split,
+        options: &PreprocessOptions,
+    ) -> (f64, f64) {
+        let (cascade, recorded) = LoadedCascade::open(saved_cascade_path);
+        let options = detection_options(options, recorded);
+        let manifest = Manifest::load(manifest_path).expect("Failed to load the manifest");
+        let data = manifest::load_split(&manifest, split, &options, true);
+        println!("{}: {}", manifest_path, data.report);
+
+        let (mut num_faces, mut num_detected) = (0, 0);
+        let (mut num_backgrounds, mut num_false_positives) = (0, 0);
+        for (sample, label) in &data.inputs {
+            let detected = cascade.detects(&sample.view());
+            match label {
+                Classification::Face => {
+                    num_faces += 1;
+                    num_detected += detected as usize;
+                }
+                Classification::NonFace => {
+                    num_backgrounds += 1;
+                    num_false_positives += detected as usize;
+                }
+            }
+        }
+
+        let detection_rate = num_detected as f64 / num_faces as f64;
+        let false_positive_rate = num_false_positives as f64 / num_backgrounds as f64;
+        println!(
+            "Detection rate:      {} / {} = {}",
+            num_detected, num_faces, detection_rate
+        );
+        println!(
+            "False positive rate: {} / {} = {}",
+            num_false_positives, num_backgrounds, false_positive_rate
+        );
+
+        (detection_rate, false_positive_rate)
+    }
+
+    /// Run a saved cascade on a test image, preprocessed as recorded in the cascade
+    /// (or with the default options for cascades that don't record it).
     pub fn test_cascade(test_img_path: &str, saved_cascade_path: &str) {
-        // Load the saved cascade
-        let mut cascade_file = File::open(saved_cascade_path).expect("Couldn't open cascade file");
-        let mut cascade_contents = String::new();
-        cascade_file.read_to_string(&mut cascade_contents).unwrap();
-        let cascade: Vec<StrongClassifier> = serde_json::from_str(&cascade_contents).unwrap();
+        let (_, recorded) = LoadedCascade::open(saved_cascade_path);
+        Learner::test_cascade_with_options(
+            test_img_path,
+            saved_cascade_path,
+            &recorded.unwrap_or_default(),
+        );
+    }
+
+    /// Run a saved cascade on a test image, preprocessed as the training images were.
+    /// The photometric normalization recorded in the cascade, if any, is always used.
+    pub fn test_cascade_with_options(
+        test_img_path: &str,
+        saved_cascade_path: &str,
+        options: &PreprocessOptions,
+    ) {
+        let (cascade, recorded) = LoadedCascade::open(saved_cascade_path);
+        let options = &detection_options(options, recorded);
+        let per_window = options
+            .photometric
+            .is_some_and(|p| p.scope == NormalizationScope::Window);
 
         // Load the test image
-        let (test_img, sliding_windows) = preprocess::load_test_image(test_img_path);
+        let (test_mat, test_img, sliding_windows) =
+            preprocess::load_test_image(test_img_path, options);
 
         println!(
             "Considering a total of {} faces within the test image",
             sliding_windows.len()
         );
 
-        let mut num_faces = 0;
-        let mut faces = Vec::new();
-        for (y, x) in sliding_windows {
-            let subimg = test_img.slice(s![y..y + 64, x..x + 64]);
-
-            for (i, classifier) in cascade.iter().enumerate() {
-                let classification = classifier.evaluate(&subimg);
-
-                if classification == Classification::NonFace {
-                    break;
-                }
-
-                if i == cascade.len() - 1 && classification == Classification::Face {
-                    num_faces += 1;
-                    faces.push((x, y));
-                }
-            }
-        }
+        // Windows are classified in parallel, and the detections collected in the
+        // order of the sliding windows
+        let side = options.window_size;
+        let faces: Vec<(usize, usize)> = sliding_windows
+            .par_iter()
+            .filter(|(y, x)| {
+                let normalized;
+                let subimg = if per_window {
+                    let window = test_mat.slice(s![*y..*y + side, *x..*x + side]).to_owned();
+                    normalized = IntegralImages::with_squares(&preprocess::normalize(
+                        window,
+                        options,
+                        NormalizationScope::Window,
+                    ));
+                    normalized.view()
+                } else {
+                    test_img.window(*y, *x, side)
+                };
+
+                // A face passes every stage
+                !cascade.is_empty() && cascade.detects(&subimg)
+            })
+            .map(|(y, x)| (*x, *y))
+            .collect();
+        let num_faces = faces.len();
 
         println!(
             "Number of identified by trained cascaded learner: {}",
@@ -288,12 +686,13 @@ impl Learner {
         // Open the image with image_proc to draw on the boxes
         let test_img = image::open(test_img_path).expect("Failed to open test image");
 
-        let face_rect = Rect::at(faces[0].0 as i32, faces[0].1 as i32).of_size(64, 64);
+        let side = options.window_size as u32;
+        let face_rect = Rect::at(faces[0].0 as i32, faces[0].1 as i32).of_size(side, side);
         let mut test_img =
             imageproc::drawing::draw_hollow_rect(&test_img, face_rect, Rgba([255, 0, 0, 255]));
         pb.inc(1);
         for (face_x, face_y) in faces.iter().skip(1) {
-            let face_rect = Rect::at(*face_x as i32, *face_y as i32).of_size(64, 64);
+            let face_rect = Rect::at(*face_x as i32, *face_y as i32).of_size(side, side);
             test_img =
                 imageproc::drawing::draw_hollow_rect(&test_img, face_rect, Rgba([255, 0, 0, 255]));
             pb.inc(1);
@@ -329,4 +728,25 @@ mod tests {
         classification = Classification::NonFace;
         assert!(label * classification * -1. == -1.);
     }
+
+    #[test]
+    // The recorded normalization overrides the caller's
+    fn detection_uses_recorded_preprocessing() {
+        let options = PreprocessOptions {
+            photometric: Some(PhotometricOptions {
+                normalization: Normalization::Clahe {
+                    tiles: 8,
+                    clip_limit: 2.,
+                },
+                scope: NormalizationScope::Window,
+            }),
+            ..PreprocessOptions::default()
+        };
+        let recorded = PreprocessOptions {
+            window_size: 24,
+            ..options
+        };
+        let detecting = detection_options(&PreprocessOptions::default(), Some(recorded));
+        assert!(detecting.photometric == options.photometric && detecting.window_size == 24);
+    }
 }
@@ -0,0 +1,322 @@
+/// Dataset manifests: files listing training and evaluation images with their
+/// labels, so that datasets can be versioned and mixed from several sources.
+use super::augment::augment_positives;
+use super::datasets::{crop_face, FaceBox, ImportOptions};
+use super::negatives::sample_negatives;
+use super::preprocess::{
+    compute_integral_images, fit_to_window, load_image, normalize_windows, IntegralImages,
+    LoadReport, PreprocessOptions,
+};
+use super::{Classification, Matrix};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    Train,
+    Val,
+    Test,
+}
+
+fn default_weight() -> f64 {
+    1.
+}
+
+fn default_split() -> Split {
+    Split::Train
+}
+
+/// One image in a manifest. Labels are `face` or `nonface`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Relative paths are relative to the manifest's directory.
+    pub path: PathBuf,
+    pub label: Classification,
+    /// The region (x, y, w, h) to take the window from, instead of the whole image.
+    /// Boxed faces are squared up and padded as by the dataset importers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<FaceBox>,
+    /// The sample's initial weight in boosting, relative to the others.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    #[serde(default = "default_split")]
+    pub split: Split,
+}
+
+/// A row of a CSV manifest, with columns `path,label,x,y,w,h,weight,split`. Only
+/// `path` and `label` are required; the box columns are left empty for whole images.
+#[derive(Deserialize)]
+struct CsvRow {
+    path: PathBuf,
+    label: Classification,
+    x: Option<f64>,
+    y: Option<f64>,
+    w: Option<f64>,
+    h: Option<f64>,
+    weight: Option<f64>,
+    split: Option<Split>,
+}
+
+impl CsvRow {
+    fn into_entry(self, line: usize) -> Result<ManifestEntry, String> {
+        let bbox = match (self.x, self.y, self.w, self.h) {
+            (Some(x), Some(y), Some(w), Some(h)) => Some((x, y, w, h)),
+            (None, None, None, None) => None,
+            _ => return Err(format!("line {}: incomplete bounding box", line)),
+        };
+
+        Ok(ManifestEntry {
+            path: self.path,
+            label: self.label,
+            bbox,
+            weight: self.weight.unwrap_or(1.),
+            split: self.split.unwrap_or(Split::Train),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Parse a CSV manifest with a header row.
+    pub fn from_csv(text: &str) -> Result<Manifest, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(text.as_bytes());
+
+        let mut entries = Vec::new();
+        for (i, row) in reader.deserialize::<CsvRow>().enumerate() {
+            // Line 1 is the header
+            let row = row.map_err(|err| format!("line {}: {}", i + 2, err))?;
+            entries.push(row.into_entry(i + 2)?);
+        }
+
+        Ok(Manifest { entries })
+    }
+
+    /// Parse a JSON manifest: an array of entries.
+    pub fn from_json(text: &str) -> Result<Manifest, String> {
+        let entries = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        Ok(Manifest { entries })
+    }
+
+    /// Load a manifest, as CSV if it has a `.csv` extension and as JSON otherwise.
+    /// Relative image paths are resolved against the manifest's directory.
+    pub fn load(path: &str) -> Result<Manifest, String> {
+        let path = Path::new(path);
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let is_csv = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        let mut manifest = if is_csv {
+            Manifest::from_csv(&text)
+        } else {
+            Manifest::from_json(&text)
+        }
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for entry in &mut manifest.entries {
+            entry.path = base.join(&entry.path);
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn split(&self, split: Split) -> impl Iterator<Item = &ManifestEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.split == split)
+    }
+}
+
+/// Training samples loaded from a manifest, with their initial weights.
+pub struct ManifestData {
+    pub inputs: Vec<(IntegralImages, Classification)>,
+    pub weights: Vec<f64>,
+    pub report: LoadReport,
+}
+
+/// Load the window of an entry: its box, or the whole image fitted to the window.
+fn load_window(entry: &ManifestEntry, options: &PreprocessOptions) -> Result<Matrix, String> {
+    let img = load_image(&entry.path, options).map_err(|err| err.to_string())?;
+    match entry.bbox {
+        Some(bbox) => {
+            let import_options = ImportOptions {
+                window_size: options.window_size,
+                luma: options.luma,
+                ..ImportOptions::default()
+            };
+            Ok(crop_face(&img, bbox, &import_options))
+        }
+        None => fit_to_window(img, options),
+    }
+}
+
+/// Load the windows of a split, faces followed by backgrounds as in
+/// `load_and_preprocess_data`. Augmentation and negative sampling apply as they
+/// do to directories: augmented copies inherit their face's weight, and sampled
+/// negatives (drawn from the whole background images) have weight 1. Windows to
+/// detect on rather than train on take `squares`, which imported OpenCV cascades
+/// need (see `IntegralImages::with_squares`).
+pub fn load_split(
+    manifest: &Manifest,
+    split: Split,
+    options: &PreprocessOptions,
+    squares: bool,
+) -> ManifestData {
+    let entries: Vec<&ManifestEntry> = manifest.split(split).collect();
+    let windows: Vec<Result<Matrix, String>> = entries
+        .par_iter()
+        .map(|entry| {
+            if entry.label == Classification::NonFace && options.negatives.is_some() {
+                load_image(&entry.path, options).map_err(|err| err.to_string())
+            } else {
+                load_window(entry, options)
+            }
+        })
+        .collect();
+
+    let mut report = LoadReport::default();
+    let mut faces = Vec::new();
+    let mut backgrounds = Vec::new();
+    for (entry, window) in entries.into_iter().zip(windows) {
+        match window {
+            Ok(window) => match entry.label {
+                Classification::Face => faces.push((window, entry.weight)),
+                Classification::NonFace => backgrounds.push((window, entry.weight)),
+            },
+            Err(err) => report.failed.push((entry.path.clone(), err)),
+        }
+    }
+    report.loaded = faces.len() + backgrounds.len();
+
+    let (mut faces, mut face_weights): (Vec<Matrix>, Vec<f64>) = faces.into_iter().unzip();
+    if let Some(augment) = &options.augment {
+        // Copies are appended face by face, so repeat each weight in turn
+        let mut copies = Vec::with_capacity(face_weights.len() * augment.copies_per_positive);
+        for weight in &face_weights {
+            copies.extend(vec![*weight; augment.copies_per_positive]);
+        }
+        faces = augment_positives(faces, augment);
+        face_weights.append(&mut copies);
+    }
+
+    let (mut backgrounds, mut background_weights): (Vec<Matrix>, Vec<f64>) =
+        backgrounds.into_iter().unzip();
+    if let Some(sampling) = &options.negatives {
+        backgrounds = sample_negatives(&backgrounds, sampling, options.window_size);
+        background_weights = vec![1.; backgrounds.len()];
+    }
+
+    let (faces, backgrounds) = (
+        normalize_windows(faces, options),
+        normalize_windows(backgrounds, options),
+    );
+    let integral_images = |windows: Vec<Matrix>| {
+        if squares {
+            windows
+                .par_iter()
+                .map(IntegralImages::with_squares)
+                .collect()
+        } else {
+            compute_integral_images(windows)
+        }
+    };
+    let mut inputs = Vec::with_capacity(faces.len() + backgrounds.len());
+    for face in integral_images(faces) {
+        inputs.push((face, Classification::Face));
+    }
+    for background in integral_images(backgrounds) {
+        inputs.push((background, Classification::NonFace));
+    }
+    face_weights.append(&mut background_weights);
+
+    ManifestData {
+        inputs,
+        weights: face_weights,
+        report,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn csv_and_json_manifests_agree() {
+        let csv = "path,label,x,y,w,h,weight,split\n\
+                   a.png,face,1,2,3,4,2.5,val\n\
+                   b.png, nonface ,,,,,,\n";
+        let json = r#"[
+            {"path": "a.png", "label": "face", "bbox": [1, 2, 3, 4], "weight": 2.5, "split": "val"},
+            {"path": "b.png", "label": "nonface"}
+        ]"#;
+
+        let from_csv = Manifest::from_csv(csv).unwrap();
+        assert!(from_csv == Manifest::from_json(json).unwrap());
+        assert!(from_csv.entries[0].bbox == Some((1., 2., 3., 4.)));
+        assert!(from_csv.entries[1].weight == 1. && from_csv.entries[1].split == Split::Train);
+        assert!(from_csv.split(Split::Val).count() == 1);
+
+        // Only the path and label columns are required
+        let minimal = Manifest::from_csv("label,path\nface,c.png\n").unwrap();
+        assert!(minimal.entries[0].path == Path::new("c.png"));
+
+        assert!(Manifest::from_csv("path,label,x,y\na.png,face,1,2\n").is_err());
+        assert!(Manifest::from_csv("path,label\na.png,cat\n").is_err());
+    }
+
+    #[test]
+    fn splits_load_with_weights() {
+        let dir = test_dir("manifest");
+        GrayImage::from_fn(2, 2, |x, y| Luma([(10 * (x + 2 * y)) as u8]))
+            .save(dir.join("window.png"))
+            .unwrap();
+        GrayImage::from_fn(8, 8, |x, _| Luma([(30 * x) as u8]))
+            .save(dir.join("scene.png"))
+            .unwrap();
+        fs::write(
+            dir.join("manifest.csv"),
+            "path,label,x,y,w,h,weight,split\n\
+             window.png,face,,,,,3,train\n\
+             scene.png,face,2,2,4,4,,train\n\
+             window.png,nonface,,,,,0.5,train\n\
+             missing.png,nonface,,,,,,train\n\
+             window.png,face,,,,,,test\n",
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(dir.join("manifest.csv").to_str().unwrap()).unwrap();
+        let options = PreprocessOptions {
+            window_size: 2,
+            ..PreprocessOptions::default()
+        };
+        let data = load_split(&manifest, Split::Train, &options, false);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(manifest.entries[0].path == dir.join("window.png"));
+        let labels: Vec<Classification> = data.inputs.iter().map(|(_, label)| *label).collect();
+        assert!(
+            labels
+                == vec![
+                    Classification::Face,
+                    Classification::Face,
+                    Classification::NonFace
+                ]
+        );
+        assert!(data.weights == vec![3., 1., 0.5]);
+        assert!(data.report.loaded == 3);
+        assert!(data.report.failed.len() == 1);
+        assert!(data.inputs.iter().all(|(img, _)| img.dim() == (3, 3)));
+    }
+}
@@ -0,0 +1,362 @@
+/// Saved cascades: the stages, with what's needed to run them (the preprocessing)
+/// and to tell where they came from (the training configuration, data and results),
+/// in a versioned envelope.
+use super::cache::Fnv1a;
+use super::compact::{self, CompactCascade};
+use super::feature::AnyFeature;
+use super::integral::AnyIntegralImage;
+use super::preprocess::{IntegralImages, PreprocessOptions};
+use super::strong_classifier::StrongClassifier;
+use super::weak_learner::WeakLearnerKind;
+use super::{Classification, HaarFeatureType, PhotometricOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The version of the model format that is written. It only changes when a field
+/// is removed or changes meaning: fields added within a version are ignored by
+/// older readers, and optional so that older files still load.
+///
+/// Version 2 records whether each stage scores its stumps by their margins or as
+/// votes (see `StumpScore`); stages saved before then score their margins.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Where a cascade came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelSource {
+    Trained,
+    /// Imported from an OpenCV Haar cascade.
+    OpenCv,
+    /// Saved before cascades recorded their source.
+    Unknown,
+}
+
+/// The features searched in each boosting round.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureSet {
+    /// The Haar feature families, in the order they were generated.
+    pub haar_families: Vec<HaarFeatureType>,
+    pub lbp: bool,
+    pub num_features: usize,
+}
+
+impl FeatureSet {
+    pub(crate) fn of(features: &[AnyFeature]) -> FeatureSet {
+        let mut haar_families = Vec::new();
+        for feature in features {
+            if let AnyFeature::Haar(haar) = feature {
+                if !haar_families.contains(&haar.feature_type()) {
+                    haar_families.push(haar.feature_type());
+                }
+            }
+        }
+
+        FeatureSet {
+            haar_families,
+            lbp: features
+                .iter()
+                .any(|feature| matches!(feature, AnyFeature::Lbp(_))),
+            num_features: features.len(),
+        }
+    }
+}
+
+/// How a cascade was trained.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrainingConfig {
+    pub max_cascade_depth: u8,
+    pub weak_learner: WeakLearnerKind,
+    pub features: FeatureSet,
+}
+
+/// How a cascade did on the data it was trained on.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrainingMetrics {
+    pub detection_rate: f64,
+    pub false_positive_rate: f64,
+    pub num_faces: usize,
+    pub num_backgrounds: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    pub source: ModelSource,
+    /// The preprocessing the cascade was trained with, including its window size and
+    /// photometric normalization. None for cascades saved before it was recorded,
+    /// which run with the caller's options.
+    pub preprocess: Option<PreprocessOptions>,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    /// A digest of the labelled training windows (see `dataset_digest`).
+    #[serde(default)]
+    pub dataset_digest: Option<String>,
+    #[serde(default)]
+    pub training: Option<TrainingConfig>,
+    #[serde(default)]
+    pub metrics: Option<TrainingMetrics>,
+}
+
+impl ModelMetadata {
+    /// Metadata for a cascade created now, with no training information.
+    pub fn new(source: ModelSource, preprocess: PreprocessOptions) -> ModelMetadata {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        ModelMetadata {
+            source,
+            preprocess: Some(preprocess),
+            created_at: Some(created_at),
+            dataset_digest: None,
+            training: None,
+            metrics: None,
+        }
+    }
+
+    pub fn window_size(&self) -> Option<usize> {
+        self.preprocess.map(|options| options.window_size)
+    }
+
+    pub fn normalization(&self) -> Option<PhotometricOptions> {
+        self.preprocess.and_then(|options| options.photometric)
+    }
+}
+
+/// A cascade as saved by `Learner::train` and `opencv::import_cascade`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CascadeModel {
+    pub format_version: u32,
+    pub metadata: ModelMetadata,
+    pub(crate) stages: Vec<StrongClassifier>,
+}
+
+/// What was saved before cascades were versioned: the stages with the preprocessing
+/// they were trained with, or before that a bare list of stages.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyCascade {
+    Recorded {
+        preprocess: PreprocessOptions,
+        stages: Vec<StrongClassifier>,
+    },
+    Stages(Vec<StrongClassifier>),
+}
+
+impl CascadeModel {
+    pub(crate) fn new(metadata: ModelMetadata, stages: Vec<StrongClassifier>) -> CascadeModel {
+        CascadeModel {
+            format_version: FORMAT_VERSION,
+            metadata,
+            stages,
+        }
+    }
+
+    pub fn num_stages(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Parse a saved cascade. Cascades saved before the format was versioned are
+    /// upgraded, and those of later versions than this build reads are rejected.
+    pub fn parse(json: &str) -> Result<CascadeModel, String> {
+        let value: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let version = match value.get("format_version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or("the format version isn't a whole number")?,
+            None => return CascadeModel::upgrade(value),
+        };
+        if version == 0 || version > u64::from(FORMAT_VERSION) {
+            return Err(format!(
+                "unsupported model format version {} (this build reads versions 1 to {})",
+                version, FORMAT_VERSION
+            ));
+        }
+
+        serde_json::from_value(value).map_err(|err| err.to_string())
+    }
+
+    fn upgrade(value: Value) -> Result<CascadeModel, String> {
+        let legacy: LegacyCascade =
+            serde_json::from_value(value).map_err(|_| "not a saved cascade".to_string())?;
+        let (preprocess, stages) = match legacy {
+            LegacyCascade::Recorded { preprocess, stages } => (Some(preprocess), stages),
+            LegacyCascade::Stages(stages) => (None, stages),
+        };
+
+        let metadata = ModelMetadata {
+            source: ModelSource::Unknown,
+            preprocess,
+            created_at: None,
+            dataset_digest: None,
+            training: None,
+            metrics: None,
+        };
+        Ok(CascadeModel::new(metadata, stages))
+    }
+
+    /// Load a saved cascade, decoding it if it's in the compact format.
+    pub fn load(path: &str) -> Result<CascadeModel, String> {
+        let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        let model = if compact::is_compact(&bytes) {
+            CompactCascade::from_bytes(bytes).and_then(|cascade| cascade.to_model())
+        } else {
+            String::from_utf8(bytes)
+                .map_err(|err| err.to_string())
+                .and_then(|json| CascadeModel::parse(&json))
+        };
+        model.map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string(self).expect("Failed to serialize cascade to string");
+        fs::write(path, json).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+/// A digest of the labels and upright integral images of the training windows, in
+/// order, so that cascades trained on the same (preprocessed) data can be told
+/// apart from those that weren't.
+pub(crate) fn dataset_digest(inputs: &[(IntegralImages, Classification)]) -> String {
+    let mut hasher = Fnv1a::new();
+    for (sample, label) in inputs {
+        hasher.write(&[(*label == Classification::Face) as u8]);
+        match &sample.upright {
+            AnyIntegralImage::U32(img) => img
+                .sums()
+                .iter()
+                .for_each(|sum| hasher.write(&i64::from(*sum).to_le_bytes())),
+            AnyIntegralImage::I32(img) => img
+                .sums()
+                .iter()
+                .for_each(|sum| hasher.write(&i64::from(*sum).to_le_bytes())),
+            AnyIntegralImage::I64(img) => img
+                .sums()
+                .iter()
+                .for_each(|sum| hasher.write(&sum.to_le_bytes())),
+        }
+    }
+
+    format!("fnv1a:{:016x}", hasher.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::WindowFit;
+    use crate::strong_classifier::StumpScore;
+    use crate::{Normalization, NormalizationScope};
+
+    #[test]
+    // Versioned cascades round-trip, with unknown fields from newer writers ignored
+    fn models_round_trip() {
+        let options = PreprocessOptions {
+            window_size: 24,
+            photometric: Some(PhotometricOptions {
+                normalization: Normalization::Clahe {
+                    tiles: 8,
+                    clip_limit: 2.,
+                },
+                scope: NormalizationScope::Window,
+            }),
+            ..PreprocessOptions::default()
+        };
+        let mut metadata = ModelMetadata::new(ModelSource::Trained, options);
+        metadata.dataset_digest = Some("fnv1a:0123456789abcdef".to_string());
+        let model = CascadeModel::new(metadata, vec![StrongClassifier::new()]);
+
+        let mut value = serde_json::to_value(&model).unwrap();
+        value["metadata"]["added_later"] = Value::Bool(true);
+        let parsed = CascadeModel::parse(&value.to_string()).unwrap();
+        assert!(parsed.format_version == FORMAT_VERSION && parsed.num_stages() == 1);
+        assert!(parsed.metadata.source == ModelSource::Trained);
+        assert!(parsed.metadata.window_size() == Some(24));
+        assert!(parsed.metadata.normalization() == options.photometric);
+        assert!(parsed.metadata.dataset_digest == model.metadata.dataset_digest);
+        assert!(parsed.metadata.created_at.is_some() && parsed.metadata.training.is_none());
+    }
+
+    #[test]
+    // Cascades saved before versioning load, with their preprocessing if recorded
+    fn legacy_cascades_are_upgraded() {
+        let options = PreprocessOptions {
+            window_size: 24,
+            ..PreprocessOptions::default()
+        };
+        let recorded = serde_json::json!({
+            "preprocess": options,
+            "stages": vec![StrongClassifier::new()],
+        });
+        let model = CascadeModel::parse(&recorded.to_string()).unwrap();
+        assert!(model.num_stages() == 1 && model.metadata.window_size() == Some(24));
+        assert!(model.metadata.source == ModelSource::Unknown);
+
+        let bare = serde_json::to_string(&vec![StrongClassifier::new()]).unwrap();
+        let model = CascadeModel::parse(&bare).unwrap();
+        assert!(model.num_stages() == 1 && model.metadata.preprocess.is_none());
+        assert!(model.format_version == FORMAT_VERSION);
+    }
+
+    #[test]
+    // Preprocessing recorded before an option existed loads with its default
+    fn missing_preprocess_options_take_their_defaults() {
+        let options = PreprocessOptions {
+            window_size: 24,
+            ..PreprocessOptions::default()
+        };
+        let model = CascadeModel::new(
+            ModelMetadata::new(ModelSource::Trained, options),
+            Vec::new(),
+        );
+        let mut value = serde_json::to_value(&model).unwrap();
+        let recorded = value["metadata"]["preprocess"].as_object_mut().unwrap();
+        recorded.remove("window_fit");
+        recorded.remove("cache");
+
+        let parsed = CascadeModel::parse(&value.to_string()).unwrap();
+        let preprocess = parsed.metadata.preprocess.unwrap();
+        assert!(preprocess.window_size == 24 && !preprocess.cache);
+        assert!(preprocess.window_fit == WindowFit::Strict);
+    }
+
+    #[test]
+    // Stages saved before stumps could vote score them by their margins
+    fn version_one_stages_score_stump_margins() {
+        let mut stage = StrongClassifier::new();
+        stage.stump_score = StumpScore::Vote;
+        let model = CascadeModel::new(
+            ModelMetadata::new(ModelSource::Trained, PreprocessOptions::default()),
+            vec![stage],
+        );
+        let mut value = serde_json::to_value(&model).unwrap();
+        let parsed = CascadeModel::parse(&value.to_string()).unwrap();
+        assert!(parsed.stages[0].stump_score == StumpScore::Vote);
+
+        value["format_version"] = 1.into();
+        value["stages"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("stump_score");
+        let parsed = CascadeModel::parse(&value.to_string()).unwrap();
+        assert!(parsed.stages[0].stump_score == StumpScore::Margin);
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        let model = CascadeModel::new(
+            ModelMetadata::new(ModelSource::Trained, PreprocessOptions::default()),
+            Vec::new(),
+        );
+        let mut value = serde_json::to_value(&model).unwrap();
+        value["format_version"] = (FORMAT_VERSION + 1).into();
+        let err = CascadeModel::parse(&value.to_string()).err().unwrap();
+        assert!(err.contains("unsupported model format version"));
+
+        value["format_version"] = "2".into();
+        assert!(CascadeModel::parse(&value.to_string()).is_err());
+        assert!(CascadeModel::parse("{\"stages\": 3}").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+/// Random sampling of negative training windows from large face-free images.
+use super::preprocess::{resize_matrix, ResizeFilter};
+use super::Matrix;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Negatives are drawn as square windows at random positions and scales from the
+/// images in the background directory, each scaled down to the training window.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct NegativeSampling {
+    /// The number of windows drawn in total.
+    pub count: usize,
+    /// Seeds the random number generator, so sampled sets are reproducible.
+    pub seed: u64,
+    /// The largest window side, as a multiple of the training window. Scales are
+    /// drawn log-uniformly from 1 up to this, or the largest that fits the image.
+    pub max_scale: f64,
+    /// Interpolation used to scale windows down to the training window.
+    pub filter: ResizeFilter,
+}
+
+impl Default for NegativeSampling {
+    fn default() -> NegativeSampling {
+        NegativeSampling {
+            count: 5000,
+            seed: 0,
+            max_scale: 8.,
+            filter: ResizeFilter::Triangle,
+        }
+    }
+}
+
+/// Check that an image is large enough to sample windows from.
+pub fn fits_window(img: Matrix, window_size: usize) -> Result<Matrix, String> {
+    let (rows, cols) = img.dim();
+    if rows < window_size || cols < window_size {
+        return Err(format!(
+            "image is {}x{}, too small to sample a {}x{} window from",
+            cols, rows, window_size, window_size
+        ));
+    }
+
+    Ok(img)
+}
+
+/// The top-left coordinates (y, x) of a random square window lying within a space
+/// rectangle of dimensions (xmax, ymax).
+fn random_window_coords<R: Rng>(
+    rng: &mut R,
+    xmax: usize,
+    ymax: usize,
+    window_side_len: usize,
+) -> (usize, usize) {
+    let y = Uniform::new_inclusive(0, ymax - window_side_len).sample(rng);
+    let x = Uniform::new_inclusive(0, xmax - window_side_len).sample(rng);
+
+    (y, x)
+}
+
+/// Draw `count` window-sized negatives from a set of images, each at least
+/// window-sized.
+pub fn sample_negatives(
+    scenes: &[Matrix],
+    options: &NegativeSampling,
+    window_size: usize,
+) -> Vec<Matrix> {
+    assert!(
+        !scenes.is_empty(),
+        "No background images to sample negatives from"
+    );
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let pick_scene = Uniform::new(0, scenes.len());
+    let log_scale = Uniform::new_inclusive(0., 1.);
+
+    let mut negatives = Vec::with_capacity(options.count);
+    for _ in 0..options.count {
+        let scene = &scenes[pick_scene.sample(&mut rng)];
+        let (rows, cols) = scene.dim();
+
+        let fits = rows.min(cols) as f64 / window_size as f64;
+        let max_scale = options.max_scale.clamp(1., fits.max(1.));
+        let scale = max_scale.powf(log_scale.sample(&mut rng));
+        let side =
+            ((window_size as f64 * scale).round() as usize).clamp(window_size, rows.min(cols));
+
+        let (y, x) = random_window_coords(&mut rng, cols, rows, side);
+        let window = scene.slice(s![y..y + side, x..x + side]).to_owned();
+        negatives.push(if side == window_size {
+            window
+        } else {
+            resize_matrix(&window, window_size, options.filter)
+        });
+    }
+
+    negatives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(rows: usize, cols: usize) -> Matrix {
+        Matrix::from_shape_fn((rows, cols), |(y, x)| (3 * y + x) as i64)
+    }
+
+    #[test]
+    fn random_windows_stay_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut corners = Vec::new();
+        for _ in 0..200 {
+            let (y, x) = random_window_coords(&mut rng, 6, 5, 4);
+            assert!(x + 4 <= 6 && y + 4 <= 5);
+            corners.push((y, x));
+        }
+        // Windows flush with the far edges are drawn too
+        assert!(corners.contains(&(1, 2)));
+    }
+
+    #[test]
+    fn unscaled_windows_are_crops() {
+        let scene = ramp(20, 30);
+        let options = NegativeSampling {
+            count: 10,
+            max_scale: 1.,
+            ..NegativeSampling::default()
+        };
+
+        for window in sample_negatives(&[scene], &options, 8) {
+            assert!(window.dim() == (8, 8));
+            // Every crop of the ramp is a shifted copy of its top-left corner
+            let offset = window[[0, 0]];
+            assert!(window == ramp(8, 8) + offset);
+        }
+    }
+
+    #[test]
+    fn sampling_is_seeded_and_multi_scale() {
+        let scenes = vec![ramp(64, 64), ramp(16, 40)];
+        let options = NegativeSampling {
+            count: 50,
+            seed: 5,
+            max_scale: 4.,
+            filter: ResizeFilter::Nearest,
+        };
+
+        let first = sample_negatives(&scenes, &options, 8);
+        let second = sample_negatives(&scenes, &options, 8);
+        assert!(first.len() == 50);
+        assert!(first == second);
+        assert!(first.iter().all(|window| window.dim() == (8, 8)));
+
+        // Downscaled windows step by more than a pixel across the ramp
+        assert!(first
+            .iter()
+            .any(|window| window[[0, 1]] - window[[0, 0]] > 1));
+
+        let reseeded = sample_negatives(&scenes, &NegativeSampling { seed: 6, ..options }, 8);
+        assert!(reseeded != first);
+    }
+
+    #[test]
+    fn small_images_are_rejected() {
+        assert!(fits_window(ramp(8, 8), 8).is_ok());
+        assert!(fits_window(ramp(7, 20), 8).is_err());
+    }
+}
@@ -0,0 +1,1166 @@
+/// Importing OpenCV Haar cascades (`haarcascade_*.xml`) in either the old
+/// `opencv-haar-classifier` format or the `opencv-cascade-classifier` format
+/// written by `opencv_traincascade`, so the detector can run OpenCV's stock models,
+/// and exporting cascades to the latter for OpenCV's `CascadeClassifier`.
+use super::decision_tree::Node as TreeNode;
+use super::feature::{scale_length, AnyFeature};
+use super::features::{HaarFeatureType, Sign};
+use super::model::{CascadeModel, ModelMetadata, ModelSource};
+use super::preprocess::{load_imgs_from_dir, IntegralImages, PreprocessOptions};
+use super::strong_classifier::{StrongClassifier, StumpScore};
+use super::util::{Rectangle, TiltedRectangle};
+use super::weak_classifier::WeakClassifier;
+use super::weak_learner::WeakLearner;
+use super::{Classification, IntegralView};
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::fs;
+use std::str::FromStr;
+
+/// OpenCV loosens every stage threshold by this much when loading a cascade.
+const THRESHOLD_EPS: f64 = 1e-5;
+
+/// A rectangle (x, y, w, h) of a feature with its weight. Tilted rectangles have
+/// their top corner at (x, y), as `TiltedRectangle`s do.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedRect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+    pub weight: f64,
+}
+
+/// An OpenCV Haar feature: the weighted sum of two or three rectangles, all upright
+/// or all tilted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenCvFeature {
+    pub rects: Vec<WeightedRect>,
+    pub tilted: bool,
+}
+
+impl OpenCvFeature {
+    fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> f64 {
+        self.rects
+            .iter()
+            .map(|r| {
+                let (x, y) = (scale_length(r.x, factor), scale_length(r.y, factor));
+                let (w, h) = (scale_length(r.w, factor), scale_length(r.h, factor));
+                let area = if self.tilted {
+                    img.tilted
+                        .compute_tilted_area(&TiltedRectangle::new(x, y, w, h))
+                } else {
+                    img.upright
+                        .compute_area(&Rectangle::new((x, y), (x + w, y + h)))
+                };
+                r.weight * area as f64
+            })
+            .sum()
+    }
+
+    /// Whether every rectangle lies within a square window of `side` pixels.
+    fn fits(&self, side: usize) -> bool {
+        self.rects.iter().all(|r| {
+            if self.tilted {
+                r.x >= r.h && r.x + r.w <= side && r.y + r.w + r.h <= side
+            } else {
+                r.x + r.w <= side && r.y + r.h <= side
+            }
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Branch {
+    /// The index of the next node within the tree.
+    Node(usize),
+    Leaf(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenCvNode {
+    pub feature: OpenCvFeature,
+    pub threshold: f64,
+    /// Taken when the feature is below the threshold.
+    pub left: Branch,
+    pub right: Branch,
+}
+
+/// One of OpenCV's weak classifiers: a tree (usually a single node) whose nodes
+/// threshold variance-normalized features, scoring the value of the leaf reached.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenCvTree {
+    /// The root comes first, and every branch leads to a later node.
+    pub nodes: Vec<OpenCvNode>,
+}
+
+impl OpenCvTree {
+    pub fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> Classification {
+        if self.evaluate_raw_scaled(img, factor) >= 0. {
+            Classification::Face
+        } else {
+            Classification::NonFace
+        }
+    }
+
+    /// The value of the leaf reached on a window `factor` times the cascade's size.
+    pub fn evaluate_raw_scaled(&self, img: &IntegralView, factor: f64) -> f64 {
+        let norm = variance_norm(img, factor);
+        let mut node = &self.nodes[0];
+        loop {
+            let value = node.feature.evaluate_scaled(img, factor);
+            let branch = if value < node.threshold * norm {
+                node.left
+            } else {
+                node.right
+            };
+            match branch {
+                Branch::Node(i) => node = &self.nodes[i],
+                Branch::Leaf(value) => return value,
+            }
+        }
+    }
+}
+
+/// OpenCV's normalization of a window, `sqrt(n * Σx² - (Σx)²)` over the `n`
+/// pixels of the window inset by a pixel on each side: `n` times their standard
+/// deviation. Feature thresholds are relative to it, so detection is insensitive
+/// to contrast.
+pub(crate) fn variance_norm(img: &IntegralView, factor: f64) -> f64 {
+    let side = img.upright.dim().0 - 1;
+    let inset = (factor.round() as usize).max(1);
+    let inner = Rectangle::new((inset, inset), (side - inset, side - inset));
+    let n = ((side - 2 * inset) * (side - 2 * inset)) as f64;
+
+    let sum = img.upright.compute_area(&inner) as f64;
+    let squares = img
+        .squared
+        .as_ref()
+        .expect("OpenCV trees need the integral image of the squared pixels")
+        .compute_area(&inner) as f64;
+    let norm = n * squares - sum * sum;
+    if norm > 0. {
+        norm.sqrt()
+    } else {
+        1.
+    }
+}
+
+/// The first child element with a tag name.
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Result<Node<'a, 'input>, String> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .ok_or_else(|| format!("<{}> has no <{}>", node.tag_name().name(), name))
+}
+
+/// The element children of a node, such as the `<_>` items of a sequence.
+fn elements<'a, 'input: 'a>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(|n| n.is_element())
+}
+
+/// The whitespace-separated numbers in a node's text.
+fn numbers<T: FromStr>(node: Node) -> Result<Vec<T>, String> {
+    let text: String = node
+        .children()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect();
+    text.split_whitespace()
+        .map(|s| {
+            s.parse()
+                .map_err(|_| format!("<{}>: bad number '{}'", node.tag_name().name(), s))
+        })
+        .collect()
+}
+
+fn number<T: FromStr>(node: Node, name: &str) -> Result<T, String> {
+    let mut values = numbers(child(node, name)?)?;
+    if values.len() != 1 {
+        return Err(format!("<{}> should hold one number", name));
+    }
+    Ok(values.remove(0))
+}
+
+fn parse_feature(node: Node, side: usize) -> Result<OpenCvFeature, String> {
+    let mut rects = Vec::new();
+    for rect in elements(child(node, "rects")?) {
+        let values: Vec<f64> = numbers(rect)?;
+        let coords: Vec<usize> = values.iter().take(4).map(|v| *v as usize).collect();
+        if values.len() != 5 || values[..4].iter().any(|v| *v < 0. || v.fract() != 0.) {
+            return Err(format!("bad rectangle {:?}", values));
+        }
+        rects.push(WeightedRect {
+            x: coords[0],
+            y: coords[1],
+            w: coords[2],
+            h: coords[3],
+            weight: values[4],
+        });
+    }
+
+    let tilted = match node.children().find(|n| n.has_tag_name("tilted")) {
+        Some(_) => number::<i64>(node, "tilted")? != 0,
+        None => false,
+    };
+    let feature = OpenCvFeature { rects, tilted };
+    if !feature.fits(side) {
+        return Err(format!("feature {:?} doesn't fit the window", feature));
+    }
+
+    Ok(feature)
+}
+
+/// Check that a tree's branches only lead forward, so evaluating it terminates.
+fn checked_tree(nodes: Vec<OpenCvNode>) -> Result<OpenCvTree, String> {
+    if nodes.is_empty() {
+        return Err("empty tree".to_string());
+    }
+    for (i, node) in nodes.iter().enumerate() {
+        for branch in &[node.left, node.right] {
+            if let Branch::Node(next) = branch {
+                if *next <= i || *next >= nodes.len() {
+                    return Err(format!("node {} branches to node {}", i, next));
+                }
+            }
+        }
+    }
+
+    Ok(OpenCvTree { nodes })
+}
+
+/// A stage of imported trees, which passes windows whose leaf values sum to at
+/// least its threshold.
+fn stage(trees: Vec<OpenCvTree>, threshold: f64) -> StrongClassifier {
+    let weights = vec![1.; trees.len()];
+    let trees = trees.into_iter().map(WeakLearner::OpenCv).collect();
+    StrongClassifier::from_parts(trees, weights, threshold - THRESHOLD_EPS)
+}
+
+fn square_window(width: usize, height: usize) -> Result<usize, String> {
+    if width != height || width < 3 {
+        return Err(format!(
+            "{}x{} windows aren't supported; detection windows are square",
+            width, height
+        ));
+    }
+    Ok(width)
+}
+
+/// The `opencv-haar-classifier` format, with each tree's nodes inline.
+fn parse_old_format(cascade: Node) -> Result<(usize, Vec<StrongClassifier>), String> {
+    let size: Vec<usize> = numbers(child(cascade, "size")?)?;
+    if size.len() != 2 {
+        return Err("<size> should be a width and a height".to_string());
+    }
+    let side = square_window(size[0], size[1])?;
+
+    let mut stages = Vec::new();
+    for (i, stage_node) in elements(child(cascade, "stages")?).enumerate() {
+        // Later versions allowed trees of stages, which a cascade can't represent
+        let parent = child(stage_node, "parent")
+            .map_or(Ok(i as i64 - 1), |_| number::<i64>(stage_node, "parent"))?;
+        let next =
+            child(stage_node, "next").map_or(Ok(-1), |_| number::<i64>(stage_node, "next"))?;
+        if parent != i as i64 - 1 || next != -1 {
+            return Err("stage trees aren't supported".to_string());
+        }
+
+        let mut trees = Vec::new();
+        for tree in elements(child(stage_node, "trees")?) {
+            let mut nodes = Vec::new();
+            for node in elements(tree) {
+                let branch = |leaf: &str, next: &str| -> Result<Branch, String> {
+                    if child(node, leaf).is_ok() {
+                        Ok(Branch::Leaf(number(node, leaf)?))
+                    } else {
+                        Ok(Branch::Node(number(node, next)?))
+                    }
+                };
+                nodes.push(OpenCvNode {
+                    feature: parse_feature(child(node, "feature")?, side)?,
+                    threshold: number(node, "threshold")?,
+                    left: branch("left_val", "left_node")?,
+                    right: branch("right_val", "right_node")?,
+                });
+            }
+            trees.push(checked_tree(nodes)?);
+        }
+
+        stages.push(stage(trees, number(stage_node, "stage_threshold")?));
+    }
+
+    Ok((side, stages))
+}
+
+/// The `opencv-cascade-classifier` format, with the features listed once and
+/// referred to by index from flattened trees.
+fn parse_new_format(cascade: Node) -> Result<(usize, Vec<StrongClassifier>), String> {
+    let text = |name: &str| -> Result<String, String> {
+        Ok(child(cascade, name)?
+            .text()
+            .unwrap_or("")
+            .trim()
+            .to_string())
+    };
+    if text("stageType")? != "BOOST" || text("featureType")? != "HAAR" {
+        return Err(format!(
+            "only boosted Haar cascades are supported, not {} {}",
+            text("stageType")?,
+            text("featureType")?
+        ));
+    }
+    let side = square_window(number(cascade, "width")?, number(cascade, "height")?)?;
+
+    let features = elements(child(cascade, "features")?)
+        .map(|feature| parse_feature(feature, side))
+        .collect::<Result<Vec<OpenCvFeature>, String>>()?;
+
+    let mut stages = Vec::new();
+    for stage_node in elements(child(cascade, "stages")?) {
+        let mut trees = Vec::new();
+        for tree in elements(child(stage_node, "weakClassifiers")?) {
+            let internal: Vec<f64> = numbers(child(tree, "internalNodes")?)?;
+            let leaves: Vec<f64> = numbers(child(tree, "leafValues")?)?;
+            // Each node is four numbers, and a tree has one more leaf than nodes
+            if leaves.is_empty() || internal.len() != 4 * (leaves.len() - 1) {
+                return Err("<internalNodes> don't match <leafValues>".to_string());
+            }
+
+            // Positive indices are nodes, and others leaves negated
+            let branch = |index: f64| -> Result<Branch, String> {
+                if index > 0. {
+                    Ok(Branch::Node(index as usize))
+                } else {
+                    leaves
+                        .get((-index) as usize)
+                        .map(|value| Branch::Leaf(*value))
+                        .ok_or_else(|| format!("no leaf {}", -index))
+                }
+            };
+            let mut nodes = Vec::new();
+            for node in internal.chunks(4) {
+                let feature = features
+                    .get(node[2] as usize)
+                    .ok_or_else(|| format!("no feature {}", node[2]))?;
+                nodes.push(OpenCvNode {
+                    feature: feature.clone(),
+                    threshold: node[3],
+                    left: branch(node[0])?,
+                    right: branch(node[1])?,
+                });
+            }
+            trees.push(checked_tree(nodes)?);
+        }
+
+        stages.push(stage(trees, number(stage_node, "stageThreshold")?));
+    }
+
+    Ok((side, stages))
+}
+
+/// Parse an OpenCV Haar cascade into its window size and stages.
+fn parse_cascade(xml: &str) -> Result<(usize, Vec<StrongClassifier>), String> {
+    let doc = Document::parse(xml).map_err(|err| err.to_string())?;
+    let cascade = elements(doc.root_element())
+        .next()
+        .ok_or("the file holds no cascade")?;
+
+    if child(cascade, "featureType").is_ok() {
+        parse_new_format(cascade)
+    } else if child(cascade, "size").is_ok() {
+        parse_old_format(cascade)
+    } else {
+        Err("not an OpenCV cascade".to_string())
+    }
+}
+
+/// Convert an OpenCV Haar cascade to a saved cascade that `Learner::test_cascade`
+/// and `Learner::evaluate_manifest` run, recording its window size. Returns the
+/// number of stages.
+pub fn import_cascade(xml_path: &str, saved_cascade_path: &str) -> Result<usize, String> {
+    let xml = fs::read_to_string(xml_path).map_err(|err| format!("{}: {}", xml_path, err))?;
+    let (window_size, stages) =
+        parse_cascade(&xml).map_err(|err| format!("{}: {}", xml_path, err))?;
+    let num_stages = stages.len();
+
+    let preprocess = PreprocessOptions {
+        window_size,
+        ..PreprocessOptions::default()
+    };
+    CascadeModel::new(ModelMetadata::new(ModelSource::OpenCv, preprocess), stages)
+        .save(saved_cascade_path)?;
+
+    Ok(num_stages)
+}
+
+/// Convert a Haar feature to weighted rectangles, merging the rectangles it sums
+/// more than once (the centres of centre-surround features). OpenCV features have
+/// at most three rectangles, so a two-by-two checkerboard becomes its whole block
+/// against its two diagonal cells, counted twice.
+fn export_feature(feature: &AnyFeature) -> Result<OpenCvFeature, String> {
+    let haar = match feature {
+        AnyFeature::Haar(haar) => haar,
+        AnyFeature::Lbp(_) => return Err("MB-LBP features can't be exported".to_string()),
+    };
+
+    if haar.feature_type() == HaarFeatureType::TwoByTwo {
+        let (w, h, x, y) = haar.geometry();
+        let tl = (haar.rects()[0].1 * 1) as f64;
+        let cell = |x, y| WeightedRect {
+            x,
+            y,
+            w,
+            h,
+            weight: 2. * tl,
+        };
+        return Ok(OpenCvFeature {
+            rects: vec![
+                WeightedRect {
+                    x,
+                    y,
+                    w: 2 * w,
+                    h: 2 * h,
+                    weight: -tl,
+                },
+                cell(x, y),
+                cell(x + w, y + h),
+            ],
+            tilted: false,
+        });
+    }
+
+    let mut rects: Vec<WeightedRect> = Vec::new();
+    for ((x, y, w, h), sign) in haar.rects() {
+        let weight = (sign * 1) as f64;
+        match rects
+            .iter_mut()
+            .find(|r| (r.x, r.y, r.w, r.h) == (x, y, w, h))
+        {
+            Some(rect) => rect.weight += weight,
+            None => rects.push(WeightedRect { x, y, w, h, weight }),
+        }
+    }
+
+    Ok(OpenCvFeature {
+        rects,
+        tilted: haar.is_tilted(),
+    })
+}
+
+/// Convert a stump to a node. Stumps threshold raw feature values, where OpenCV
+/// thresholds them relative to each window's normalization, so thresholds are
+/// divided by a reference normalization: windows normalized by exactly that are
+/// classified the same.
+fn export_stump(
+    stump: &WeakClassifier,
+    norm: f64,
+    face: Branch,
+    non_face: Branch,
+) -> Result<OpenCvNode, String> {
+    let feature = export_feature(stump.feature())?;
+    // Faces are at or above the threshold for positive toggles, and at or below it
+    // for negative ones. Feature values are integers, so splitting halfway to the
+    // next one leaves no ties for rounding to break either way.
+    Ok(match stump.toggle() {
+        Sign::Positive => OpenCvNode {
+            feature,
+            threshold: (stump.threshold() as f64 - 0.5) / norm,
+            left: non_face,
+            right: face,
+        },
+        Sign::Negative => OpenCvNode {
+            feature,
+            threshold: (stump.threshold() as f64 + 0.5) / norm,
+            left: face,
+            right: non_face,
+        },
+    })
+}
+
+/// Append a decision tree's nodes in preorder, so branches lead to later nodes,
+/// returning the branch to its root. Leaves are scaled by the tree's weight.
+fn export_tree_node(
+    node: &TreeNode,
+    alpha: f64,
+    norm: f64,
+    nodes: &mut Vec<OpenCvNode>,
+) -> Result<Branch, String> {
+    match node {
+        TreeNode::Leaf(value) => Ok(Branch::Leaf(alpha * value)),
+        TreeNode::Split {
+            stump,
+            face,
+            non_face,
+        } => {
+            let index = nodes.len();
+            nodes.push(export_stump(
+                stump,
+                norm,
+                Branch::Leaf(0.),
+                Branch::Leaf(0.),
+            )?);
+            let face = export_tree_node(face, alpha, norm, nodes)?;
+            let non_face = export_tree_node(non_face, alpha, norm, nodes)?;
+            nodes[index] = export_stump(stump, norm, face, non_face)?;
+            Ok(Branch::Node(index))
+        }
+    }
+}
+
+/// Convert a weak learner with its weight to a tree whose leaves carry the weight.
+fn export_learner(learner: &WeakLearner, alpha: f64, norm: f64) -> Result<OpenCvTree, String> {
+    match learner {
+        // Stumps vote +/- alpha
+        WeakLearner::Stump(stump) => Ok(OpenCvTree {
+            nodes: vec![export_stump(
+                stump,
+                norm,
+                Branch::Leaf(alpha),
+                Branch::Leaf(-alpha),
+            )?],
+        }),
+        WeakLearner::Tree(tree) => {
+            let mut nodes = Vec::new();
+            if let Branch::Leaf(value) = export_tree_node(tree.root(), alpha, norm, &mut nodes)? {
+                // A tree that never split always scores its root's value
+                return Ok(OpenCvTree {
+                    nodes: vec![OpenCvNode {
+                        feature: OpenCvFeature {
+                            rects: vec![WeightedRect {
+                                x: 0,
+                                y: 0,
+                                w: 1,
+                                h: 1,
+                                weight: 0.,
+                            }],
+                            tilted: false,
+                        },
+                        threshold: 0.,
+                        left: Branch::Leaf(value),
+                        right: Branch::Leaf(value),
+                    }],
+                });
+            }
+            Ok(OpenCvTree { nodes })
+        }
+        WeakLearner::Lbp(_) => Err("MB-LBP lookup tables can't be exported".to_string()),
+        WeakLearner::OpenCv(tree) => {
+            let mut tree = tree.clone();
+            for node in &mut tree.nodes {
+                for branch in &mut [&mut node.left, &mut node.right] {
+                    if let Branch::Leaf(value) = branch {
+                        *value *= alpha;
+                    }
+                }
+            }
+            Ok(tree)
+        }
+    }
+}
+
+/// Convert stages to stages of OpenCV trees. Trained stages need calibration
+/// faces: thresholds are divided by the median normalization of the faces, so
+/// exported stages decide as trained only on windows normalized by exactly that,
+/// and otherwise as if the window's contrast were scaled to it. Stages scoring
+/// stumps by their margins (which OpenCV's constant leaves can't) have their
+/// thresholds set again to pass the same share of the faces as in training.
+/// Imported stages are kept as they are.
+fn export_stages(
+    stages: &[StrongClassifier],
+    calibration: &Vec<(IntegralImages, Classification)>,
+) -> Result<Vec<StrongClassifier>, String> {
+    let trained = stages.iter().any(|stage| {
+        stage
+            .classifiers
+            .iter()
+            .any(|learner| !matches!(learner, WeakLearner::OpenCv(_)))
+    });
+    let norm = if trained {
+        if calibration.is_empty() {
+            return Err("exporting a trained cascade needs calibration faces".to_string());
+        }
+        let mut norms: Vec<f64> = calibration
+            .iter()
+            .map(|(img, _)| variance_norm(&img.view(), 1.))
+            .collect();
+        norms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        norms[norms.len() / 2]
+    } else {
+        1.
+    };
+
+    let mut exported = Vec::with_capacity(stages.len());
+    for stage in stages {
+        let trees = stage
+            .classifiers
+            .iter()
+            .zip(&stage.weights)
+            .map(|(learner, alpha)| export_learner(learner, *alpha, norm).map(WeakLearner::OpenCv))
+            .collect::<Result<Vec<WeakLearner>, String>>()?;
+        let weights = vec![1.; trees.len()];
+        let mut exported_stage = StrongClassifier::from_parts(trees, weights, stage.threshold());
+
+        let has_stumps = stage
+            .classifiers
+            .iter()
+            .any(|learner| matches!(learner, WeakLearner::Stump(_)));
+        if has_stumps && stage.stump_score == StumpScore::Margin {
+            exported_stage.update_threshold(calibration);
+        }
+        exported.push(exported_stage);
+    }
+
+    Ok(exported)
+}
+
+/// Numbers in exponent notation, which OpenCV always reads as reals and Rust
+/// prints with as many digits as it takes to read back exactly.
+fn real(value: f64) -> String {
+    format!("{:e}", value)
+}
+
+/// Write stages of OpenCV trees in the `opencv-cascade-classifier` format.
+fn write_xml(side: usize, stages: &[StrongClassifier]) -> String {
+    let mut features = Vec::new();
+    let mut xml = String::new();
+    let tilted = stages.iter().any(|stage| {
+        stage.classifiers.iter().any(|learner| match learner {
+            WeakLearner::OpenCv(tree) => tree.nodes.iter().any(|node| node.feature.tilted),
+            _ => false,
+        })
+    });
+    let max_weak_count = stages
+        .iter()
+        .map(|stage| stage.classifiers.len())
+        .max()
+        .unwrap_or(0);
+
+    // Writing to a String can't fail
+    let _ = write!(
+        xml,
+        "<?xml version=\"1.0\"?>\n<opencv_storage>\n\
+         <cascade type_id=\"opencv-cascade-classifier\"><stageType>BOOST</stageType>\n  \
+         <featureType>HAAR</featureType>\n  <height>{side}</height>\n  <width>{side}</width>\n  \
+         <stageParams>\n    <maxWeakCount>{}</maxWeakCount></stageParams>\n  \
+         <featureParams>\n    <maxCatCount>0</maxCatCount>\n    <featSize>1</featSize>\n    \
+         <mode>{}</mode></featureParams>\n  <stageNum>{}</stageNum>\n  <stages>\n",
+        max_weak_count,
+        if tilted { "ALL" } else { "BASIC" },
+        stages.len(),
+        side = side
+    );
+
+    for (i, stage) in stages.iter().enumerate() {
+        let _ = write!(
+            xml,
+            "    <!-- stage {} -->\n    <_>\n      <maxWeakCount>{}</maxWeakCount>\n      \
+             <stageThreshold>{}</stageThreshold>\n      <weakClassifiers>\n",
+            i,
+            stage.classifiers.len(),
+            // OpenCV loosens the threshold again when loading it
+            real(stage.threshold() + THRESHOLD_EPS)
+        );
+        for learner in &stage.classifiers {
+            let tree = match learner {
+                WeakLearner::OpenCv(tree) => tree,
+                _ => unreachable!("Stages are exported as OpenCV trees"),
+            };
+
+            // Nodes are referred to by index, and leaves by their negated index
+            let mut internal = Vec::new();
+            let mut leaves = Vec::new();
+            for node in &tree.nodes {
+                let mut index = |branch: Branch| match branch {
+                    Branch::Node(next) => next.to_string(),
+                    Branch::Leaf(value) => {
+                        leaves.push(real(value));
+                        format!("{}", -(leaves.len() as i64 - 1))
+                    }
+                };
+                let (left, right) = (index(node.left), index(node.right));
+                internal.push(format!(
+                    "{} {} {} {}",
+                    left,
+                    right,
+                    features.len(),
+                    real(node.threshold)
+                ));
+                features.push(&node.feature);
+            }
+            let _ = write!(
+                xml,
+                "        <_>\n          <internalNodes>\n            {}</internalNodes>\n          \
+                 <leafValues>\n            {}</leafValues></_>\n",
+                internal.join("\n            "),
+                leaves.join(" ")
+            );
+        }
+        xml.push_str("      </weakClassifiers></_>\n");
+    }
+
+    xml.push_str("  </stages>\n  <features>\n");
+    for feature in features {
+        xml.push_str("    <_>\n      <rects>\n");
+        for r in &feature.rects {
+            let _ = writeln!(
+                xml,
+                "        <_>\n          {} {} {} {} {}</_>",
+                r.x,
+                r.y,
+                r.w,
+                r.h,
+                real(r.weight)
+            );
+        }
+        let _ = writeln!(
+            xml,
+            "      </rects>\n      <tilted>{}</tilted></_>",
+            feature.tilted as u8
+        );
+    }
+    xml.push_str("  </features></cascade>\n</opencv_storage>\n");
+
+    xml
+}
+
+/// Write a saved cascade as an OpenCV cascade for `CascadeClassifier`. Trained
+/// cascades need a directory of faces to calibrate against (see `export_stages`),
+/// since OpenCV normalizes each window where they were trained on raw ones, and
+/// can't use MB-LBP features or photometric normalization. Returns the number of
+/// stages.
+pub fn export_cascade(
+    saved_cascade_path: &str,
+    xml_path: &str,
+    calibration_faces_dir: Option<&str>,
+) -> Result<usize, String> {
+    let model = CascadeModel::load(saved_cascade_path)?;
+    let (stages, options) = (model.stages, model.metadata.preprocess.unwrap_or_default());
+    if options.photometric.is_some() {
+        return Err("OpenCV can't normalize windows photometrically".to_string());
+    }
+
+    let calibration = match calibration_faces_dir {
+        Some(dir) => load_imgs_from_dir(dir, &options)
+            .iter()
+            .map(|face| (IntegralImages::with_squares(face), Classification::Face))
+            .collect(),
+        None => Vec::new(),
+    };
+    let exported = export_stages(&stages, &calibration)?;
+    fs::write(xml_path, write_xml(options.window_size, &exported))
+        .map_err(|err| format!("{}: {}", xml_path, err))?;
+
+    Ok(exported.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision_tree::DecisionTree;
+    use crate::features::HaarFeature;
+    use crate::lbp::MbLbpFeature;
+    use crate::manifest::Split;
+    use crate::{Learner, Matrix};
+    use image::{GrayImage, Luma};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    // A 4x4 cascade of one stage with a stump on a left-right edge and a two-node
+    // tree on a tilted feature, in both formats
+    const OLD_FORMAT: &str = r#"<?xml version="1.0"?>
+<opencv_storage>
+<test_cascade type_id="opencv-haar-classifier">
+  <size>4 4</size>
+  <stages>
+    <_>
+      <!-- stage 0 -->
+      <trees>
+        <_>
+          <_>
+            <feature>
+              <rects>
+                <_>0 0 2 4 -1.</_>
+                <_>2 0 2 4 1.</_></rects>
+              <tilted>0</tilted></feature>
+            <threshold>0.1</threshold>
+            <left_val>-1.</left_val>
+            <right_val>1.</right_val></_></_>
+        <_>
+          <_>
+            <feature>
+              <rects>
+                <_>2 0 1 1 1.</_></rects>
+              <tilted>1</tilted></feature>
+            <threshold>0.</threshold>
+            <left_val>-0.5</left_val>
+            <right_node>1</right_node></_>
+          <_>
+            <feature>
+              <rects>
+                <_>0 0 4 4 1.</_></rects>
+              <tilted>0</tilted></feature>
+            <threshold>100.</threshold>
+            <left_val>0.25</left_val>
+            <right_val>0.5</right_val></_></_></trees>
+      <stage_threshold>1.</stage_threshold>
+      <parent>-1</parent>
+      <next>-1</next></_></stages></test_cascade>
+</opencv_storage>
+"#;
+
+    const NEW_FORMAT: &str = r#"<?xml version="1.0"?>
+<opencv_storage>
+<cascade type_id="opencv-cascade-classifier"><stageType>BOOST</stageType>
+  <featureType>HAAR</featureType>
+  <height>4</height>
+  <width>4</width>
+  <stageNum>1</stageNum>
+  <stages>
+    <_>
+      <maxWeakCount>2</maxWeakCount>
+      <stageThreshold>1.</stageThreshold>
+      <weakClassifiers>
+        <_>
+          <internalNodes>
+            0 -1 0 1.0000000000000001e-01</internalNodes>
+          <leafValues>
+            -1. 1.</leafValues></_>
+        <_>
+          <internalNodes>
+            0 1 1 0.
+            -1 -2 2 100.</internalNodes>
+          <leafValues>
+            -0.5 0.25 0.5</leafValues></_></weakClassifiers></_></stages>
+  <features>
+    <_>
+      <rects>
+        <_>
+          0 0 2 4 -1.</_>
+        <_>
+          2 0 2 4 1.</_></rects>
+      <tilted>0</tilted></_>
+    <_>
+      <rects>
+        <_>
+          2 0 1 1 1.</_></rects>
+      <tilted>1</tilted></_>
+    <_>
+      <rects>
+        <_>
+          0 0 4 4 1.</_></rects>
+      <tilted>0</tilted></_></features></cascade>
+</opencv_storage>
+"#;
+
+    fn passes(stages: &[StrongClassifier], img: &Matrix) -> bool {
+        let integral = IntegralImages::with_squares(img);
+        stages
+            .iter()
+            .all(|stage| stage.evaluate(&integral.view()) == Classification::Face)
+    }
+
+    #[test]
+    fn both_formats_import_the_same_cascade() {
+        let (old_side, old) = parse_cascade(OLD_FORMAT).unwrap();
+        let (new_side, new) = parse_cascade(NEW_FORMAT).unwrap();
+        assert!(old_side == 4 && new_side == 4);
+        assert!(old.len() == 1 && new.len() == 1);
+        for (a, b) in old[0].classifiers.iter().zip(&new[0].classifiers) {
+            match (a, b) {
+                (WeakLearner::OpenCv(a), WeakLearner::OpenCv(b)) => assert!(a == b),
+                _ => panic!("Imported trees should be OpenCV trees"),
+            }
+        }
+    }
+
+    #[test]
+    fn imported_cascades_normalize_variance() {
+        let (_, stages) = parse_cascade(NEW_FORMAT).unwrap();
+
+        // Brighter on the right passes the edge stump, and with the tree scores 1.25;
+        // brighter on the left fails it
+        let right = Matrix::from_shape_fn((4, 4), |(_, x)| if x < 2 { 10 } else { 20 });
+        let left = Matrix::from_shape_fn((4, 4), |(_, x)| if x < 2 { 20 } else { 10 });
+        assert!(passes(&stages, &right));
+        assert!(!passes(&stages, &left));
+
+        // Thresholds are relative to the contrast, so stretching it changes nothing
+        assert!(passes(&stages, &(right.clone() * 5)));
+
+        // Flat windows have no variance; the stump's threshold is then absolute
+        assert!(!passes(&stages, &Matrix::from_elem((4, 4), 50)));
+    }
+
+    #[test]
+    // Imported cascades evaluate on manifest windows, which carry their squares
+    fn imported_cascades_evaluate_on_manifests() {
+        let dir = crate::util::test_dir("opencv-manifest");
+        let (xml, saved) = (dir.join("cascade.xml"), dir.join("cascade.json"));
+        fs::write(&xml, NEW_FORMAT).unwrap();
+        import_cascade(xml.to_str().unwrap(), saved.to_str().unwrap()).unwrap();
+
+        let two_tone = |bright_right: bool| {
+            GrayImage::from_fn(4, 4, |x, _| {
+                Luma([if (x >= 2) == bright_right { 20 } else { 10 }])
+            })
+        };
+        two_tone(true).save(dir.join("right.png")).unwrap();
+        two_tone(false).save(dir.join("left.png")).unwrap();
+        let manifest = dir.join("manifest.csv");
+        fs::write(
+            &manifest,
+            "path,label,x,y,w,h,weight,split\n\
+             right.png,face,,,,,,test\n\
+             left.png,face,,,,,,test\n\
+             left.png,nonface,,,,,,test\n",
+        )
+        .unwrap();
+
+        let rates = Learner::evaluate_manifest(
+            saved.to_str().unwrap(),
+            manifest.to_str().unwrap(),
+            Split::Test,
+            &PreprocessOptions::default(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(rates == (0.5, 0.));
+    }
+
+    #[test]
+    fn unsupported_cascades_are_rejected() {
+        let wide = NEW_FORMAT.replace("<width>4</width>", "<width>8</width>");
+        assert!(parse_cascade(&wide).unwrap_err().contains("8x4"));
+
+        let lbp = NEW_FORMAT.replace(">HAAR<", ">LBP<");
+        assert!(parse_cascade(&lbp).is_err());
+
+        let outside = NEW_FORMAT.replace("0 0 4 4 1.", "1 0 4 4 1.");
+        assert!(parse_cascade(&outside).is_err());
+
+        let looping =
+            OLD_FORMAT.replace("<right_node>1</right_node>", "<right_node>0</right_node>");
+        assert!(parse_cascade(&looping).is_err());
+
+        assert!(parse_cascade("<opencv_storage><x/></opencv_storage>").is_err());
+    }
+
+    fn trees(stages: &[StrongClassifier]) -> Vec<&OpenCvTree> {
+        stages
+            .iter()
+            .flat_map(|stage| stage.classifiers.iter())
+            .map(|learner| match learner {
+                WeakLearner::OpenCv(tree) => tree,
+                _ => panic!("Exported stages should hold OpenCV trees"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imported_cascades_export_unchanged() {
+        let (side, stages) = parse_cascade(OLD_FORMAT).unwrap();
+        let exported = export_stages(&stages, &Vec::new()).unwrap();
+        let (round_side, round_trip) = parse_cascade(&write_xml(side, &exported)).unwrap();
+
+        assert!(round_side == 4);
+        assert!(trees(&round_trip) == trees(&stages));
+        assert!((round_trip[0].threshold() - stages[0].threshold()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn trained_stumps_export_as_votes() {
+        let edge = HaarFeature::new(HaarFeatureType::TwoHorizontal, 2, 4, 0, 0);
+        let line = HaarFeature::new(HaarFeatureType::TwoVertical, 4, 2, 0, 0);
+        let stump = |feature, threshold, toggle| {
+            WeakLearner::Stump(WeakClassifier::new(
+                &AnyFeature::Haar(feature),
+                threshold,
+                toggle,
+            ))
+        };
+        let mut votes = StrongClassifier::from_parts(
+            vec![
+                stump(edge, 40, Sign::Positive),
+                stump(line, -40, Sign::Negative),
+            ],
+            vec![0.7, 0.3],
+            0.,
+        );
+        votes.stump_score = StumpScore::Vote;
+        let stages = vec![votes];
+
+        // Every window has the same contrast in its centre, so the same norm
+        let two_tone = |bright: &dyn Fn(usize, usize) -> bool| {
+            IntegralImages::with_squares(&Matrix::from_shape_fn((4, 4), |(y, x)| {
+                if bright(y, x) {
+                    20
+                } else {
+                    10
+                }
+            }))
+        };
+        let faces: Vec<(IntegralImages, Classification)> = vec![
+            two_tone(&|_, x| x >= 2),
+            two_tone(&|_, x| x < 2),
+            two_tone(&|y, _| y >= 2),
+            two_tone(&|y, _| y < 2),
+        ]
+        .into_iter()
+        .map(|img| (img, Classification::Face))
+        .collect();
+        let exported = export_stages(&stages, &faces).unwrap();
+
+        let exported_trees = trees(&exported);
+        assert!(exported_trees[0].nodes[0].feature.rects[0].weight == -1.);
+        assert!(exported_trees[0].nodes[0].threshold == 39.5 / 20.);
+        assert!(exported_trees[0].nodes[0].right == Branch::Leaf(0.7));
+        for (img, _) in &faces {
+            for (stump, tree) in stages[0].classifiers.iter().zip(&exported[0].classifiers) {
+                assert!(stump.evaluate(&img.view()) == tree.evaluate(&img.view()));
+            }
+            // Stumps that vote in their stage too keep its threshold
+            assert!(exported[0].evaluate(&img.view()) == stages[0].evaluate(&img.view()));
+        }
+        assert!(exported[0].threshold() == stages[0].threshold());
+
+        // Stages scoring stump margins are recalibrated on votes to pass every face,
+        // as they did
+        let mut margins = stages[0].clone();
+        margins.stump_score = StumpScore::Margin;
+        let recalibrated = export_stages(&[margins], &faces).unwrap();
+        for (img, _) in &faces {
+            assert!(recalibrated[0].evaluate(&img.view()) == Classification::Face);
+        }
+
+        let (_, round_trip) = parse_cascade(&write_xml(4, &exported)).unwrap();
+        assert!(trees(&round_trip) == exported_trees);
+        assert!((round_trip[0].threshold() - exported[0].threshold()).abs() < 1e-12);
+
+        // Trained stages can't be exported without faces to calibrate them on
+        assert!(export_stages(&stages, &Vec::new()).is_err());
+    }
+
+    #[test]
+    fn features_export_as_weighted_rects() {
+        let surround = HaarFeature::new(HaarFeatureType::CenterSurround, 1, 1, 0, 0);
+        let rects = export_feature(&AnyFeature::Haar(surround)).unwrap().rects;
+        let weights: Vec<f64> = rects.iter().map(|r| r.weight).collect();
+        assert!(weights == vec![-1., 2.]);
+        assert!((rects[1].x, rects[1].y, rects[1].w, rects[1].h) == (1, 1, 1, 1));
+
+        // Checkerboards fit in three rectangles and sum the same
+        let checkerboard = HaarFeature::new(HaarFeatureType::TwoByTwo, 2, 1, 1, 2);
+        let exported = export_feature(&AnyFeature::Haar(checkerboard)).unwrap();
+        let weights: Vec<f64> = exported.rects.iter().map(|r| r.weight).collect();
+        assert!(weights == vec![1., -2., -2.] && !exported.tilted);
+        let img = IntegralImages::from_image(&Matrix::from_shape_fn((6, 6), |(y, x)| {
+            ((y * 6 + x) * 37 % 256) as i64
+        }));
+        assert!(
+            exported.evaluate_scaled(&img.view(), 1.) == checkerboard.evaluate(&img.view()) as f64
+        );
+
+        let tilted = HaarFeature::new(HaarFeatureType::TiltedTwoVertical, 2, 1, 3, 0);
+        let feature = export_feature(&AnyFeature::Haar(tilted)).unwrap();
+        assert!(feature.tilted && feature.rects.len() == 2);
+
+        let lbp = AnyFeature::Lbp(MbLbpFeature::new(1, 1, 0, 0));
+        assert!(export_feature(&lbp).is_err());
+    }
+
+    #[test]
+    // Exported trees compare features with thresholds scaled by each window's norm,
+    // so on windows normalized like the calibration faces they decide as trained
+    fn exported_stages_decide_like_trained_ones_at_the_calibrated_norm() {
+        let haar =
+            |feature_type, w, h, x, y| AnyFeature::Haar(HaarFeature::new(feature_type, w, h, x, y));
+        let stump = |feature, threshold, toggle| WeakClassifier::new(&feature, threshold, toggle);
+        let tree = DecisionTree::from_parts(
+            2,
+            TreeNode::Split {
+                stump: stump(
+                    haar(HaarFeatureType::TwoByTwo, 2, 2, 2, 2),
+                    0,
+                    Sign::Positive,
+                ),
+                face: Box::new(TreeNode::Split {
+                    stump: stump(
+                        haar(HaarFeatureType::TiltedTwoHorizontal, 2, 2, 4, 0),
+                        -20,
+                        Sign::Negative,
+                    ),
+                    face: Box::new(TreeNode::Leaf(0.75)),
+                    non_face: Box::new(TreeNode::Leaf(-0.25)),
+                }),
+                non_face: Box::new(TreeNode::Leaf(-1.)),
+            },
+        );
+        let mut stages = vec![
+            StrongClassifier::from_parts(
+                vec![
+                    WeakLearner::Stump(stump(
+                        haar(HaarFeatureType::TwoHorizontal, 2, 4, 1, 1),
+                        30,
+                        Sign::Positive,
+                    )),
+                    WeakLearner::Stump(stump(
+                        haar(HaarFeatureType::ThreeVertical, 4, 2, 2, 1),
+                        -10,
+                        Sign::Negative,
+                    )),
+                    WeakLearner::Tree(tree),
+                ],
+                vec![0.5, 0.3, 0.4],
+                0.,
+            ),
+            StrongClassifier::from_parts(
+                vec![WeakLearner::Stump(stump(
+                    haar(HaarFeatureType::CenterSurround, 1, 1, 2, 3),
+                    -900,
+                    Sign::Negative,
+                ))],
+                vec![1.],
+                0.,
+            ),
+        ];
+        // Stages scoring stump margins are recalibrated rather than kept
+        for stage in &mut stages {
+            stage.stump_score = StumpScore::Vote;
+        }
+
+        // Every window's inner pixels are the same values shuffled, so every window
+        // has the same norm
+        let mut rng = StdRng::seed_from_u64(5);
+        let inner: Vec<i64> = (0..36).map(|_| rng.gen_range(0, 256)).collect();
+        let window = |rng: &mut StdRng| {
+            let mut values = inner.clone();
+            values.shuffle(rng);
+            IntegralImages::with_squares(&Matrix::from_shape_fn((8, 8), |(y, x)| {
+                if (1..7).contains(&y) && (1..7).contains(&x) {
+                    values[(y - 1) * 6 + x - 1]
+                } else {
+                    rng.gen_range(0, 256)
+                }
+            }))
+        };
+        let faces: Vec<(IntegralImages, Classification)> = (0..3)
+            .map(|_| (window(&mut rng), Classification::Face))
+            .collect();
+        let exported = export_stages(&stages, &faces).unwrap();
+
+        let mut accepted = vec![0; stages.len()];
+        for _ in 0..200 {
+            let img = window(&mut rng);
+            for (s, stage) in stages.iter().enumerate() {
+                let decision = stage.evaluate(&img.view());
+                assert!(exported[s].evaluate(&img.view()) == decision);
+                if decision == Classification::Face {
+                    accepted[s] += 1;
+                }
+            }
+        }
+        // Both stages accept some windows and reject others
+        assert!(accepted.iter().all(|n| 0 < *n && *n < 200));
+    }
+}
@@ -0,0 +1,218 @@
+/// Photometric normalizations that make training and detection less sensitive to
+/// lighting, applied to whole images or to each window.
+use super::Matrix;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Spread the gray levels so that their cumulative histogram is linear.
+    HistogramEqualization,
+    /// Contrast-limited adaptive histogram equalization: equalize each tile of a
+    /// `tiles` x `tiles` grid, with bins clipped at `clip_limit` times the mean bin
+    /// count, interpolating between the tiles' mappings.
+    Clahe { tiles: usize, clip_limit: f64 },
+    /// The difference of two Gaussian blurs, a band-pass filter that removes slow
+    /// changes of illumination, stretched back to [0, 255].
+    DifferenceOfGaussians { sigma_inner: f64, sigma_outer: f64 },
+}
+
+/// Whether a normalization applies to each (training or detection) window, or to
+/// the whole image windows are taken from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalizationScope {
+    Image,
+    Window,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhotometricOptions {
+    pub normalization: Normalization,
+    pub scope: NormalizationScope,
+}
+
+impl Normalization {
+    pub fn apply(&self, img: &Matrix) -> Matrix {
+        match *self {
+            Normalization::HistogramEqualization => equalize(img),
+            Normalization::Clahe { tiles, clip_limit } => clahe(img, tiles, clip_limit),
+            Normalization::DifferenceOfGaussians {
+                sigma_inner,
+                sigma_outer,
+            } => difference_of_gaussians(img, sigma_inner, sigma_outer),
+        }
+    }
+}
+
+fn histogram<'a, I: Iterator<Item = &'a i64>>(pixels: I) -> [f64; 256] {
+    let mut hist = [0.; 256];
+    for px in pixels {
+        hist[(*px).clamp(0, 255) as usize] += 1.;
+    }
+
+    hist
+}
+
+/// The gray level each level maps to when equalizing a histogram.
+fn equalization_map(hist: &[f64; 256]) -> [f64; 256] {
+    let total: f64 = hist.iter().sum();
+    let mut cdf = [0.; 256];
+    let mut sum = 0.;
+    for (level, count) in hist.iter().enumerate() {
+        sum += count;
+        cdf[level] = sum;
+    }
+
+    // Map the darkest level present to 0, as the usual definition does
+    let cdf_min = cdf.iter().cloned().find(|c| *c > 0.).unwrap_or(0.);
+    let mut map = [0.; 256];
+    for (level, mapped) in map.iter_mut().enumerate() {
+        *mapped = if total > cdf_min {
+            255. * (cdf[level] - cdf_min).max(0.) / (total - cdf_min)
+        } else {
+            level as f64
+        };
+    }
+
+    map
+}
+
+fn equalize(img: &Matrix) -> Matrix {
+    let map = equalization_map(&histogram(img.iter()));
+    img.mapv(|px| map[px.clamp(0, 255) as usize].round() as i64)
+}
+
+/// Clip the bins of a histogram at `limit`, spreading the excess evenly over all
+/// bins as in Zuiderveld's CLAHE.
+fn clip_histogram(hist: &mut [f64; 256], limit: f64) {
+    let excess: f64 = hist.iter().map(|count| (count - limit).max(0.)).sum();
+    for count in hist.iter_mut() {
+        *count = count.min(limit) + excess / 256.;
+    }
+}
+
+fn clahe(img: &Matrix, tiles: usize, clip_limit: f64) -> Matrix {
+    let (rows, cols) = img.dim();
+    let tiles = tiles.clamp(1, rows.min(cols).max(1));
+    let (tile_h, tile_w) = (rows as f64 / tiles as f64, cols as f64 / tiles as f64);
+
+    let mut maps = Vec::with_capacity(tiles * tiles);
+    for ty in 0..tiles {
+        for tx in 0..tiles {
+            let (y0, y1) = (ty * rows / tiles, (ty + 1) * rows / tiles);
+            let (x0, x1) = (tx * cols / tiles, (tx + 1) * cols / tiles);
+            let tile = img.slice(s![y0..y1, x0..x1]);
+            let mut hist = histogram(tile.iter());
+            clip_histogram(&mut hist, (clip_limit * tile.len() as f64 / 256.).max(1.));
+            maps.push(equalization_map(&hist));
+        }
+    }
+
+    // Each pixel blends the mappings of the (up to) four tiles whose centres
+    // surround it
+    let neighbours = |pos: f64, tile_len: f64| {
+        let t = (pos + 0.5) / tile_len - 0.5;
+        let t = t.clamp(0., (tiles - 1) as f64);
+        let t0 = t.floor() as usize;
+        (t0, (t0 + 1).min(tiles - 1), t - t0 as f64)
+    };
+    Matrix::from_shape_fn((rows, cols), |(y, x)| {
+        let level = img[[y, x]].clamp(0, 255) as usize;
+        let (ty0, ty1, fy) = neighbours(y as f64, tile_h);
+        let (tx0, tx1, fx) = neighbours(x as f64, tile_w);
+        let map = |ty: usize, tx: usize| maps[ty * tiles + tx][level];
+
+        let top = map(ty0, tx0) * (1. - fx) + map(ty0, tx1) * fx;
+        let bottom = map(ty1, tx0) * (1. - fx) + map(ty1, tx1) * fx;
+        (top * (1. - fy) + bottom * fy).round() as i64
+    })
+}
+
+/// A normalized Gaussian kernel spanning three standard deviations either way.
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = (3. * sigma).ceil().max(0.) as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2. * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| k / sum).collect()
+}
+
+/// Blur an image with a separable Gaussian, replicating its edges.
+fn gaussian_blur(img: &ndarray::Array2<f64>, sigma: f64) -> ndarray::Array2<f64> {
+    if sigma <= 0. {
+        return img.clone();
+    }
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as i64;
+    let (rows, cols) = img.dim();
+    let clamp = |i: i64, len: usize| i.clamp(0, len as i64 - 1) as usize;
+
+    let horizontal = ndarray::Array2::from_shape_fn((rows, cols), |(y, x)| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(k, w)| w * img[[y, clamp(x as i64 + k as i64 - radius, cols)]])
+            .sum::<f64>()
+    });
+    ndarray::Array2::from_shape_fn((rows, cols), |(y, x)| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(k, w)| w * horizontal[[clamp(y as i64 + k as i64 - radius, rows), x]])
+            .sum::<f64>()
+    })
+}
+
+fn difference_of_gaussians(img: &Matrix, sigma_inner: f64, sigma_outer: f64) -> Matrix {
+    let img = img.mapv(|px| px as f64);
+    let dog = gaussian_blur(&img, sigma_inner) - gaussian_blur(&img, sigma_outer);
+
+    let min = dog.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = dog.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    // Flat images have no contrast to stretch
+    if max - min < 1e-9 {
+        return dog.mapv(|_| 128);
+    }
+    dog.mapv(|v| (255. * (v - min) / (max - min)).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> Matrix {
+        Matrix::from_shape_fn((16, 16), |(y, x)| (40 + 2 * x + y) as i64)
+    }
+
+    #[test]
+    fn equalization_stretches_levels() {
+        let img = array![[10, 10], [20, 30]];
+        assert!(equalize(&img) == array![[0, 0], [128, 255]]);
+
+        let flat = Matrix::from_elem((3, 3), 77);
+        assert!(equalize(&flat) == flat);
+    }
+
+    #[test]
+    fn clahe_with_one_unclipped_tile_is_equalization() {
+        let img = ramp();
+        assert!(clahe(&img, 1, 256.) == equalize(&img));
+
+        let out = clahe(&img, 4, 2.);
+        assert!(out.dim() == img.dim());
+        assert!(out.iter().all(|px| 0 <= *px && *px <= 255));
+        // Neighbouring pixels keep their order along the ramp
+        assert!(out[[8, 0]] <= out[[8, 15]]);
+    }
+
+    #[test]
+    fn difference_of_gaussians_ignores_offsets() {
+        let img = ramp() * 3 % 50;
+        let dog = difference_of_gaussians(&img, 1., 2.);
+        assert!(dog == difference_of_gaussians(&(img + 100), 1., 2.));
+        assert!(dog.iter().any(|px| *px == 0) && dog.iter().any(|px| *px == 255));
+
+        let flat = Matrix::from_elem((4, 4), 9);
+        assert!(difference_of_gaussians(&flat, 1., 2.) == Matrix::from_elem((4, 4), 128));
+    }
+}
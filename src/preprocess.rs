@@ -1,16 +1,173 @@
 /// Functions for loading the pre-processing data
 extern crate image;
 
+use super::augment::{augment_positives, AugmentOptions};
+use super::integral::{AnyIntegralImage, AnyIntegralImageView};
+use super::negatives::{fits_window, sample_negatives, NegativeSampling};
+use super::photometric::{NormalizationScope, PhotometricOptions};
 use super::{Classification, Matrix};
-use image::{DynamicImage, GenericImageView};
+use image::{
+    imageops, ColorType, DynamicImage, FilterType, GrayImage, ImageDecoder, ImageFormat,
+    ImageResult, Luma,
+};
 use ndarray::Array;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Luma coefficients for converting colour images to grayscale.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LumaStandard {
+    /// ITU-R BT.601, as used by JPEG and most decoders.
+    Rec601,
+    /// ITU-R BT.709, for sRGB and HD sources.
+    Rec709,
+}
+
+impl LumaStandard {
+    /// The (red, green, blue) weights.
+    fn weights(&self) -> [f64; 3] {
+        match self {
+            LumaStandard::Rec601 => [0.299, 0.587, 0.114],
+            LumaStandard::Rec709 => [0.2126, 0.7152, 0.0722],
+        }
+    }
+}
+
+/// How images are turned into matrices, shared by training and detection so that
+/// both see the same pixel values. Options missing from a saved cascade (recorded
+/// before they existed) take their defaults.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreprocessOptions {
+    pub luma: LumaStandard,
+    /// The side length of the square training (and detection) window.
+    pub window_size: usize,
+    /// How training images that aren't window-sized are handled.
+    pub window_fit: WindowFit,
+    /// Augmentation applied to the positives before computing integral images.
+    pub augment: Option<AugmentOptions>,
+    /// Sample negatives from large face-free images in the background directory,
+    /// instead of loading it as window-sized patches.
+    pub negatives: Option<NegativeSampling>,
+    /// Cache the preprocessed training data on disk (in `.viola_jones_cache` under
+    /// the working directory), so that later runs on the same images with the same
+    /// settings load it directly. Off by default.
+    pub cache: bool,
+    /// Lighting normalization, applied identically at training and detection time.
+    pub photometric: Option<PhotometricOptions>,
+}
+
+impl Default for PreprocessOptions {
+    fn default() -> PreprocessOptions {
+        PreprocessOptions {
+            luma: LumaStandard::Rec601,
+            window_size: 64,
+            window_fit: WindowFit::Strict,
+            augment: None,
+            negatives: None,
+            cache: false,
+            photometric: None,
+        }
+    }
+}
+
+/// Interpolation used when resizing training images, as in `image::FilterType`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn filter_type(&self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// How a training image is fitted to the window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowFit {
+    /// Reject images that aren't exactly window-sized.
+    Strict,
+    /// Keep the window-sized centre of the image, rejecting smaller images.
+    CentreCrop,
+    /// Stretch the whole image to the window.
+    Resize(ResizeFilter),
+}
+
+/// Fit a grayscale image to the square window, or explain why it can't be.
+pub fn fit_to_window(img: Matrix, options: &PreprocessOptions) -> Result<Matrix, String> {
+    let side = options.window_size;
+    let (rows, cols) = img.dim();
+    if (rows, cols) == (side, side) {
+        return Ok(img);
+    }
+
+    match options.window_fit {
+        WindowFit::Strict => Err(format!(
+            "image is {}x{} but the window is {}x{}",
+            cols, rows, side, side
+        )),
+        WindowFit::CentreCrop => {
+            if rows < side || cols < side {
+                return Err(format!(
+                    "image is {}x{}, too small to crop a {}x{} window from",
+                    cols, rows, side, side
+                ));
+            }
+            let (top, left) = ((rows - side) / 2, (cols - side) / 2);
+            Ok(img.slice(s![top..top + side, left..left + side]).to_owned())
+        }
+        WindowFit::Resize(filter) => Ok(resize_matrix(&img, side, filter)),
+    }
+}
+
+/// Resize a grayscale image to a square of the given side.
+pub fn resize_matrix(img: &Matrix, side: usize, filter: ResizeFilter) -> Matrix {
+    let (rows, cols) = img.dim();
+    // Pixels are already in [0, 255] after the grayscale conversion
+    let gray = GrayImage::from_fn(cols as u32, rows as u32, |x, y| {
+        Luma([img[[y as usize, x as usize]] as u8])
+    });
+    let resized = imageops::resize(&gray, side as u32, side as u32, filter.filter_type());
+    Matrix::from_shape_fn((side, side), |(y, x)| {
+        resized.get_pixel(x as u32, y as u32).data[0] as i64
+    })
+}
+
+/// Apply the photometric normalization to an image if it has the given scope.
+pub fn normalize(img: Matrix, options: &PreprocessOptions, scope: NormalizationScope) -> Matrix {
+    match options.photometric {
+        Some(photometric) if photometric.scope == scope => photometric.normalization.apply(&img),
+        _ => img,
+    }
+}
+
+/// Normalize every window of a set when normalizing per window.
+pub fn normalize_windows(imgs: Vec<Matrix>, options: &PreprocessOptions) -> Vec<Matrix> {
+    imgs.into_par_iter()
+        .map(|img| normalize(img, options, NormalizationScope::Window))
+        .collect()
+}
 
 /// Take two lists of integral images and flatten them into a list of (img, label) tuples
 fn flatten_to_classlist(
-    integral_faces: Vec<Matrix>,
-    integral_backgrounds: Vec<Matrix>,
-) -> Vec<(Matrix, super::Classification)> {
+    integral_faces: Vec<IntegralImages>,
+    integral_backgrounds: Vec<IntegralImages>,
+) -> Vec<(IntegralImages, super::Classification)> {
     let mut out = Vec::with_capacity(integral_faces.len() + integral_backgrounds.len());
 
     for i in integral_faces {
@@ -26,77 +183,292 @@ fn flatten_to_classlist(
 pub fn load_and_preprocess_data(
     faces_dir: &str,
     background_dir: &str,
-) -> Vec<(Matrix, Classification)> {
-    let faces = load_imgs_from_dir(faces_dir);
-    let backgrounds = load_imgs_from_dir(background_dir);
+    options: &PreprocessOptions,
+) -> Vec<(IntegralImages, Classification)> {
+    let mut faces = load_imgs_from_dir(faces_dir, options);
+    if let Some(augment) = &options.augment {
+        faces = augment_positives(faces, augment);
+        println!("Augmented the training set to {} faces", faces.len());
+    }
+    let backgrounds = match &options.negatives {
+        Some(sampling) => {
+            let (scenes, report) = load_dir(background_dir, options, |img| {
+                fits_window(img, options.window_size)
+            });
+            println!("{}: {}", background_dir, report);
+            let negatives = sample_negatives(&scenes, sampling, options.window_size);
+            println!(
+                "Sampled {} negatives from {} background images",
+                negatives.len(),
+                scenes.len()
+            );
+            negatives
+        }
+        None => load_imgs_from_dir(background_dir, options),
+    };
 
-    let integral_faces = compute_integral_images(faces);
-    let integral_backgrounds = compute_integral_images(backgrounds);
+    let integral_faces = compute_integral_images(normalize_windows(faces, options));
+    let integral_backgrounds = compute_integral_images(normalize_windows(backgrounds, options));
 
     let flattened = flatten_to_classlist(integral_faces, integral_backgrounds);
 
     flattened
 }
 
-/// Load an opened training image into a matrix
-fn img_as_matrix(img: DynamicImage) -> Matrix {
-    // raw_pixels gives a flat vector of the form [r1,g1,b1,r2,g2,b2,...]
-    let raw_pixels = img.raw_pixels();
-    let (w, h) = img.dimensions();
-
-    let out_pixels = match img.color() {
-        image::ColorType::Gray(8) => {
-            assert!(raw_pixels.len() == w as usize * h as usize);
-            img.raw_pixels().iter().map(|x| *x as i64).collect()
-        },
-        image::ColorType::RGB(8) => {
-            assert!(raw_pixels.len() == w as usize * h as usize * 3);
-            let mut out: Vec<i64> = Vec::with_capacity(64 * 64);
-            // Average over the colors (doing integer division)
-            for i in 0..(64 * 64) {
-                let start_ind = i * 3;
-                let mut out_px = 0;
-                out_px += raw_pixels[start_ind] / 3;
-                out_px += raw_pixels[start_ind + 1] / 3;
-                out_px += raw_pixels[start_ind + 2] / 3;
-
-                out.push(out_px as i64);
-            }
-            out
-        },
-        _ => unimplemented!(),
+/// Decoded pixel samples, interleaved by channel, at the bit depth of `color`.
+pub struct Samples {
+    pub color: ColorType,
+    pub w: u32,
+    pub h: u32,
+    pub data: Vec<u16>,
+}
+
+/// The number of bits per channel.
+fn bit_depth(color: ColorType) -> u8 {
+    match color {
+        ColorType::Gray(bits)
+        | ColorType::GrayA(bits)
+        | ColorType::RGB(bits)
+        | ColorType::RGBA(bits)
+        | ColorType::BGR(bits)
+        | ColorType::BGRA(bits)
+        | ColorType::Palette(bits) => bits,
+    }
+}
+
+/// Read 16-bit samples straight from a decoder, since `image` only builds
+/// `DynamicImage`s from 8-bit data. Returns `None` for other bit depths.
+fn read_wide_samples<D: ImageDecoder>(decoder: D) -> ImageResult<Option<Samples>> {
+    let color = decoder.colortype();
+    if bit_depth(color) != 16 {
+        return Ok(None);
+    }
+
+    let (w, h) = decoder.dimensions();
+    let data = decoder
+        .read_image()?
+        .chunks(2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .collect();
+
+    Ok(Some(Samples {
+        color,
+        w: w as u32,
+        h: h as u32,
+        data,
+    }))
+}
+
+/// Detect the format of an encoded image from its first bytes.
+pub fn guess_format(bytes: &[u8]) -> ImageResult<ImageFormat> {
+    // image's signature for big-endian TIFFs is "MM.*" rather than "MM\0*"
+    if bytes.starts_with(b"MM\0*") {
+        return Ok(ImageFormat::TIFF);
+    }
+    image::guess_format(bytes)
+}
+
+/// Decode an encoded image of any supported format and bit depth.
+pub fn decode_samples(bytes: &[u8]) -> ImageResult<Samples> {
+    let format = guess_format(bytes)?;
+
+    // The TIFF and PNM decoders hand out 16-bit samples in native byte order. The
+    // PNG decoder already reduces them to 8 bits, so PNGs take the usual path.
+    let wide = match format {
+        ImageFormat::TIFF => read_wide_samples(image::tiff::TIFFDecoder::new(Cursor::new(bytes))?)?,
+        ImageFormat::PNM => read_wide_samples(image::pnm::PNMDecoder::new(Cursor::new(bytes))?)?,
+        _ => None,
     };
+    if let Some(samples) = wide {
+        return Ok(samples);
+    }
 
-    let pixel_arr = Array::from_vec(out_pixels);
+    Ok(image_samples(&image::load_from_memory_with_format(
+        bytes, format,
+    )?))
+}
 
-    pixel_arr
-        .into_shape((h as usize, w as usize))
+fn image_samples(img: &DynamicImage) -> Samples {
+    let (w, h) = image::GenericImageView::dimensions(img);
+    Samples {
+        color: img.color(),
+        w,
+        h,
+        data: img.raw_pixels().into_iter().map(u16::from).collect(),
+    }
+}
+
+/// Convert samples to grayscale in [0, 255] whatever their bit depth. Alpha
+/// channels are ignored.
+pub fn samples_as_matrix(samples: &Samples, luma: LumaStandard) -> Matrix {
+    // (channels per pixel, offsets of the red, green and blue channels)
+    let (channels, rgb) = match samples.color {
+        ColorType::Gray(_) => (1, None),
+        ColorType::GrayA(_) => (2, None),
+        ColorType::RGB(_) => (3, Some([0, 1, 2])),
+        ColorType::RGBA(_) => (4, Some([0, 1, 2])),
+        ColorType::BGR(_) => (3, Some([2, 1, 0])),
+        ColorType::BGRA(_) => (4, Some([2, 1, 0])),
+        ColorType::Palette(_) => unreachable!("Decoders expand palettes to RGB"),
+    };
+    let scale = 255. / ((1u32 << bit_depth(samples.color)) - 1) as f64;
+    let weights = luma.weights();
+
+    let (w, h) = (samples.w as usize, samples.h as usize);
+    assert!(samples.data.len() == w * h * channels);
+
+    let out_pixels = samples
+        .data
+        .chunks(channels)
+        .map(|px| {
+            let value = match rgb {
+                None => px[0] as f64,
+                Some(offsets) => (0..3).map(|c| weights[c] * px[offsets[c]] as f64).sum(),
+            };
+            (value * scale).round() as i64
+        })
+        .collect();
+
+    Array::from_vec(out_pixels)
+        .into_shape((h, w))
         .expect("Failed to transform pixel array into matrix")
 }
 
-/// Returns a vector of matrices loaded from the input directory
-fn load_imgs_from_dir(dir_name: &str) -> Vec<Matrix> {
-    let imgs = fs::read_dir(dir_name).expect("Data directory not found");
+/// Load an image file as a grayscale matrix, whatever its format or extension.
+pub fn load_image(path: &Path, options: &PreprocessOptions) -> ImageResult<Matrix> {
+    let bytes = fs::read(path)?;
+    let img = samples_as_matrix(&decode_samples(&bytes)?, options.luma);
+    Ok(normalize(img, options, NormalizationScope::Image))
+}
 
-    let mut loaded: Vec<Matrix> = Vec::new();
-    for img_path in imgs {
-        let img_path = img_path
-            .expect("Failed while computing a input file path")
-            .path();
+/// What happened to the files found while loading a directory of images.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub loaded: usize,
+    /// Files that aren't in a supported image format.
+    pub skipped: Vec<PathBuf>,
+    /// Files that looked like images but couldn't be read or decoded.
+    pub failed: Vec<(PathBuf, String)>,
+}
 
-        let ext = img_path.extension();
-        if let None = ext {
-            println!(
-                "Ignoring input file while loading data: {}",
-                img_path.display()
-            );
-            continue;
-        } else if "jpg" == ext.unwrap() {
-            let img = image::open(img_path).expect("Failed to open image");
-            loaded.push(img_as_matrix(img));
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Loaded {} images, skipped {} files, failed on {}",
+            self.loaded,
+            self.skipped.len(),
+            self.failed.len()
+        )?;
+        for path in &self.skipped {
+            write!(f, "\n  skipped {}: not a supported image", path.display())?;
         }
+        for (path, err) in &self.failed {
+            write!(f, "\n  failed {}: {}", path.display(), err)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collect the files under a directory and its subdirectories, sorted by path so
+/// that images load in the same order on every platform.
+pub fn find_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).expect("Data directory not found");
+    for entry in entries {
+        let entry = entry.expect("Failed while computing a input file path");
+        // Symlinked directories aren't followed, so links can't form cycles
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if is_dir {
+            find_files(&entry.path(), files);
+        } else {
+            files.push(entry.path());
+        }
+    }
+}
+
+/// Returns the images found (recursively) under the input directory, detecting
+/// their formats by content and fitting them to the window, with a report of the
+/// files that weren't loaded.
+pub fn load_imgs_from_dir_with_report(
+    dir_name: &str,
+    options: &PreprocessOptions,
+) -> (Vec<Matrix>, LoadReport) {
+    load_dir(dir_name, options, |img| fit_to_window(img, options))
+}
+
+/// What became of a file found while loading a directory.
+enum Loaded {
+    Image(Matrix),
+    Skipped,
+    Failed(String),
+}
+
+/// Read, decode, convert and fit a single file.
+fn load_file<F: Fn(Matrix) -> Result<Matrix, String>>(
+    path: &Path,
+    options: &PreprocessOptions,
+    fit: &F,
+) -> Loaded {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return Loaded::Failed(err.to_string()),
+    };
+    if guess_format(&bytes).is_err() {
+        return Loaded::Skipped;
     }
 
+    let img = match decode_samples(&bytes) {
+        Ok(samples) => normalize(
+            samples_as_matrix(&samples, options.luma),
+            options,
+            NormalizationScope::Image,
+        ),
+        Err(err) => return Loaded::Failed(err.to_string()),
+    };
+    match fit(img) {
+        Ok(img) => Loaded::Image(img),
+        Err(err) => Loaded::Failed(err),
+    }
+}
+
+/// Load the images under a directory, passing each through `fit`, which may reject
+/// it with a reason. Files are decoded in parallel, but the images come out in the
+/// order of their sorted paths.
+fn load_dir<F: Fn(Matrix) -> Result<Matrix, String> + Sync>(
+    dir_name: &str,
+    options: &PreprocessOptions,
+    fit: F,
+) -> (Vec<Matrix>, LoadReport) {
+    let mut paths = Vec::new();
+    find_files(Path::new(dir_name), &mut paths);
+    paths.sort();
+
+    let outcomes: Vec<Loaded> = paths
+        .par_iter()
+        .map(|path| load_file(path, options, &fit))
+        .collect();
+
+    let mut loaded: Vec<Matrix> = Vec::new();
+    let mut report = LoadReport::default();
+    for (path, outcome) in paths.into_iter().zip(outcomes) {
+        match outcome {
+            Loaded::Image(img) => loaded.push(img),
+            Loaded::Skipped => report.skipped.push(path),
+            Loaded::Failed(err) => report.failed.push((path, err)),
+        }
+    }
+    report.loaded = loaded.len();
+
+    (loaded, report)
+}
+
+/// Returns a vector of matrices loaded from the input directory, printing a report
+/// of any files that weren't loaded.
+pub fn load_imgs_from_dir(dir_name: &str, options: &PreprocessOptions) -> Vec<Matrix> {
+    let (loaded, report) = load_imgs_from_dir_with_report(dir_name, options);
+    println!("{}: {}", dir_name, report);
+
     loaded
 }
 
@@ -126,29 +498,133 @@ pub fn compute_integral_image(img: &Matrix) -> Matrix {
     integral
 }
 
-/// Compute the integral images for a set of image matrices
-fn compute_integral_images(imgs: Vec<Matrix>) -> Vec<Matrix> {
-    // Unfortunately ndarray doesn't have something like np's cumsum yet
-    let mut integral_imgs: Vec<Matrix> = Vec::with_capacity(imgs.len());
-    for img in imgs.iter() {
-        integral_imgs.push(compute_integral_image(img));
+/// Compute the 45-degree tilted integral image for a matrix, zero-padded like the
+/// upright one. Entry (y, x) is the sum of the pixels in the triangle with its apex
+/// at pixel (y - 1, x - 1) that widens by a pixel on each side for every row above.
+pub fn compute_tilted_integral_image(img: &Matrix) -> Matrix {
+    let (rows, cols) = img.dim();
+
+    // In the rotated coordinates s = col + row and d = col - row, every triangle is a
+    // quadrant { s <= S, d >= D }. quadrant[[s + 1, d + rows - 1]] holds its sum,
+    // with an empty first row and last column to start the recurrence from.
+    let span = rows + cols - 1;
+    let mut quadrant = Matrix::zeros((span + 1, span + 1));
+    for s in 0..span {
+        for d in (0..span).rev() {
+            // Pixel coordinates are only integral when s and d have the same parity
+            let mut pixel = 0;
+            if s + d + 1 >= rows && (s + d + 1 - rows) % 2 == 0 {
+                let col = (s + d + 1 - rows) / 2;
+                if s >= col && col < cols && s - col < rows {
+                    pixel = img[[s - col, col]];
+                }
+            }
+
+            quadrant[[s + 1, d]] =
+                quadrant[[s, d]] + quadrant[[s + 1, d + 1]] - quadrant[[s, d + 1]] + pixel;
+        }
+    }
+
+    let mut tilted = Matrix::zeros((rows + 1, cols + 1));
+    for y in 1..=rows {
+        for x in 0..=cols {
+            let s = (x + y - 1).min(span);
+            let d = (x + rows - 1).saturating_sub(y);
+            tilted[[y, x]] = quadrant[[s, d]];
+        }
+    }
+
+    tilted
+}
+
+/// The upright and tilted integral images of an image.
+#[derive(Clone)]
+pub struct IntegralImages {
+    pub upright: AnyIntegralImage,
+    pub tilted: AnyIntegralImage,
+    /// The upright integral image of the squared pixels, for the variance of
+    /// windows. Only imported OpenCV cascades need it, so it's left out of training
+    /// samples: see `with_squares`.
+    pub squared: Option<AnyIntegralImage>,
+}
+
+/// Views into both integral images of an image, or of a window within it.
+pub struct IntegralView<'a> {
+    pub upright: AnyIntegralImageView<'a>,
+    pub tilted: AnyIntegralImageView<'a>,
+    pub squared: Option<AnyIntegralImageView<'a>>,
+}
+
+impl IntegralImages {
+    /// Each integral image uses the narrowest accumulator its sums fit in. Panics
+    /// if they don't even fit in an `i64`.
+    pub fn from_image(img: &Matrix) -> IntegralImages {
+        let overflow = |err| panic!("Image too large for its integral image: {}", err);
+        IntegralImages {
+            upright: AnyIntegralImage::upright(img).unwrap_or_else(overflow),
+            tilted: AnyIntegralImage::tilted(img).unwrap_or_else(overflow),
+            squared: None,
+        }
+    }
+
+    /// The integral images along with that of the squared pixels, for detecting
+    /// with any cascade.
+    pub fn with_squares(img: &Matrix) -> IntegralImages {
+        let overflow = |err| panic!("Image too large for its integral image: {}", err);
+        IntegralImages {
+            squared: Some(
+                AnyIntegralImage::upright(&img.mapv(|px| px * px)).unwrap_or_else(overflow),
+            ),
+            ..IntegralImages::from_image(img)
+        }
+    }
+
+    /// The dimensions of the (padded) integral images.
+    pub fn dim(&self) -> (usize, usize) {
+        self.upright.dim()
+    }
+
+    pub fn view(&self) -> IntegralView<'_> {
+        IntegralView {
+            upright: self.upright.view(),
+            tilted: self.tilted.view(),
+            squared: self.squared.as_ref().map(AnyIntegralImage::view),
+        }
+    }
+
+    /// The integral images of a square window of `side_len` pixels with its top-left
+    /// pixel at (y, x), the same size as those of a training window.
+    pub fn window(&self, y: usize, x: usize, side_len: usize) -> IntegralView<'_> {
+        IntegralView {
+            upright: self.upright.window(y, x, side_len),
+            tilted: self.tilted.window(y, x, side_len),
+            squared: self.squared.as_ref().map(|img| img.window(y, x, side_len)),
+        }
     }
+}
 
-    integral_imgs
+/// Compute the integral images for a set of image matrices
+pub fn compute_integral_images(imgs: Vec<Matrix>) -> Vec<IntegralImages> {
+    // Unfortunately ndarray doesn't have something like np's cumsum yet. The
+    // images are independent, so they're computed in parallel (in order).
+    imgs.par_iter().map(IntegralImages::from_image).collect()
 }
 
-/// Returns a set of integral images corresponding to windows in the test
-/// image, and a top-right coordinate in the image.
-pub fn load_test_image(test_img_path: &str) -> (Matrix, Vec<(usize, usize)>) {
-    let test_img = image::open(test_img_path).expect("Failed to open test image");
-    let test_img_mat = img_as_matrix(test_img);
+/// Returns the test image, its integral images and the top-left coordinates of the
+/// windows in it.
+pub fn load_test_image(
+    test_img_path: &str,
+    options: &PreprocessOptions,
+) -> (Matrix, IntegralImages, Vec<(usize, usize)>) {
+    let test_img_mat =
+        load_image(Path::new(test_img_path), options).expect("Failed to open test image");
     // 1280 rows and 1600 columns
     assert!((1280, 1600) == test_img_mat.dim());
 
-    let test_integral = compute_integral_image(&test_img_mat);
-    let sliding_coords = get_sliding_window_coords(1600, 1280, 64, 3);
+    let test_integral = IntegralImages::with_squares(&test_img_mat);
+    let sliding_coords = get_sliding_window_coords(1600, 1280, options.window_size, 3);
 
-    (test_integral, sliding_coords)
+    (test_img_mat, test_integral, sliding_coords)
 }
 
 /// Compute the top-left coordinates of a square window sliding over a space rectangle
@@ -179,7 +655,7 @@ mod tests {
     #[test]
     // Builds a purely red (255, 0, 0) 64x64 input and checks that it's
     // correctly turned into the corresponding grayscale matrix
-    fn image_averages_correctly() {
+    fn image_converts_to_luma() {
         let (w, h) = (64, 64);
         let mut img = image::DynamicImage::new_rgb8(w, h);
 
@@ -189,15 +665,175 @@ mod tests {
             }
         }
 
-        let mat = img_as_matrix(img);
+        for &(luma, expected) in &[(LumaStandard::Rec601, 76), (LumaStandard::Rec709, 54)] {
+            let mat = samples_as_matrix(&image_samples(&img), luma);
 
-        assert!(mat.ndim() == 2);
-        assert!(mat.dim() == (64, 64));
+            assert!(mat.ndim() == 2);
+            assert!(mat.dim() == (64, 64));
+            assert!(mat.iter().all(|px| *px == expected));
+        }
+    }
 
-        for x in 0..w {
-            for y in 0..h {
-                assert!(mat[[y as usize, x as usize]] == 255 / 3);
-            }
+    #[test]
+    // The same colour in every channel layout and bit depth gives the same luma
+    fn all_color_types_convert_consistently() {
+        let (r, g, b) = (200u16, 100u16, 50u16);
+        let wide = |v: u16| v * 257;
+        let layouts = vec![
+            (ColorType::RGB(8), vec![r, g, b]),
+            (ColorType::RGBA(8), vec![r, g, b, 7]),
+            (ColorType::BGR(8), vec![b, g, r]),
+            (ColorType::BGRA(8), vec![b, g, r, 7]),
+            (ColorType::RGB(16), vec![wide(r), wide(g), wide(b)]),
+            (ColorType::RGBA(16), vec![wide(r), wide(g), wide(b), 7]),
+        ];
+
+        for (color, data) in layouts {
+            let samples = Samples {
+                color,
+                w: 1,
+                h: 1,
+                data,
+            };
+            // 0.299 * 200 + 0.587 * 100 + 0.114 * 50 = 124.2
+            assert!(samples_as_matrix(&samples, LumaStandard::Rec601)[[0, 0]] == 124);
+        }
+
+        for (color, data) in [
+            (ColorType::Gray(8), vec![r]),
+            (ColorType::GrayA(8), vec![r, 7]),
+            (ColorType::Gray(16), vec![wide(r)]),
+            (ColorType::GrayA(16), vec![wide(r), 7]),
+        ] {
+            let samples = Samples {
+                color,
+                w: 1,
+                h: 1,
+                data,
+            };
+            assert!(samples_as_matrix(&samples, LumaStandard::Rec709)[[0, 0]] == 200);
+        }
+    }
+
+    #[test]
+    // 16-bit PNMs can't be opened as DynamicImages, so check they decode at all
+    fn sixteen_bit_images_decode() {
+        // A 2x1 16-bit PGM with big-endian samples
+        let mut pgm = b"P5\n2 1\n65535\n".to_vec();
+        pgm.extend_from_slice(&[0xff, 0xff, 0x80, 0x00]);
+        let samples = decode_samples(&pgm).unwrap();
+        assert!(samples.color == ColorType::Gray(16));
+        assert!(samples_as_matrix(&samples, LumaStandard::Rec601) == array![[255, 128]]);
+
+        let mut png = Vec::new();
+        image::png::PNGEncoder::new(&mut png)
+            .encode(&[0xff, 0xff, 0x80, 0x00], 2, 1, ColorType::Gray(16))
+            .unwrap();
+        let samples = decode_samples(&png).unwrap();
+        assert!(samples_as_matrix(&samples, LumaStandard::Rec601) == array![[255, 128]]);
+
+        let mut png = Vec::new();
+        image::png::PNGEncoder::new(&mut png)
+            .encode(&[10, 20, 30, 0], 1, 1, ColorType::RGBA(8))
+            .unwrap();
+        let samples = decode_samples(&png).unwrap();
+        assert!(samples.color == ColorType::RGBA(8));
+        assert!(samples_as_matrix(&samples, LumaStandard::Rec601) == array![[18]]);
+    }
+
+    #[test]
+    // Formats are detected by content, so extensions don't matter
+    fn directories_load_recursively_by_content() {
+        let dir = crate::util::test_dir("load");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let mut pgm = b"P5\n2 2\n255\n".to_vec();
+        pgm.extend_from_slice(&[1, 2, 3, 4]);
+        fs::write(dir.join("face.pgm"), &pgm).unwrap();
+        fs::write(dir.join("nested").join("FACE.JPEG"), &pgm).unwrap();
+
+        let mut png = Vec::new();
+        image::png::PNGEncoder::new(&mut png)
+            .encode(&[10, 20, 30, 40], 2, 2, ColorType::Gray(8))
+            .unwrap();
+        fs::write(dir.join("nested").join("face"), &png).unwrap();
+
+        fs::write(dir.join("README.txt"), b"not an image").unwrap();
+        fs::write(dir.join("truncated.png"), &png[..20]).unwrap();
+
+        let options = PreprocessOptions {
+            window_size: 2,
+            ..PreprocessOptions::default()
+        };
+        let (imgs, report) = load_imgs_from_dir_with_report(dir.to_str().unwrap(), &options);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(report.loaded == 3);
+        assert!(imgs[0] == array![[1, 2], [3, 4]]);
+        assert!(imgs[1] == array![[1, 2], [3, 4]]);
+        assert!(imgs[2] == array![[10, 20], [30, 40]]);
+        assert!(report.skipped == vec![dir.join("README.txt")]);
+        assert!(report.failed.len() == 1 && report.failed[0].0 == dir.join("truncated.png"));
+    }
+
+    #[test]
+    // Images decoded in parallel still come out in path order
+    fn parallel_loading_keeps_path_order() {
+        let dir = crate::util::test_dir("parallel-load");
+        for i in 0..40 {
+            GrayImage::from_pixel(2, 2, Luma([i as u8]))
+                .save(dir.join(format!("{:02}.png", i)))
+                .unwrap();
+        }
+
+        let options = PreprocessOptions {
+            window_size: 2,
+            ..PreprocessOptions::default()
+        };
+        let (imgs, report) = load_imgs_from_dir_with_report(dir.to_str().unwrap(), &options);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(report.loaded == 40);
+        for (i, img) in imgs.iter().enumerate() {
+            assert!(img[[0, 0]] == i as i64);
+        }
+        let integral = compute_integral_images(imgs);
+        let whole = crate::util::Rectangle::new((0, 0), (2, 2));
+        assert!(integral[39].upright.compute_area(&whole) == 4 * 39);
+        assert!(integral[39].upright.accumulator() == "u32");
+        // Training samples leave out the squared pixels only detection needs
+        assert!(integral[39].squared.is_none());
+    }
+
+    #[test]
+    fn images_fit_to_window() {
+        let options = |window_fit| PreprocessOptions {
+            window_size: 2,
+            window_fit,
+            ..PreprocessOptions::default()
+        };
+        let img = Matrix::from_shape_fn((4, 6), |(y, x)| (10 * y + x) as i64);
+
+        assert!(fit_to_window(img.clone(), &options(WindowFit::Strict)).is_err());
+        assert!(fit_to_window(
+            img.slice(s![..2, ..2]).to_owned(),
+            &options(WindowFit::Strict)
+        )
+        .is_ok());
+
+        let cropped = fit_to_window(img.clone(), &options(WindowFit::CentreCrop)).unwrap();
+        assert!(cropped == array![[12, 13], [22, 23]]);
+        let small = Matrix::zeros((1, 3));
+        assert!(fit_to_window(small, &options(WindowFit::CentreCrop)).is_err());
+
+        let flat = Matrix::from_elem((5, 3), 70);
+        for &filter in &[
+            ResizeFilter::Nearest,
+            ResizeFilter::Triangle,
+            ResizeFilter::Lanczos3,
+        ] {
+            let resized = fit_to_window(flat.clone(), &options(WindowFit::Resize(filter))).unwrap();
+            assert!(resized == Matrix::from_elem((2, 2), 70));
         }
     }
 
@@ -242,6 +878,36 @@ mod tests {
         assert!(int_inp_mat == exp_mat);
     }
 
+    #[test]
+    // Checks the tilted integral image against a brute-force sum over each triangle
+    fn tilted_integral_images_computed_correctly() {
+        let (rows, cols) = (5, 7);
+        let mut img = Matrix::zeros((rows, cols));
+        for row in 0..rows {
+            for col in 0..cols {
+                img[[row, col]] = (row * cols + col) as i64 % 11;
+            }
+        }
+
+        let tilted = compute_tilted_integral_image(&img);
+        assert!(tilted.dim() == (rows + 1, cols + 1));
+
+        for y in 0..=rows {
+            for x in 0..=cols {
+                let mut expected = 0;
+                for row in 0..y {
+                    for col in 0..cols {
+                        let dist = (col as i64 - (x as i64 - 1)).abs();
+                        if dist <= (y - 1 - row) as i64 {
+                            expected += img[[row, col]];
+                        }
+                    }
+                }
+                assert!(tilted[[y, x]] == expected);
+            }
+        }
+    }
+
     #[test]
     fn correct_sliding_windows_computed() {
         let xmax = 10;
@@ -1,16 +1,29 @@
 use serde::{Deserialize, Serialize};
 use std::f64;
 
-type WeakClassifier = super::weak_classifier::WeakClassifier;
+type WeakLearner = super::weak_learner::WeakLearner;
 type Classification = super::Classification;
-type Matrix = ndarray::Array2<i64>;
-type MatrixView<'a> = ndarray::ArrayView2<'a, i64>;
+type IntegralImages = super::IntegralImages;
+type IntegralView<'a> = super::IntegralView<'a>;
+
+/// How stumps count towards a stage's weighted sum.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StumpScore {
+    /// +1 on the face side of the threshold and -1 on the other, on the scale of the
+    /// other weak learners' scores.
+    Vote,
+    /// The toggled margin of the feature over the threshold.
+    #[default]
+    Margin,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StrongClassifier {
-    pub classifiers: Vec<WeakClassifier>,
-    weights: Vec<f64>,
+    pub classifiers: Vec<WeakLearner>,
+    pub weights: Vec<f64>,
     threshold: f64,
+    #[serde(default)]
+    pub stump_score: StumpScore,
 }
 
 impl StrongClassifier {
@@ -21,30 +34,75 @@ impl StrongClassifier {
             classifiers: Vec::new(),
             weights: Vec::new(),
             threshold: 0.,
+            stump_score: StumpScore::default(),
+        }
+    }
+
+    /// A strong classifier with a fixed threshold, such as an imported stage.
+    pub fn from_parts(
+        classifiers: Vec<WeakLearner>,
+        weights: Vec<f64>,
+        threshold: f64,
+    ) -> StrongClassifier {
+        assert!(classifiers.len() == weights.len());
+        let stump_score = if mixes_stumps(&classifiers) {
+            StumpScore::Vote
+        } else {
+            StumpScore::default()
+        };
+        StrongClassifier {
+            classifiers,
+            weights,
+            threshold,
+            stump_score,
         }
     }
 
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
     /// Makes a weighted classification prediction using the ensemble of classifiers.
-    pub fn evaluate(&self, img: &MatrixView) -> Classification {
-        if self.evaluate_raw(img) >= 0. {
+    pub fn evaluate(&self, img: &IntegralView) -> Classification {
+        self.evaluate_scaled(img, 1.)
+    }
+
+    /// Makes a prediction on a window `factor` times the size of the training
+    /// windows, scaling the features instead of resampling the image.
+    pub fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> Classification {
+        if self.evaluate_raw_scaled(img, factor) >= 0. {
             Classification::Face
         } else {
             Classification::NonFace
         }
     }
 
-    fn evaluate_raw(&self, img: &MatrixView) -> f64 {
+    fn evaluate_raw_scaled(&self, img: &IntegralView, factor: f64) -> f64 {
+        self.weighted_score(img, factor) - self.threshold
+    }
+
+    /// The weighted sum of the weak learners' scores.
+    fn weighted_score(&self, img: &IntegralView, factor: f64) -> f64 {
         let mut weighted_score = 0.;
 
         for (classifier, weight) in self.classifiers.iter().zip(self.weights.iter()) {
-            weighted_score += weight * classifier.evaluate_raw(img) as f64;
+            let score = match (classifier, self.stump_score) {
+                (WeakLearner::Stump(stump), StumpScore::Margin) => {
+                    stump.evaluate_raw_scaled(img, factor) as f64
+                }
+                _ => classifier.evaluate_raw_scaled(img, factor),
+            };
+            weighted_score += weight * score;
         }
 
-        weighted_score - self.threshold
+        weighted_score
     }
 
     /// Computes the error for an ensemble of classifiers (for a given threshold).
-    pub fn compute_error(&self, input_samples: &Vec<(Matrix, Classification)>) -> (f64, f64, f64) {
+    pub fn compute_error(
+        &self,
+        input_samples: &Vec<(IntegralImages, Classification)>,
+    ) -> (f64, f64, f64) {
         let mut num_false_negatives: f64 = 0.;
         let mut num_false_positives: f64 = 0.;
         let mut num_negatives = 0.;
@@ -74,7 +132,10 @@ impl StrongClassifier {
 
     /// Sets the threshold for this strong classifier (assuming the other fields have
     /// been initialized). Returns a copy of the updated weight value.
-    fn update_threshold(&mut self, input_samples: &Vec<(Matrix, Classification)>) -> f64 {
+    pub fn update_threshold(
+        &mut self,
+        input_samples: &Vec<(IntegralImages, Classification)>,
+    ) -> f64 {
         // Compute the minimal score of a face, and set that to be the threshold
         let mut face_scores = Vec::new();
         for (img, classification) in input_samples {
@@ -82,12 +143,7 @@ impl StrongClassifier {
                 continue;
             }
 
-            let mut score = 0.;
-            for (classifier, weight) in self.classifiers.iter().zip(self.weights.iter()) {
-                score += weight * classifier.evaluate_raw(&img.view()) as f64;
-            }
-
-            face_scores.push(score);
+            face_scores.push(self.weighted_score(&img.view(), 1.));
         }
 
         face_scores.sort_by(|a, b| a.partial_cmp(&b).unwrap());
@@ -102,13 +158,146 @@ impl StrongClassifier {
     /// associated weight.
     pub fn add_weak_classifier(
         &mut self,
-        classifier: WeakClassifier,
+        classifier: WeakLearner,
         weight: f64,
-        input_samples: &Vec<(Matrix, Classification)>,
+        input_samples: &Vec<(IntegralImages, Classification)>,
     ) {
         self.classifiers.push(classifier);
         self.weights.push(weight);
+        if mixes_stumps(&self.classifiers) {
+            self.stump_score = StumpScore::Vote;
+        }
 
         self.update_threshold(input_samples);
     }
 }
+
+/// Whether a stage mixes stumps with other weak learners. Those score within
+/// [-1, 1], where feature margins run into the hundreds and would outweigh them
+/// whatever their weights, so the stumps of such a stage vote instead.
+fn mixes_stumps(classifiers: &[WeakLearner]) -> bool {
+    let is_stump = |classifier: &WeakLearner| matches!(classifier, WeakLearner::Stump(_));
+    classifiers.iter().any(is_stump) && !classifiers.iter().all(is_stump)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::{AnyFeature, Feature};
+    use crate::features::{HaarFeature, HaarFeatureType, Sign};
+    use crate::lbp::{LbpClassifier, MbLbpFeature};
+    use crate::weak_classifier::WeakClassifier;
+    use crate::Matrix;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    // A voting stump far past its threshold counts as one vote, so a lookup table
+    // with more weight decides the stage rather than being outweighed by the margin
+    fn mixed_stages_sum_votes() {
+        let mut img = Matrix::zeros((6, 6));
+        for y in 0..6 {
+            for x in 3..6 {
+                img[[y, x]] = 100;
+            }
+        }
+        let img = IntegralImages::from_image(&img);
+
+        let edge = AnyFeature::Haar(HaarFeature::new(HaarFeatureType::TwoHorizontal, 3, 6, 0, 0));
+        let value = edge.evaluate(&img.view());
+        assert!(value.abs() >= 1000);
+        let toggle = if value > 0 {
+            Sign::Positive
+        } else {
+            Sign::Negative
+        };
+        let stump = WeakLearner::Stump(WeakClassifier::new(&edge, 0, toggle));
+
+        let lbp = MbLbpFeature::new(2, 2, 0, 0);
+        let code = lbp.evaluate(&img.view()) as usize;
+        let mut face_codes = [0; 8];
+        let votes_non_face = WeakLearner::Lbp(LbpClassifier::new(lbp, face_codes));
+        face_codes[code / 32] |= 1 << (code % 32);
+        let votes_face = WeakLearner::Lbp(LbpClassifier::new(lbp, face_codes));
+
+        let stage = |lut, stump_score| {
+            let mut stage =
+                StrongClassifier::from_parts(vec![stump.clone(), lut], vec![1., 2.], 0.);
+            stage.stump_score = stump_score;
+            stage
+        };
+        let votes = |lut| stage(lut, StumpScore::Vote).evaluate(&img.view());
+        assert!(votes(votes_non_face.clone()) == Classification::NonFace);
+        assert!(votes(votes_face) == Classification::Face);
+
+        // Scored by its margin, the stump outweighs the table
+        let margins = stage(votes_non_face, StumpScore::Margin);
+        assert!(margins.evaluate(&img.view()) == Classification::Face);
+    }
+
+    #[test]
+    // Stages of stumps alone score their margins, so they train to the same scores
+    // and thresholds as before stumps could vote; another kind of learner makes the
+    // stage's stumps vote
+    fn stump_stages_keep_baseline_scores() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let samples: Vec<(IntegralImages, Classification)> = (0..20)
+            .map(|i| {
+                let img = Matrix::from_shape_fn((6, 6), |_| rng.gen_range(0, 256));
+                let label = if i % 2 == 0 {
+                    Classification::Face
+                } else {
+                    Classification::NonFace
+                };
+                (IntegralImages::from_image(&img), label)
+            })
+            .collect();
+        let haar =
+            |feature_type, w, h| AnyFeature::Haar(HaarFeature::new(feature_type, w, h, 0, 0));
+        let stumps = [
+            WeakClassifier::new(
+                &haar(HaarFeatureType::TwoHorizontal, 3, 6),
+                20,
+                Sign::Positive,
+            ),
+            WeakClassifier::new(
+                &haar(HaarFeatureType::TwoVertical, 6, 3),
+                -10,
+                Sign::Negative,
+            ),
+        ];
+        let weights = [0.6, 0.4];
+
+        let mut stage = StrongClassifier::new();
+        for (stump, weight) in stumps.iter().zip(&weights) {
+            stage.add_weak_classifier(WeakLearner::Stump(stump.clone()), *weight, &samples);
+        }
+        assert!(stage.stump_score == StumpScore::Margin);
+
+        // As the stage scored windows and set its threshold before stumps could vote:
+        // the weighted margins, and the lowest face's score of the ten
+        let baseline = |img: &IntegralImages| {
+            stumps
+                .iter()
+                .zip(&weights)
+                .fold(0., |score, (stump, weight)| {
+                    score + weight * stump.evaluate_raw(&img.view()) as f64
+                })
+        };
+        let lowest_face = samples
+            .iter()
+            .filter(|(_, label)| *label == Classification::Face)
+            .map(|(img, _)| baseline(img))
+            .fold(f64::INFINITY, f64::min);
+        assert!(stage.threshold() == lowest_face);
+        for (img, _) in &samples {
+            let face = baseline(img) >= lowest_face;
+            assert!((stage.evaluate(&img.view()) == Classification::Face) == face);
+        }
+
+        let always_face = LbpClassifier::new(MbLbpFeature::new(2, 2, 0, 0), [u32::MAX; 8]);
+        stage.add_weak_classifier(WeakLearner::Lbp(always_face), 0.5, &samples);
+        assert!(stage.stump_score == StumpScore::Vote);
+        assert!(stage.threshold().abs() <= 1.5);
+    }
+}
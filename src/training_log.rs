@@ -0,0 +1,285 @@
+/// Machine-readable training logs and the HTML report rendered from them.
+use super::weak_learner::WeakLearner;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+/// A single line of the JSONL training log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LogRecord {
+    /// Emitted after every boosting round within a stage.
+    Round {
+        stage: usize,
+        round: usize,
+        weak_classifier: WeakLearner,
+        alpha: f64,
+        weighted_error: f64,
+        stage_fpr: f64,
+        stage_fnr: f64,
+        stage_error: f64,
+        num_weak_classifiers: usize,
+        num_positives: usize,
+        num_negatives: usize,
+        round_secs: f64,
+        elapsed_secs: f64,
+    },
+    /// Emitted once a stage has been added to the cascade and the training set has
+    /// been filtered through it.
+    Stage {
+        stage: usize,
+        num_weak_classifiers: usize,
+        stage_fpr: f64,
+        stage_fnr: f64,
+        remaining_positives: usize,
+        remaining_negatives: usize,
+        stage_secs: f64,
+        elapsed_secs: f64,
+    },
+}
+
+/// Appends one JSON record per line to a log file.
+pub struct TrainingLog {
+    file: File,
+}
+
+impl TrainingLog {
+    pub fn create(path: &str) -> TrainingLog {
+        TrainingLog {
+            file: File::create(path).expect("Failed to create training log file"),
+        }
+    }
+
+    pub fn record(&mut self, record: &LogRecord) {
+        let line = serde_json::to_string(record).expect("Failed to serialize log record");
+        writeln!(self.file, "{}", line).expect("Failed to write to training log");
+    }
+}
+
+/// Read every record from a JSONL training log, skipping blank lines.
+pub fn read_log(log_path: &str) -> Vec<LogRecord> {
+    let file = File::open(log_path).expect("Couldn't open training log");
+
+    let mut records = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.expect("Failed to read line from training log");
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .unwrap_or_else(|e| panic!("Malformed record on line {}: {}", i + 1, e));
+        records.push(record);
+    }
+
+    records
+}
+
+/// Render a training log into a self-contained HTML report and write it to disk.
+pub fn write_report(log_path: &str, report_path: &str) {
+    let records = read_log(log_path);
+    fs::write(report_path, render_report(&records)).expect("Failed to write training report");
+    println!("Saved training report to '{}'", report_path);
+}
+
+/// Render the records of a training log as an HTML page with an inline SVG plot of
+/// the error curves of every stage, followed by a summary table of the stages.
+pub fn render_report(records: &[LogRecord]) -> String {
+    let mut num_stages = 0;
+    for record in records {
+        let stage = match record {
+            LogRecord::Round { stage, .. } => *stage,
+            LogRecord::Stage { stage, .. } => *stage,
+        };
+        num_stages = num_stages.max(stage);
+    }
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>Training report</title>\n<style>\n");
+    html.push_str("body { font-family: sans-serif; margin: 2em; }\n");
+    html.push_str("table { border-collapse: collapse; }\n");
+    html.push_str("td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }\n");
+    html.push_str("</style>\n</head>\n<body>\n<h1>Training report</h1>\n");
+
+    for stage in 1..=num_stages {
+        // (round, fpr, fnr, overall error)
+        let mut points = Vec::new();
+        for record in records {
+            if let LogRecord::Round {
+                stage: s,
+                round,
+                stage_fpr,
+                stage_fnr,
+                stage_error,
+                ..
+            } = record
+            {
+                if *s == stage {
+                    points.push((*round, *stage_fpr, *stage_fnr, *stage_error));
+                }
+            }
+        }
+
+        html.push_str(&format!("<h2>Stage {}</h2>\n", stage));
+        html.push_str(&render_stage_plot(&points));
+    }
+
+    html.push_str("<h2>Stages</h2>\n<table>\n<tr><th>Stage</th><th>Weak classifiers</th>");
+    html.push_str("<th>FPR</th><th>FNR</th><th>Positives left</th><th>Negatives left</th>");
+    html.push_str("<th>Time (s)</th></tr>\n");
+    for record in records {
+        if let LogRecord::Stage {
+            stage,
+            num_weak_classifiers,
+            stage_fpr,
+            stage_fnr,
+            remaining_positives,
+            remaining_negatives,
+            stage_secs,
+            ..
+        } = record
+        {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.4}</td><td>{:.4}</td><td>{}</td><td>{}</td><td>{:.1}</td></tr>\n",
+                stage,
+                num_weak_classifiers,
+                stage_fpr,
+                stage_fnr,
+                remaining_positives,
+                remaining_negatives,
+                stage_secs
+            ));
+        }
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    html
+}
+
+/// Plot the FPR, FNR and overall error of a stage against the boosting round.
+fn render_stage_plot(points: &[(usize, f64, f64, f64)]) -> String {
+    let (width, height, margin) = (600., 300., 40.);
+    let max_round = points.iter().map(|p| p.0).max().unwrap_or(1).max(2) as f64;
+
+    let to_x =
+        |round: usize| margin + (round as f64 - 1.) / (max_round - 1.) * (width - 2. * margin);
+    let to_y = |rate: f64| height - margin - rate.clamp(0., 1.) * (height - 2. * margin);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        width, height
+    );
+    svg.push_str(&format!(
+        "<rect x=\"{m}\" y=\"{m}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#999\"/>\n",
+        width - 2. * margin,
+        height - 2. * margin,
+        m = margin
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"end\">1</text>\n",
+        margin - 4.,
+        to_y(1.) + 4.
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"end\">0</text>\n",
+        margin - 4.,
+        to_y(0.) + 4.
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"middle\">boosting round (1-{})</text>\n",
+        width / 2.,
+        height - margin / 3.,
+        max_round
+    ));
+
+    let series = [("FPR", "#d62728"), ("FNR", "#1f77b4"), ("error", "#2ca02c")];
+    for (i, (name, color)) in series.iter().enumerate() {
+        let coords: Vec<String> = points
+            .iter()
+            .map(|&(round, fpr, fnr, error)| {
+                let rate = [fpr, fnr, error][i];
+                format!("{:.1},{:.1}", to_x(round), to_y(rate))
+            })
+            .collect();
+        svg.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"2\" points=\"{}\"/>\n",
+            color,
+            coords.join(" ")
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"12\" fill=\"{}\">{}</text>\n",
+            width - margin + 4.,
+            margin + 14. * (i as f64 + 1.),
+            color,
+            name
+        ));
+    }
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::AnyFeature;
+    use crate::features::{HaarFeature, HaarFeatureType, Sign};
+    use crate::weak_classifier::WeakClassifier;
+
+    fn round_record(stage: usize, round: usize) -> LogRecord {
+        let feature = AnyFeature::Haar(HaarFeature::new(HaarFeatureType::TwoVertical, 1, 1, 0, 0));
+        LogRecord::Round {
+            stage,
+            round,
+            weak_classifier: WeakLearner::Stump(WeakClassifier::new(&feature, 0, Sign::Positive)),
+            alpha: 0.5,
+            weighted_error: 0.25,
+            stage_fpr: 0.5,
+            stage_fnr: 0.05,
+            stage_error: 0.3,
+            num_weak_classifiers: round,
+            num_positives: 10,
+            num_negatives: 10,
+            round_secs: 1.,
+            elapsed_secs: round as f64,
+        }
+    }
+
+    #[test]
+    // Checks that records survive a round trip through their JSON representation
+    fn records_round_trip() {
+        let line = serde_json::to_string(&round_record(1, 2)).unwrap();
+        assert!(line.contains("\"kind\":\"round\""));
+
+        match serde_json::from_str(&line).unwrap() {
+            LogRecord::Round { stage, round, .. } => assert!(stage == 1 && round == 2),
+            LogRecord::Stage { .. } => panic!("Round record deserialized as a stage record"),
+        }
+    }
+
+    #[test]
+    fn report_has_a_plot_per_stage() {
+        let records = vec![
+            round_record(1, 1),
+            round_record(1, 2),
+            round_record(2, 1),
+            LogRecord::Stage {
+                stage: 1,
+                num_weak_classifiers: 2,
+                stage_fpr: 0.3,
+                stage_fnr: 0.05,
+                remaining_positives: 10,
+                remaining_negatives: 3,
+                stage_secs: 2.,
+                elapsed_secs: 2.,
+            },
+        ];
+
+        let report = render_report(&records);
+        assert!(report.matches("<svg").count() == 2);
+        assert!(report.contains("<h2>Stage 2</h2>"));
+        assert!(report.matches("<tr><td>").count() == 1);
+    }
+}
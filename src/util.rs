@@ -1,5 +1,4 @@
 /// General utility functions
-type MatrixView<'a> = ndarray::ArrayView2<'a, i64>;
 
 #[derive(Debug)]
 pub struct Rectangle {
@@ -11,7 +10,6 @@ pub struct Rectangle {
 
 impl Rectangle {
     pub fn new(p1: (usize, usize), p2: (usize, usize)) -> Rectangle {
-        assert!(p1.0 <= 64 && p1.1 <= 64 && p2.0 <= 64 && p2.1 <= 64);
         assert!(p1.0 <= p2.0);
         assert!(p1.1 <= p2.1);
 
@@ -22,16 +20,56 @@ impl Rectangle {
             ymax: p2.1,
         }
     }
+
+    /// Whether pixel (px, py) of the image is summed by `IntegralImage::compute_area`.
+    pub fn contains(&self, px: usize, py: usize) -> bool {
+        self.xmin <= px && px < self.xmax && self.ymin <= py && py < self.ymax
+    }
+}
+
+/// A block rotated by 45 degrees. Its top corner is at (x, y) in tilted integral
+/// image coordinates, and it extends w pixels down and to the right and h pixels
+/// down and to the left.
+#[derive(Debug)]
+pub struct TiltedRectangle {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl TiltedRectangle {
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> TiltedRectangle {
+        assert!(x >= h);
+
+        TiltedRectangle { x, y, w, h }
+    }
+
+    /// Whether pixel (px, py) of the image is summed by
+    /// `IntegralImage::compute_tilted_area`. In
+    /// coordinates rotated about the top corner (and doubled), those are the pixels
+    /// whose centres fall within [0, 2w) x (0, 2h].
+    pub fn contains(&self, px: usize, py: usize) -> bool {
+        let u = (px + py + 1) as i64 - (self.x + self.y) as i64;
+        let v = (py + self.x) as i64 - (px + self.y) as i64;
+        0 <= u && u < 2 * self.w as i64 && 0 < v && v <= 2 * self.h as i64
+    }
 }
 
-/// Compute the area of a block within an (assumed) padded integral image
-pub fn compute_area(img: &MatrixView, r: &Rectangle) -> i64 {
-    img[[r.ymax, r.xmax]] + img[[r.ymin, r.xmin]] - img[[r.ymin, r.xmax]] - img[[r.ymax, r.xmin]]
+/// An empty directory for a test's files, named after the test and the process so
+/// that concurrent test runs don't share it.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("vj-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("Failed to create a test directory");
+    dir
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::integral::IntegralImage;
     use ndarray::Array;
 
     #[test]
@@ -42,15 +80,62 @@ mod tests {
             0, 0, 0, 0, 0, 0, 1, 3, 6, 10, 0, 6, 14, 24, 36, 0, 15, 33, 54, 78, 0, 28, 60, 96, 136,
         ];
 
-        let img = Array::from_vec(inp)
-            .into_shape((5, 5))
-            .expect("Failed to transform input array into matrix");
+        let img = IntegralImage::from_sums_unchecked(
+            Array::from_vec(inp)
+                .into_shape((5, 5))
+                .expect("Failed to transform input array into matrix"),
+        );
 
-        assert!(compute_area(&img.view(), &Rectangle::new((2, 2), (2, 2))) == 0);
-        assert!(compute_area(&img.view(), &Rectangle::new((0, 0), (2, 2))) == 14);
-        assert!(compute_area(&img.view(), &Rectangle::new((0, 0), (4, 4))) == 136);
-        assert!(compute_area(&img.view(), &Rectangle::new((1, 1), (4, 4))) == 99);
-        assert!(compute_area(&img.view(), &Rectangle::new((1, 1), (2, 2))) == 6);
-        assert!(compute_area(&img.view(), &Rectangle::new((1, 1), (3, 3))) == 34);
+        assert!(img.compute_area(&Rectangle::new((2, 2), (2, 2))) == 0);
+        assert!(img.compute_area(&Rectangle::new((0, 0), (2, 2))) == 14);
+        assert!(img.compute_area(&Rectangle::new((0, 0), (4, 4))) == 136);
+        assert!(img.compute_area(&Rectangle::new((1, 1), (4, 4))) == 99);
+        assert!(img.compute_area(&Rectangle::new((1, 1), (2, 2))) == 6);
+        assert!(img.compute_area(&Rectangle::new((1, 1), (3, 3))) == 34);
+    }
+
+    #[test]
+    // Checks rotated areas against the pixels they're expected to cover
+    fn tilted_areas_computed_correctly() {
+        let ones = Array::ones((8, 8));
+        let tilted = IntegralImage::<i64>::tilted(&ones).unwrap();
+        for &(x, y, w, h) in &[(1, 0, 1, 1), (2, 1, 2, 1), (3, 0, 2, 3), (4, 2, 1, 4)] {
+            let r = TiltedRectangle::new(x, y, w, h);
+            assert!(tilted.compute_tilted_area(&r) == 2 * (w * h) as i64);
+        }
+
+        // The unit block with its top corner at (2, 1) covers the pixels at (1, 1)
+        // and (1, 2)
+        let mut img = Array::zeros((4, 4));
+        img[[1, 1]] = 1;
+        img[[2, 1]] = 10;
+        img[[1, 2]] = 100;
+        let tilted = IntegralImage::<i64>::tilted(&img).unwrap();
+        let r = TiltedRectangle::new(2, 1, 1, 1);
+        assert!(tilted.compute_tilted_area(&r) == 11);
+    }
+
+    #[test]
+    // Checks membership against the areas of images with a single lit pixel
+    fn rectangles_contain_summed_pixels() {
+        let rect = Rectangle::new((1, 2), (4, 3));
+        let tilted = [(1, 0, 1, 1), (2, 1, 2, 1), (3, 0, 2, 3), (4, 2, 1, 4)];
+        for py in 0..8 {
+            for px in 0..8 {
+                let mut img = Array::zeros((8, 8));
+                img[[py, px]] = 1;
+
+                let integral = IntegralImage::<i64>::upright(&img).unwrap();
+                let summed = integral.compute_area(&rect) == 1;
+                assert!(rect.contains(px, py) == summed);
+
+                let integral = IntegralImage::<i64>::tilted(&img).unwrap();
+                for &(x, y, w, h) in &tilted {
+                    let r = TiltedRectangle::new(x, y, w, h);
+                    let summed = integral.compute_tilted_area(&r) == 1;
+                    assert!(r.contains(px, py) == summed, "({}, {}) in {:?}", px, py, r);
+                }
+            }
+        }
     }
 }
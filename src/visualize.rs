@@ -0,0 +1,210 @@
+/// Renders the features selected by each stage of a cascade over the mean training
+/// face, for sanity-checking trained models.
+use super::feature::AnyFeature;
+use super::features::{HaarFeature, Sign};
+use super::preprocess::{load_imgs_from_dir, PreprocessOptions};
+use super::strong_classifier::StrongClassifier;
+use super::Matrix;
+use image::{Rgb, RgbImage};
+use ndarray::Array2;
+use std::fs;
+
+/// Output pixels per window pixel in the PNG sheets.
+const ZOOM: u32 = 4;
+/// Regions that are brighter on faces are drawn in red, darker ones in blue.
+const BRIGHTER: [u8; 3] = [214, 39, 40];
+const DARKER: [u8; 3] = [31, 119, 180];
+
+/// The Haar features used by a stage with the weight of the weak learner they
+/// belong to. A feature's sign is flipped when its stump calls faces below the
+/// threshold, so that positive regions are always those brighter on faces. MB-LBP
+/// lookup tables have no polarity and are left out.
+fn stage_features(stage: &StrongClassifier) -> Vec<(HaarFeature, Sign, f64)> {
+    let mut features = Vec::new();
+    for (learner, alpha) in stage.classifiers.iter().zip(stage.weights.iter()) {
+        for stump in learner.stumps() {
+            if let AnyFeature::Haar(feature) = stump.feature() {
+                features.push((*feature, stump.toggle(), *alpha));
+            }
+        }
+    }
+
+    features
+}
+
+/// The pixel-wise mean of a set of equally sized images.
+pub fn mean_image(imgs: &[Matrix]) -> Matrix {
+    assert!(!imgs.is_empty(), "Can't average an empty set of images");
+
+    let mut sum = Matrix::zeros(imgs[0].dim());
+    for img in imgs {
+        sum += img;
+    }
+
+    sum / imgs.len() as i64
+}
+
+/// The alpha-weighted sum, at each pixel of the window, of the signs of the feature
+/// rectangles covering it.
+pub fn weight_map(stage: &StrongClassifier, dim: (usize, usize)) -> Array2<f64> {
+    let features = stage_features(stage);
+    Array2::from_shape_fn(dim, |(py, px)| {
+        features
+            .iter()
+            .map(|(feature, toggle, alpha)| alpha * (*toggle * feature.pixel_weight(px, py)) as f64)
+            .sum()
+    })
+}
+
+/// Tint the mean face by the weight map, with the strongest weight fully tinted.
+fn render_png(mean_face: &Matrix, weights: &Array2<f64>) -> RgbImage {
+    let max_weight = weights.iter().fold(0., |max: f64, w| max.max(w.abs()));
+    let (h, w) = mean_face.dim();
+
+    RgbImage::from_fn(w as u32 * ZOOM, h as u32 * ZOOM, |x, y| {
+        let (px, py) = ((x / ZOOM) as usize, (y / ZOOM) as usize);
+        let gray = mean_face[[py, px]].clamp(0, 255) as f64;
+        let weight = weights[[py, px]];
+        if max_weight == 0. || weight == 0. {
+            return Rgb([gray as u8; 3]);
+        }
+
+        let tint = if weight > 0. { BRIGHTER } else { DARKER };
+        let t = 0.8 * weight.abs() / max_weight;
+        let mut out = [0; 3];
+        for (c, channel) in out.iter_mut().enumerate() {
+            *channel = (gray * (1. - t) + tint[c] as f64 * t).round() as u8;
+        }
+        Rgb(out)
+    })
+}
+
+/// An SVG sheet with each feature rectangle drawn over the mean face (referenced
+/// by file name), with an opacity proportional to its weak learner's weight.
+fn render_svg(stage: &StrongClassifier, mean_face_file: &str, dim: (usize, usize)) -> String {
+    let features = stage_features(stage);
+    let max_alpha = features.iter().fold(0., |max: f64, f| max.max(f.2));
+    let (h, w) = dim;
+    let zoom = ZOOM as usize;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{}\" height=\"{}\">\n",
+        w * zoom,
+        h * zoom
+    );
+    svg.push_str(&format!(
+        "<image xlink:href=\"{}\" width=\"{}\" height=\"{}\" style=\"image-rendering: pixelated\"/>\n",
+        mean_face_file,
+        w * zoom,
+        h * zoom
+    ));
+
+    for (feature, toggle, alpha) in &features {
+        for (corners, sign) in feature.polygons() {
+            let tint = if *toggle * (sign * 1) > 0 {
+                BRIGHTER
+            } else {
+                DARKER
+            };
+            let points: Vec<String> = corners
+                .iter()
+                .map(|(x, y)| format!("{},{}", x * zoom, y * zoom))
+                .collect();
+            svg.push_str(&format!(
+                "<polygon points=\"{}\" fill=\"rgb({},{},{})\" fill-opacity=\"{:.3}\" stroke=\"none\"/>\n",
+                points.join(" "),
+                tint[0],
+                tint[1],
+                tint[2],
+                0.6 * alpha / max_alpha
+            ));
+        }
+    }
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+/// Write `mean_face.png` and a `stage_<i>.png` and `stage_<i>.svg` sheet per stage
+/// of a saved cascade to `out_dir`, with the mean face computed over the images in
+/// `faces_dir`.
+pub fn render_cascade(faces_dir: &str, cascade_path: &str, out_dir: &str) {
+    let cascade = super::load_cascade(cascade_path);
+    let faces = load_imgs_from_dir(faces_dir, &PreprocessOptions::default());
+    let mean_face = mean_image(&faces);
+    let dim = mean_face.dim();
+
+    fs::create_dir_all(out_dir).expect("Failed to create the output directory");
+    let out_path = |name: &str| format!("{}/{}", out_dir, name);
+
+    render_png(&mean_face, &Array2::zeros(dim))
+        .save(out_path("mean_face.png"))
+        .expect("Failed to write the mean face");
+
+    for (i, stage) in cascade.iter().enumerate() {
+        render_png(&mean_face, &weight_map(stage, dim))
+            .save(out_path(&format!("stage_{}.png", i)))
+            .expect("Failed to write a stage sheet");
+        fs::write(
+            out_path(&format!("stage_{}.svg", i)),
+            render_svg(stage, "mean_face.png", dim),
+        )
+        .expect("Failed to write a stage sheet");
+    }
+
+    println!("Rendered {} stages to {}", cascade.len(), out_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::HaarFeatureType;
+    use crate::weak_classifier::WeakClassifier;
+    use crate::weak_learner::WeakLearner;
+
+    fn stage(features: &[(HaarFeature, Sign, f64)]) -> StrongClassifier {
+        let mut stage = StrongClassifier::new();
+        for (feature, toggle, alpha) in features {
+            let stump = WeakClassifier::new(&AnyFeature::Haar(*feature), 0, *toggle);
+            stage.classifiers.push(WeakLearner::Stump(stump));
+            stage.weights.push(*alpha);
+        }
+
+        stage
+    }
+
+    #[test]
+    // Two overlapping features, the second with its polarity flipped and twice the
+    // weight
+    fn weight_maps_sum_signed_alphas() {
+        let two_vert = HaarFeature::new(HaarFeatureType::TwoVertical, 2, 1, 0, 0);
+        let two_horiz = HaarFeature::new(HaarFeatureType::TwoHorizontal, 1, 2, 0, 0);
+        let stage = stage(&[
+            (two_vert, Sign::Positive, 1.),
+            (two_horiz, Sign::Negative, 2.),
+        ]);
+
+        let weights = weight_map(&stage, (3, 3));
+        let expected = array![[3., -1., 0.], [1., -3., 0.], [0., 0., 0.]];
+        assert!(weights == expected);
+    }
+
+    #[test]
+    fn renders_mean_face_and_sheets() {
+        let imgs = vec![Matrix::zeros((4, 4)), Matrix::from_elem((4, 4), 100)];
+        let mean_face = mean_image(&imgs);
+        assert!(mean_face == Matrix::from_elem((4, 4), 50));
+
+        let feature = HaarFeature::new(HaarFeatureType::TiltedTwoHorizontal, 1, 1, 1, 0);
+        let stage = stage(&[(feature, Sign::Positive, 0.5)]);
+
+        let png = render_png(&mean_face, &weight_map(&stage, (4, 4)));
+        assert!(png.dimensions() == (4 * ZOOM, 4 * ZOOM));
+        assert!(png.get_pixel(0, 0) != png.get_pixel(3 * ZOOM, 3 * ZOOM));
+        assert!(png.get_pixel(3 * ZOOM, 3 * ZOOM).data == [50; 3]);
+
+        let svg = render_svg(&stage, "mean_face.png", (4, 4));
+        assert!(svg.matches("<polygon").count() == 2);
+        assert!(svg.contains("points=\"4,0 8,4 4,8 0,4\""));
+    }
+}
@@ -2,21 +2,22 @@ use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
 use std::f64;
 
-type Feature = super::features::HaarFeature;
+use super::feature::{AnyFeature, Feature};
+
 type Toggle = super::features::Sign;
-type Matrix = ndarray::Array2<i64>;
+type IntegralImages = super::IntegralImages;
 type Classification = super::Classification;
-type MatrixView<'a> = ndarray::ArrayView2<'a, i64>;
+type IntegralView<'a> = super::IntegralView<'a>;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct WeakClassifier {
-    feature: Feature,
+    feature: AnyFeature,
     toggle: Toggle,
     threshold: i64,
 }
 
 impl WeakClassifier {
-    pub fn new(feature: &Feature, threshold: i64, toggle: Toggle) -> WeakClassifier {
+    pub fn new(feature: &AnyFeature, threshold: i64, toggle: Toggle) -> WeakClassifier {
         WeakClassifier {
             feature: *feature,
             threshold,
@@ -24,10 +25,24 @@ impl WeakClassifier {
         }
     }
 
+    pub fn feature(&self) -> &AnyFeature {
+        &self.feature
+    }
+
+    pub fn threshold(&self) -> i64 {
+        self.threshold
+    }
+
+    /// Negative when faces fall below the threshold, i.e. faces have the opposite
+    /// polarity to the feature.
+    pub fn toggle(&self) -> Toggle {
+        self.toggle
+    }
+
     fn get_optimal(
-        feature: &Feature,
-        training_samples: &Vec<(Matrix, Classification)>,
-        distribution_t: &Vec<f64>,
+        feature: &AnyFeature,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
         t_pos: f64,
         t_neg: f64,
     ) -> (WeakClassifier, f64) {
@@ -44,7 +59,7 @@ impl WeakClassifier {
         let mut best_error = 2.;
         let mut s_pos = 0.;
         let mut s_neg = 0.;
-        for (score, dist, label) in scores.iter().skip(1) {
+        for (i, (score, dist, label)) in scores.iter().enumerate() {
             if *label == Classification::Face {
                 s_pos += dist;
             } else {
@@ -52,17 +67,27 @@ impl WeakClassifier {
             }
             // println!("spos {} sneg {} sum {}", s_pos, s_neg, s_pos + s_neg);
 
+            // Only split between distinct scores, so that every sample ends up on the
+            // side of the threshold its weight was counted on
+            let next_score = scores.get(i + 1).map(|next| next.0);
+            if next_score == Some(*score) {
+                continue;
+            }
+
             let (a, b) = (s_pos + t_neg - s_neg, s_neg + t_pos - s_pos);
             // assert!((t_pos + t_neg - 1.).abs() < 0.0001);
             let error = a.min(b);
             if error < best_error {
                 best_error = error;
-                best_threshold = *score;
-                best_toggle = if a < b {
-                    Toggle::Positive
+                // Positive toggles classify scores at or above the threshold as faces,
+                // negative ones scores at or below it
+                if a < b {
+                    best_toggle = Toggle::Positive;
+                    best_threshold = next_score.unwrap_or(*score + 1);
                 } else {
-                    Toggle::Negative
-                };
+                    best_toggle = Toggle::Negative;
+                    best_threshold = *score;
+                }
             }
         }
 
@@ -75,9 +100,9 @@ impl WeakClassifier {
     /// Finds the optimal (attaining the lowest empirical loss) weak classifier for
     /// each feature, returning a vector of optimal weak classifiers.
     fn get_optimals(
-        features: &Vec<Feature>,
-        training_samples: &Vec<(Matrix, Classification)>,
-        distribution_t: &Vec<f64>,
+        features: &Vec<AnyFeature>,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
     ) -> Vec<(WeakClassifier, f64)> {
         assert!(training_samples.len() == distribution_t.len());
 
@@ -122,9 +147,9 @@ impl WeakClassifier {
 
     /// Returns the best decision stump over the set of optimal stumps.
     pub fn best_stump(
-        features: &Vec<Feature>,
-        training_samples: &Vec<(Matrix, Classification)>,
-        distribution_t: &Vec<f64>,
+        features: &Vec<AnyFeature>,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
     ) -> (WeakClassifier, f64) {
         let mut weak_classifiers = Self::get_optimals(features, training_samples, distribution_t);
 
@@ -137,8 +162,13 @@ impl WeakClassifier {
     }
 
     /// Evaluate the weak classifier on an input image.
-    pub fn evaluate(&self, img: &MatrixView) -> Classification {
-        if self.evaluate_raw(img) >= 0 {
+    pub fn evaluate(&self, img: &IntegralView) -> Classification {
+        self.evaluate_scaled(img, 1.)
+    }
+
+    /// Evaluate the weak classifier on a window `factor` times the training size.
+    pub fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> Classification {
+        if self.evaluate_raw_scaled(img, factor) >= 0 {
             Classification::Face
         } else {
             Classification::NonFace
@@ -146,7 +176,60 @@ impl WeakClassifier {
     }
 
     /// Return the raw score of the evaluated feature.
-    pub fn evaluate_raw(&self, img: &MatrixView) -> i64 {
-        self.toggle * (self.feature.evaluate(img) - self.threshold)
+    pub fn evaluate_raw(&self, img: &IntegralView) -> i64 {
+        self.evaluate_raw_scaled(img, 1.)
+    }
+
+    /// Return the raw score of the feature evaluated on a window `factor` times the
+    /// training size.
+    pub fn evaluate_raw_scaled(&self, img: &IntegralView, factor: f64) -> i64 {
+        self.toggle * (self.feature.evaluate_scaled(img, factor) - self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::{HaarFeature, HaarFeatureType};
+    use crate::Matrix;
+
+    #[test]
+    // The reported error is the weighted error the stump makes. Splitting at a score
+    // (rather than between two) put the samples with that score on the other side of
+    // the threshold from where their weight was counted, and skipping the first
+    // sample dropped its weight altogether, so separable samples were misclassified.
+    fn stumps_make_the_error_they_report() {
+        let features = vec![AnyFeature::Haar(HaarFeature::new(
+            HaarFeatureType::TwoHorizontal,
+            1,
+            2,
+            0,
+            0,
+        ))];
+
+        let mut samples = Vec::new();
+        for &(right, label) in &[
+            (0, Classification::NonFace),
+            (1, Classification::NonFace),
+            (2, Classification::Face),
+            (3, Classification::Face),
+        ] {
+            let mut img = Matrix::ones((2, 2));
+            img[[0, 1]] += right;
+            img[[1, 1]] += right;
+            samples.push((IntegralImages::from_image(&img), label));
+        }
+        let distribution = vec![1. / samples.len() as f64; samples.len()];
+
+        let (stump, error) = WeakClassifier::best_stump(&features, &samples, &distribution);
+        let mut made = 0.;
+        for ((sample, label), dist) in samples.iter().zip(distribution.iter()) {
+            if stump.evaluate(&sample.view()) != *label {
+                made += dist;
+            }
+        }
+
+        assert!(error == made);
+        assert!(error == 0.);
     }
 }
@@ -0,0 +1,169 @@
+/// The weak learners that can be boosted into a strong classifier.
+use super::decision_tree::DecisionTree;
+use super::feature::AnyFeature;
+use super::lbp::LbpClassifier;
+use super::opencv::OpenCvTree;
+use serde::{Deserialize, Serialize};
+
+type WeakClassifier = super::weak_classifier::WeakClassifier;
+type IntegralImages = super::IntegralImages;
+type Classification = super::Classification;
+type IntegralView<'a> = super::IntegralView<'a>;
+
+/// How thresholded (Haar) features are turned into weak learners in each boosting
+/// round. MB-LBP features are always boosted as 256-way lookup tables.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeakLearnerKind {
+    Stump,
+    /// A decision tree with at most `depth` splits on any path from the root.
+    Tree {
+        depth: usize,
+    },
+    /// Only lookup tables over the MB-LBP features, ignoring any Haar features.
+    #[deprecated(
+        note = "MB-LBP features mix with Haar ones: use `Learner::set_lbp_features(true)`, \
+                and `set_haar_families(&[])` for a pure MB-LBP cascade"
+    )]
+    Lbp,
+}
+
+/// Untagged so that cascades saved before trees were supported (a plain list of
+/// stumps per stage) still load. OpenCV trees are only ever imported, never trained.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WeakLearner {
+    Stump(WeakClassifier),
+    Tree(DecisionTree),
+    Lbp(LbpClassifier),
+    OpenCv(OpenCvTree),
+}
+
+impl WeakLearner {
+    /// Trains the best weak learner over the given features, returning it with its
+    /// weighted error. Stumps or trees (depending on `kind`) over the Haar features
+    /// compete with lookup tables over the MB-LBP ones.
+    #[allow(deprecated)]
+    pub fn train(
+        kind: WeakLearnerKind,
+        features: &Vec<AnyFeature>,
+        training_samples: &[(IntegralImages, Classification)],
+        distribution_t: &[f64],
+    ) -> (WeakLearner, f64) {
+        let mut thresholded = Vec::new();
+        let mut lbp_features = Vec::new();
+        for feature in features {
+            match feature {
+                AnyFeature::Haar(_) if kind == WeakLearnerKind::Lbp => (),
+                AnyFeature::Haar(_) => thresholded.push(*feature),
+                AnyFeature::Lbp(lbp) => lbp_features.push(*lbp),
+            }
+        }
+
+        let mut best: Option<(WeakLearner, f64)> = None;
+        if !thresholded.is_empty() {
+            best = Some(match kind {
+                WeakLearnerKind::Stump => {
+                    let (stump, error) =
+                        WeakClassifier::best_stump(&thresholded, training_samples, distribution_t);
+                    (WeakLearner::Stump(stump), error)
+                }
+                WeakLearnerKind::Tree { depth } => {
+                    let (tree, error) =
+                        DecisionTree::train(depth, &thresholded, training_samples, distribution_t);
+                    (WeakLearner::Tree(tree), error)
+                }
+                WeakLearnerKind::Lbp => unreachable!("Haar features are skipped for Lbp"),
+            });
+        }
+        if !lbp_features.is_empty() {
+            let (lut, error) =
+                LbpClassifier::best_lut(&lbp_features, training_samples, distribution_t);
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_error)| error < *best_error)
+            {
+                best = Some((WeakLearner::Lbp(lut), error));
+            }
+        }
+
+        best.expect("No features to train a weak learner on")
+    }
+
+    /// The thresholded stumps making up the learner (none for lookup tables or
+    /// imported OpenCV trees).
+    pub fn stumps(&self) -> Vec<&WeakClassifier> {
+        match self {
+            WeakLearner::Stump(stump) => vec![stump],
+            WeakLearner::Tree(tree) => tree.stumps(),
+            WeakLearner::Lbp(_) | WeakLearner::OpenCv(_) => Vec::new(),
+        }
+    }
+
+    pub fn evaluate(&self, img: &IntegralView) -> Classification {
+        self.evaluate_scaled(img, 1.)
+    }
+
+    /// Evaluate on a window `factor` times the training size.
+    pub fn evaluate_scaled(&self, img: &IntegralView, factor: f64) -> Classification {
+        match self {
+            WeakLearner::Stump(stump) => stump.evaluate_scaled(img, factor),
+            WeakLearner::Tree(tree) => tree.evaluate_scaled(img, factor),
+            WeakLearner::Lbp(lut) => lut.evaluate_scaled(img, factor),
+            WeakLearner::OpenCv(tree) => tree.evaluate_scaled(img, factor),
+        }
+    }
+
+    /// The real-valued score whose sign is the classification: +/-1 for stumps and
+    /// lookup tables, and a leaf's value for trees, so that the learners of a stage
+    /// are summed on one scale.
+    pub fn evaluate_raw(&self, img: &IntegralView) -> f64 {
+        self.evaluate_raw_scaled(img, 1.)
+    }
+
+    /// The real-valued score on a window `factor` times the training size.
+    pub fn evaluate_raw_scaled(&self, img: &IntegralView, factor: f64) -> f64 {
+        match self {
+            WeakLearner::Stump(stump) => stump.evaluate_scaled(img, factor) * 1.,
+            WeakLearner::Tree(tree) => tree.evaluate_raw_scaled(img, factor),
+            WeakLearner::Lbp(lut) => lut.evaluate_raw_scaled(img, factor),
+            WeakLearner::OpenCv(tree) => tree.evaluate_raw_scaled(img, factor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::{HaarFeature, HaarFeatureType};
+    use crate::lbp::MbLbpFeature;
+    use crate::Matrix;
+
+    #[test]
+    #[allow(deprecated)]
+    // The deprecated Lbp kind still boosts only lookup tables
+    fn lbp_kind_ignores_haar_features() {
+        let features = vec![
+            AnyFeature::Haar(HaarFeature::new(HaarFeatureType::TwoHorizontal, 3, 6, 0, 0)),
+            AnyFeature::Lbp(MbLbpFeature::new(2, 2, 0, 0)),
+        ];
+        let samples: Vec<(IntegralImages, Classification)> = (0..4)
+            .map(|i| {
+                let img = Matrix::from_shape_fn((6, 6), |(_, x)| if x >= 3 { i * 10 } else { 0 });
+                let label = if i >= 2 {
+                    Classification::Face
+                } else {
+                    Classification::NonFace
+                };
+                (IntegralImages::from_image(&img), label)
+            })
+            .collect();
+        let distribution = vec![0.25; 4];
+
+        let (learner, _) =
+            WeakLearner::train(WeakLearnerKind::Stump, &features, &samples, &distribution);
+        assert!(matches!(learner, WeakLearner::Stump(_)));
+        let (learner, _) =
+            WeakLearner::train(WeakLearnerKind::Lbp, &features, &samples, &distribution);
+        assert!(matches!(learner, WeakLearner::Lbp(_)));
+    }
+}